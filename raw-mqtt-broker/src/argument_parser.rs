@@ -0,0 +1,159 @@
+use clap::Parser;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::framing::DEFAULT_MAX_PACKET_SIZE;
+use crate::impairment::{DelayDistribution, Impairment, LossModel};
+use crate::reflector::ReflectMode;
+
+const DEFAULT_TCP_ADDRESS: &str = "127.0.0.1:1883";
+const DEFAULT_CERTIFICATE_NAME: &str = "localhost";
+const DEFAULT_DEBUG: bool = false;
+const DEFAULT_DELAY: f64 = 0.0;
+const DEFAULT_JITTER: f64 = 0.0;
+const DEFAULT_LOSS: &str = "none";
+const DEFAULT_REORDER: f64 = 0.0;
+const DEFAULT_RETRANSMISSION_TIMEOUT: f64 = 200.0;
+
+#[derive(Parser)]
+#[command(name = "mqtt-broker")]
+#[command(bin_name = "mqtt-broker")]
+pub enum MqttBrokerCli {
+    /// Run the broker
+    Serve(ServeArgs),
+    /// Run a timestamping reflector, sending every message back to its sender
+    Reflect(ReflectArgs),
+    /// Relay connections to a broker through impaired links (delay, loss, bandwidth)
+    Impair(ImpairArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ListenArgs {
+    /// TCP listen address
+    #[arg(long, default_value = DEFAULT_TCP_ADDRESS)]
+    pub tcp: SocketAddr,
+
+    /// TLS listen address (disabled if not set)
+    #[arg(long)]
+    pub tls: Option<SocketAddr>,
+
+    /// QUIC listen address (disabled if not set)
+    #[arg(long)]
+    pub quic: Option<SocketAddr>,
+
+    /// Subject alternative name of the generated certificate, repeatable
+    #[arg(long = "name", default_value = DEFAULT_CERTIFICATE_NAME)]
+    pub names: Vec<String>,
+
+    /// Write the generated certificate to this file (PEM)
+    #[arg(long)]
+    pub cert_out: Option<PathBuf>,
+
+    /// Largest packet accepted in bytes, clients sending more are disconnected
+    #[arg(long, default_value_t = DEFAULT_MAX_PACKET_SIZE)]
+    pub max_packet_size: usize,
+}
+
+#[derive(clap::Args, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct ServeArgs {
+    #[command(flatten)]
+    pub listen: ListenArgs,
+
+    #[arg(short, long, default_value_t = DEFAULT_DEBUG)]
+    pub debug: bool,
+}
+
+#[derive(clap::Args, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct ReflectArgs {
+    #[command(flatten)]
+    pub listen: ListenArgs,
+
+    /// Reflected payload: "echo" (whole payload) or "ack" (timestamps only)
+    #[arg(long, default_value_t = ReflectMode::default())]
+    pub mode: ReflectMode,
+
+    #[arg(short, long, default_value_t = DEFAULT_DEBUG)]
+    pub debug: bool,
+}
+
+#[derive(clap::Args, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct ImpairArgs {
+    /// Relay TCP (and TLS) connections, as LISTEN_ADDRESS=TARGET_ADDRESS
+    #[arg(long, value_parser = parse_relay)]
+    pub tcp: Option<(SocketAddr, SocketAddr)>,
+
+    /// Relay UDP datagrams (QUIC), as LISTEN_ADDRESS=TARGET_ADDRESS
+    #[arg(long, value_parser = parse_relay)]
+    pub udp: Option<(SocketAddr, SocketAddr)>,
+
+    /// Mean one-way delay in milliseconds (each direction)
+    #[arg(long, default_value_t = DEFAULT_DELAY)]
+    pub delay: f64,
+
+    /// Delay variation in milliseconds
+    #[arg(long, default_value_t = DEFAULT_JITTER)]
+    pub jitter: f64,
+
+    /// Distribution of the delay variation: "uniform", "normal" or "pareto"
+    #[arg(long, default_value_t = DelayDistribution::default())]
+    pub distribution: DelayDistribution,
+
+    /// Loss model: a probability (e.g. 0.01) or Gilbert-Elliott "ge:P,R[,LOSS_GOOD,LOSS_BAD]"
+    #[arg(long, default_value = DEFAULT_LOSS)]
+    pub loss: LossModel,
+
+    /// Probability that a datagram overtakes the ones in flight (UDP only)
+    #[arg(long, default_value_t = DEFAULT_REORDER)]
+    pub reorder: f64,
+
+    /// Bottleneck bandwidth in kbit/s (each direction, unlimited if not set)
+    #[arg(long)]
+    pub bandwidth: Option<u64>,
+
+    /// Extra delay of a lost TCP segment in milliseconds, standing for its retransmission
+    #[arg(long, default_value_t = DEFAULT_RETRANSMISSION_TIMEOUT)]
+    pub rto: f64,
+
+    /// Seed of the random generators, for reproducible runs
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    #[arg(short, long, default_value_t = DEFAULT_DEBUG)]
+    pub debug: bool,
+}
+
+impl ImpairArgs {
+    /// Impairment of each direction (the downlink is seeded differently from the uplink).
+    pub fn impairments(&self) -> (Impairment, Impairment) {
+        let uplink = Impairment {
+            delay: Duration::from_secs_f64(self.delay / 1000.0),
+            jitter: Duration::from_secs_f64(self.jitter / 1000.0),
+            distribution: self.distribution,
+            loss: self.loss,
+            reorder: self.reorder,
+            bandwidth: self.bandwidth.map(|kbits| kbits * 1000 / 8),
+            retransmission_timeout: Some(Duration::from_secs_f64(self.rto / 1000.0)),
+            seed: self.seed,
+        };
+        let downlink = Impairment {
+            seed: self.seed.map(|seed| seed.wrapping_add(1)),
+            ..uplink.clone()
+        };
+        (uplink, downlink)
+    }
+}
+
+fn parse_relay(s: &str) -> Result<(SocketAddr, SocketAddr), String> {
+    let (listen, target) = s
+        .split_once('=')
+        .ok_or(format!("Expected LISTEN_ADDRESS=TARGET_ADDRESS, got {s}"))?;
+    let parse = |addr: &str| {
+        addr.parse::<SocketAddr>()
+            .map_err(|e| format!("Invalid address {addr}: {e}"))
+    };
+    Ok((parse(listen)?, parse(target)?))
+}
@@ -0,0 +1,744 @@
+use bytes::BytesMut;
+use log::{debug, info};
+use mqttbytes::v4::{
+    ConnAck, ConnectReturnCode, Packet, PubAck, PubComp, PubRec, PubRel, Publish, SubAck,
+    SubscribeFilter, SubscribeReasonCode, UnsubAck,
+};
+use mqttbytes::QoS;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use crate::framing::{read_packet, write_packet, DEFAULT_MAX_PACKET_SIZE};
+use crate::listener::{RxStream, Service, ServiceError, TxStream};
+use crate::topic;
+
+/// Outgoing packets coalesced into a single write
+const MAX_WRITE_SIZE: usize = 64 * 1024;
+
+#[derive(Debug)]
+struct Session {
+    client_id: String,
+    outgoing: mpsc::UnboundedSender<Packet>,
+    subscriptions: Vec<SubscribeFilter>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    sessions: HashMap<u64, Session>,
+    retained: HashMap<String, Publish>,
+}
+
+/// Minimal MQTT 3.1.1 broker. Sessions are clean (nothing is kept after a client disconnects)
+/// and outgoing QoS 1/2 deliveries are never retransmitted.
+#[derive(Debug, Clone)]
+pub struct Broker {
+    state: Arc<Mutex<State>>,
+    next_session: Arc<AtomicU64>,
+    max_packet_size: usize,
+}
+
+impl Default for Broker {
+    fn default() -> Broker {
+        Broker {
+            state: Arc::default(),
+            next_session: Arc::default(),
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+        }
+    }
+}
+
+impl Broker {
+    pub fn new() -> Broker {
+        Broker::default()
+    }
+
+    /// Largest packet accepted (bytes), clients sending more are disconnected.
+    pub fn set_max_packet_size(&mut self, max_packet_size: usize) {
+        self.max_packet_size = max_packet_size;
+    }
+}
+
+impl Service for Broker {
+    async fn serve(
+        &self,
+        mut rx_stream: RxStream,
+        tx_stream: TxStream,
+    ) -> Result<(), ServiceError> {
+        let mut buffer = BytesMut::new();
+
+        // The first packet must be CONNECT
+        let connect = match read_packet(&mut rx_stream, &mut buffer, self.max_packet_size).await? {
+            Some(Packet::Connect(connect)) => connect,
+            Some(other) => Err(format!("Expected CONNECT, received {other:?}"))?,
+            None => return Ok(()),
+        };
+
+        // Register the session and start writing to the client
+        let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+        let writer = tokio::spawn(write_packets(tx_stream, outgoing_rx));
+        let session_id = self.next_session.fetch_add(1, Ordering::Relaxed);
+        self.state.lock().unwrap().sessions.insert(
+            session_id,
+            Session {
+                client_id: connect.client_id.clone(),
+                outgoing: outgoing.clone(),
+                subscriptions: Vec::new(),
+            },
+        );
+        let _ = outgoing.send(Packet::ConnAck(ConnAck::new(
+            ConnectReturnCode::Success,
+            false,
+        )));
+        info!("Client {} connected", connect.client_id);
+
+        let res = self
+            .session(session_id, &mut rx_stream, &mut buffer, &outgoing)
+            .await;
+
+        // Drop the session, the writer stops once every queued packet is sent
+        self.state.lock().unwrap().sessions.remove(&session_id);
+        drop(outgoing);
+        writer.await??;
+        info!("Client {} disconnected", connect.client_id);
+
+        res
+    }
+}
+
+impl Broker {
+    async fn session<R>(
+        &self,
+        session_id: u64,
+        rx_stream: &mut R,
+        buffer: &mut BytesMut,
+        outgoing: &mpsc::UnboundedSender<Packet>,
+    ) -> Result<(), ServiceError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        // Packet ids of QoS 2 messages received but not released yet
+        let mut incoming_qos2 = HashSet::new();
+
+        while let Some(packet) = read_packet(rx_stream, buffer, self.max_packet_size).await? {
+            let reply = match packet {
+                Packet::Publish(publish) => match publish.qos {
+                    QoS::AtMostOnce => {
+                        self.publish(publish);
+                        None
+                    }
+                    QoS::AtLeastOnce => {
+                        let pkid = publish.pkid;
+                        self.publish(publish);
+                        Some(Packet::PubAck(PubAck::new(pkid)))
+                    }
+                    QoS::ExactlyOnce => {
+                        // Deliver once, duplicates are only acked
+                        let pkid = publish.pkid;
+                        if incoming_qos2.insert(pkid) {
+                            self.publish(publish);
+                        }
+                        Some(Packet::PubRec(PubRec::new(pkid)))
+                    }
+                },
+                Packet::PubRel(pub_rel) => {
+                    incoming_qos2.remove(&pub_rel.pkid);
+                    Some(Packet::PubComp(PubComp::new(pub_rel.pkid)))
+                }
+                Packet::PubRec(pub_rec) => Some(Packet::PubRel(PubRel::new(pub_rec.pkid))),
+                Packet::PubAck(_) | Packet::PubComp(_) => None,
+                Packet::Subscribe(subscribe) => {
+                    let codes = self.subscribe(session_id, &subscribe.filters);
+                    let sub_ack = Packet::SubAck(SubAck::new(subscribe.pkid, codes));
+
+                    // Retained messages follow the SUBACK
+                    let _ = outgoing.send(sub_ack);
+                    for publish in self.retained(&subscribe.filters) {
+                        let _ = outgoing.send(Packet::Publish(publish));
+                    }
+                    None
+                }
+                Packet::Unsubscribe(unsubscribe) => {
+                    self.unsubscribe(session_id, &unsubscribe.topics);
+                    Some(Packet::UnsubAck(UnsubAck::new(unsubscribe.pkid)))
+                }
+                Packet::PingReq => Some(Packet::PingResp),
+                Packet::Disconnect => return Ok(()),
+                other => Err(format!("Unexpected packet: {other:?}"))?,
+            };
+
+            if let Some(reply) = reply {
+                let _ = outgoing.send(reply);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Forward a message to every matching subscription and update retained messages.
+    fn publish(&self, publish: Publish) {
+        let mut state = self.state.lock().unwrap();
+
+        // An empty retained message clears the retained one
+        if publish.retain {
+            if publish.payload.is_empty() {
+                state.retained.remove(&publish.topic);
+            } else {
+                let mut retained = publish.clone();
+                retained.dup = false;
+                retained.pkid = 0;
+                state.retained.insert(publish.topic.clone(), retained);
+            }
+        }
+
+        for session in state.sessions.values() {
+            // Overlapping subscriptions get a single copy at the highest granted QoS
+            let granted = session
+                .subscriptions
+                .iter()
+                .filter(|filter| topic::matches(&filter.path, &publish.topic))
+                .map(|filter| filter.qos)
+                .max_by_key(|qos| *qos as u8);
+
+            if let Some(granted) = granted {
+                let qos = min_qos(granted, publish.qos);
+                let forward =
+                    Publish::from_bytes(publish.topic.clone(), qos, publish.payload.clone());
+                debug!("Forwarding {} to {}", publish.topic, session.client_id);
+                let _ = session.outgoing.send(Packet::Publish(forward));
+            }
+        }
+    }
+
+    fn subscribe(&self, session_id: u64, filters: &[SubscribeFilter]) -> Vec<SubscribeReasonCode> {
+        let mut state = self.state.lock().unwrap();
+        let session = state.sessions.get_mut(&session_id).unwrap();
+
+        filters
+            .iter()
+            .map(|filter| {
+                if !topic::valid_filter(&filter.path) {
+                    return SubscribeReasonCode::Failure;
+                }
+
+                // A new subscription with the same filter replaces the previous one
+                session
+                    .subscriptions
+                    .retain(|subscription| subscription.path != filter.path);
+                session.subscriptions.push(filter.clone());
+                SubscribeReasonCode::Success(filter.qos)
+            })
+            .collect()
+    }
+
+    fn unsubscribe(&self, session_id: u64, topics: &[String]) {
+        let mut state = self.state.lock().unwrap();
+        let session = state.sessions.get_mut(&session_id).unwrap();
+        session
+            .subscriptions
+            .retain(|subscription| !topics.contains(&subscription.path));
+    }
+
+    /// Retained messages matching new subscriptions, with the retain flag set.
+    fn retained(&self, filters: &[SubscribeFilter]) -> Vec<Publish> {
+        let state = self.state.lock().unwrap();
+        state
+            .retained
+            .values()
+            .filter_map(|retained| {
+                filters
+                    .iter()
+                    .filter(|filter| topic::valid_filter(&filter.path))
+                    .filter(|filter| topic::matches(&filter.path, &retained.topic))
+                    .map(|filter| filter.qos)
+                    .max_by_key(|qos| *qos as u8)
+                    .map(|granted| {
+                        let mut publish = Publish::from_bytes(
+                            retained.topic.clone(),
+                            min_qos(granted, retained.qos),
+                            retained.payload.clone(),
+                        );
+                        publish.retain = true;
+                        publish
+                    })
+            })
+            .collect()
+    }
+}
+
+fn min_qos(a: QoS, b: QoS) -> QoS {
+    if (a as u8) < (b as u8) {
+        a
+    } else {
+        b
+    }
+}
+
+/// Write queued packets to the client, assigning packet ids to outgoing QoS 1/2 messages.
+async fn write_packets<W>(
+    mut tx_stream: W,
+    mut outgoing: mpsc::UnboundedReceiver<Packet>,
+) -> Result<(), ServiceError>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut pkid: u16 = 0;
+    let mut buffer = BytesMut::new();
+
+    while let Some(packet) = outgoing.recv().await {
+        let mut next = Some(packet);
+        while let Some(mut packet) = next {
+            if let Packet::Publish(ref mut publish) = packet {
+                if publish.qos != QoS::AtMostOnce {
+                    pkid = pkid.wrapping_add(1).max(1);
+                    publish.pkid = pkid;
+                }
+            }
+            write_packet(&packet, &mut buffer);
+
+            // Coalesce packets already queued
+            next = if buffer.len() < MAX_WRITE_SIZE {
+                outgoing.try_recv().ok()
+            } else {
+                None
+            };
+        }
+
+        tx_stream.write_all(&buffer).await?;
+        buffer.clear();
+    }
+
+    tx_stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mqttbytes::v4::{Connect, PingReq, Subscribe};
+    use raw_mqtt::client::simple_client::SimpleMqttClient;
+    use raw_mqtt::client::stream_client::StreamMqttClient;
+    use raw_mqtt::network::server_verification::Fingerprint;
+    use raw_mqtt::network::session::SessionInfo;
+    use raw_mqtt::network::transport::{QuicConfig, TlsConfig, Transport};
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpStream;
+    use tokio::time::timeout;
+
+    use crate::certificate::Certificate;
+    use crate::listener::{listen_quic, listen_tcp, listen_tls};
+
+    /// Raw MQTT client driving the broker packet by packet.
+    struct TestClient {
+        stream: TcpStream,
+        buffer: BytesMut,
+    }
+
+    impl TestClient {
+        async fn connect(addr: SocketAddr, client_id: &str) -> TestClient {
+            let mut client = TestClient {
+                stream: TcpStream::connect(addr).await.unwrap(),
+                buffer: BytesMut::new(),
+            };
+            client.send(Packet::Connect(Connect::new(client_id))).await;
+            assert!(matches!(client.recv().await, Packet::ConnAck(_)));
+            client
+        }
+
+        async fn send(&mut self, packet: Packet) {
+            let mut buffer = BytesMut::new();
+            write_packet(&packet, &mut buffer);
+            self.stream.write_all(&buffer).await.unwrap();
+        }
+
+        async fn recv(&mut self) -> Packet {
+            let packet = read_packet(&mut self.stream, &mut self.buffer, DEFAULT_MAX_PACKET_SIZE);
+            timeout(Duration::from_secs(5), packet)
+                .await
+                .expect("No packet received")
+                .unwrap()
+                .expect("Connection closed")
+        }
+
+        async fn subscribe(&mut self, filter: &str, qos: QoS) {
+            let mut subscribe = Subscribe::new(filter, qos);
+            subscribe.pkid = 1;
+            self.send(Packet::Subscribe(subscribe)).await;
+            match self.recv().await {
+                Packet::SubAck(sub_ack) => {
+                    assert_eq!(
+                        sub_ack.return_codes,
+                        vec![SubscribeReasonCode::Success(qos)]
+                    )
+                }
+                other => panic!("Unexpected packet: {other:?}"),
+            }
+        }
+
+        async fn publish(&mut self, topic: &str, payload: &str, qos: QoS, retain: bool) {
+            let mut publish = Publish::new(topic, qos, payload);
+            publish.pkid = if qos == QoS::AtMostOnce { 0 } else { 10 };
+            publish.retain = retain;
+            self.send(Packet::Publish(publish)).await;
+        }
+
+        /// Check that nothing is received (a PINGRESP comes back first).
+        async fn assert_idle(&mut self) {
+            self.send(Packet::PingReq).await;
+            assert_eq!(self.recv().await, Packet::PingResp);
+        }
+    }
+
+    async fn start() -> (Broker, SocketAddr) {
+        let broker = Broker::new();
+        let addr = listen_tcp("127.0.0.1:0".parse().unwrap(), broker.clone())
+            .await
+            .unwrap();
+        (broker, addr)
+    }
+
+    fn publish_of(packet: Packet) -> Publish {
+        match packet {
+            Packet::Publish(publish) => publish,
+            other => panic!("Unexpected packet: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_connect() {
+        let (_broker, addr) = start().await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        let mut buffer = BytesMut::new();
+        PingReq.write(&mut buffer).unwrap();
+        stream.write_all(&buffer).await.unwrap();
+
+        // Closed without answer
+        let mut buffer = BytesMut::new();
+        assert_eq!(stream.read_buf(&mut buffer).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_packet() {
+        let mut broker = Broker::new();
+        broker.set_max_packet_size(1024);
+        let addr = listen_tcp("127.0.0.1:0".parse().unwrap(), broker)
+            .await
+            .unwrap();
+        let mut client = TestClient::connect(addr, "client").await;
+
+        client
+            .publish("big", &"x".repeat(1000), QoS::AtMostOnce, false)
+            .await;
+        client.assert_idle().await;
+
+        // Closed without answer (or reset, the packet is left unread)
+        client
+            .publish("big", &"x".repeat(1024), QoS::AtMostOnce, false)
+            .await;
+        let mut buffer = BytesMut::new();
+        let read = client.stream.read_buf(&mut buffer).await;
+        assert!(matches!(read, Ok(0) | Err(_)), "{read:?}");
+    }
+
+    #[tokio::test]
+    async fn ping() {
+        let (_broker, addr) = start().await;
+        let mut client = TestClient::connect(addr, "ping").await;
+        client.assert_idle().await;
+    }
+
+    #[tokio::test]
+    async fn forward_qos0_and_qos1() {
+        let (_broker, addr) = start().await;
+        let mut subscriber = TestClient::connect(addr, "subscriber").await;
+        subscriber
+            .subscribe("sensors/+/temperature", QoS::AtLeastOnce)
+            .await;
+        let mut publisher = TestClient::connect(addr, "publisher").await;
+
+        publisher
+            .publish("sensors/1/temperature", "21", QoS::AtMostOnce, false)
+            .await;
+        let publish = publish_of(subscriber.recv().await);
+        assert_eq!(publish.qos, QoS::AtMostOnce);
+        assert_eq!(publish.payload.as_ref(), b"21");
+
+        publisher
+            .publish("sensors/2/temperature", "22", QoS::AtLeastOnce, false)
+            .await;
+        assert_eq!(publisher.recv().await, Packet::PubAck(PubAck::new(10)));
+        let publish = publish_of(subscriber.recv().await);
+        assert_eq!(publish.topic, "sensors/2/temperature");
+        assert_eq!(publish.qos, QoS::AtLeastOnce);
+        assert_eq!(publish.pkid, 1);
+        assert!(!publish.retain);
+        subscriber.send(Packet::PubAck(PubAck::new(1))).await;
+
+        // Not matching
+        publisher
+            .publish("sensors/1/humidity", "40", QoS::AtMostOnce, false)
+            .await;
+        subscriber.assert_idle().await;
+    }
+
+    #[tokio::test]
+    async fn forward_qos2() {
+        let (_broker, addr) = start().await;
+        let mut subscriber = TestClient::connect(addr, "subscriber").await;
+        subscriber.subscribe("#", QoS::ExactlyOnce).await;
+        let mut publisher = TestClient::connect(addr, "publisher").await;
+
+        // Incoming QoS 2 flow, the duplicate is acked but not forwarded
+        publisher
+            .publish("a", "once", QoS::ExactlyOnce, false)
+            .await;
+        assert_eq!(publisher.recv().await, Packet::PubRec(PubRec::new(10)));
+        publisher
+            .publish("a", "once", QoS::ExactlyOnce, false)
+            .await;
+        assert_eq!(publisher.recv().await, Packet::PubRec(PubRec::new(10)));
+        publisher.send(Packet::PubRel(PubRel::new(10))).await;
+        assert_eq!(publisher.recv().await, Packet::PubComp(PubComp::new(10)));
+
+        // Outgoing QoS 2 flow
+        let publish = publish_of(subscriber.recv().await);
+        assert_eq!(publish.qos, QoS::ExactlyOnce);
+        subscriber
+            .send(Packet::PubRec(PubRec::new(publish.pkid)))
+            .await;
+        assert_eq!(
+            subscriber.recv().await,
+            Packet::PubRel(PubRel::new(publish.pkid))
+        );
+        subscriber
+            .send(Packet::PubComp(PubComp::new(publish.pkid)))
+            .await;
+        subscriber.assert_idle().await;
+    }
+
+    #[tokio::test]
+    async fn downgrade_to_subscription_qos() {
+        let (_broker, addr) = start().await;
+        let mut subscriber = TestClient::connect(addr, "subscriber").await;
+        subscriber.subscribe("a", QoS::AtMostOnce).await;
+        let mut publisher = TestClient::connect(addr, "publisher").await;
+
+        publisher.publish("a", "x", QoS::ExactlyOnce, false).await;
+        assert_eq!(publish_of(subscriber.recv().await).qos, QoS::AtMostOnce);
+    }
+
+    #[tokio::test]
+    async fn retained_messages() {
+        let (_broker, addr) = start().await;
+        let mut publisher = TestClient::connect(addr, "publisher").await;
+        publisher
+            .publish("status/a", "up", QoS::AtMostOnce, true)
+            .await;
+        publisher
+            .publish("status/b", "down", QoS::AtMostOnce, true)
+            .await;
+        publisher
+            .publish("status/b", "", QoS::AtMostOnce, true)
+            .await;
+        publisher.assert_idle().await;
+
+        // Delivered on subscription, with the retain flag
+        let mut subscriber = TestClient::connect(addr, "subscriber").await;
+        subscriber.subscribe("status/#", QoS::AtLeastOnce).await;
+        let publish = publish_of(subscriber.recv().await);
+        assert_eq!(publish.topic, "status/a");
+        assert_eq!(publish.payload.as_ref(), b"up");
+        assert_eq!(publish.qos, QoS::AtMostOnce);
+        assert!(publish.retain);
+        subscriber.assert_idle().await;
+    }
+
+    #[tokio::test]
+    async fn unsubscribe() {
+        let (_broker, addr) = start().await;
+        let mut subscriber = TestClient::connect(addr, "subscriber").await;
+        subscriber.subscribe("a", QoS::AtMostOnce).await;
+
+        let mut unsubscribe = mqttbytes::v4::Unsubscribe::new("a");
+        unsubscribe.pkid = 2;
+        subscriber.send(Packet::Unsubscribe(unsubscribe)).await;
+        assert_eq!(subscriber.recv().await, Packet::UnsubAck(UnsubAck::new(2)));
+
+        let mut publisher = TestClient::connect(addr, "publisher").await;
+        publisher.publish("a", "x", QoS::AtMostOnce, false).await;
+        publisher.assert_idle().await;
+        subscriber.assert_idle().await;
+    }
+
+    #[tokio::test]
+    async fn invalid_filter() {
+        let (_broker, addr) = start().await;
+        let mut client = TestClient::connect(addr, "client").await;
+
+        let mut subscribe = Subscribe::new("a/#/b", QoS::AtMostOnce);
+        subscribe.pkid = 1;
+        client.send(Packet::Subscribe(subscribe)).await;
+        match client.recv().await {
+            Packet::SubAck(sub_ack) => {
+                assert_eq!(sub_ack.return_codes, vec![SubscribeReasonCode::Failure])
+            }
+            other => panic!("Unexpected packet: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn session_dropped_on_disconnect() {
+        let (broker, addr) = start().await;
+        let mut client = TestClient::connect(addr, "client").await;
+        client.subscribe("a", QoS::AtMostOnce).await;
+        client.send(Packet::Disconnect).await;
+
+        let mut buffer = BytesMut::new();
+        assert_eq!(client.stream.read_buf(&mut buffer).await.unwrap(), 0);
+        assert!(broker.state.lock().unwrap().sessions.is_empty());
+    }
+
+    /// Publish through the library client and check the message reaches a subscriber.
+    /// Publish through `transport`, returning the TLS session of the connection.
+    async fn round_trip(
+        transport: Transport,
+        port: u16,
+        tcp_addr: SocketAddr,
+    ) -> Option<SessionInfo> {
+        let mut subscriber = TestClient::connect(tcp_addr, "subscriber").await;
+        subscriber.subscribe("round/trip", QoS::AtLeastOnce).await;
+
+        let mut client = SimpleMqttClient::new(
+            "127.0.0.1".to_string(),
+            "localhost".to_string(),
+            port.to_string(),
+            transport,
+            raw_mqtt::Version::V311,
+        );
+        client.connect().await.unwrap();
+        client
+            .publish(
+                "round/trip".to_string(),
+                "hello".to_string(),
+                QoS::AtLeastOnce,
+            )
+            .await
+            .unwrap();
+        let session = client.session_info();
+        client.disconnect().await.unwrap();
+
+        let publish = publish_of(subscriber.recv().await);
+        assert_eq!(publish.payload.as_ref(), b"hello");
+        session
+    }
+
+    #[tokio::test]
+    async fn tcp_round_trip() {
+        let (_broker, addr) = start().await;
+        round_trip(Transport::from_str("tcp").unwrap(), addr.port(), addr).await;
+    }
+
+    #[tokio::test]
+    async fn tls_round_trip() {
+        let (broker, tcp_addr) = start().await;
+        let certificate = Certificate::self_signed(vec!["localhost".to_string()]).unwrap();
+        let addr = listen_tls(
+            "127.0.0.1:0".parse().unwrap(),
+            certificate.tls_config().unwrap(),
+            broker,
+        )
+        .await
+        .unwrap();
+
+        let transport = Transport::TLS(TlsConfig {
+            pins: vec![Fingerprint::of(&certificate.der)],
+            ..TlsConfig::default()
+        });
+        let session = round_trip(transport, addr.port(), tcp_addr).await.unwrap();
+        // A fresh client configuration has no session to resume
+        assert_eq!(session.resumed, Some(false));
+    }
+
+    #[tokio::test]
+    async fn quic_round_trip() {
+        let (broker, tcp_addr) = start().await;
+        let certificate = Certificate::self_signed(vec!["localhost".to_string()]).unwrap();
+        let addr = listen_quic(
+            "127.0.0.1:0".parse().unwrap(),
+            certificate.quic_config().unwrap(),
+            broker,
+        )
+        .unwrap();
+
+        let transport = Transport::QUIC(QuicConfig {
+            pins: vec![Fingerprint::of(&certificate.der)],
+            ..QuicConfig::default()
+        });
+        let session = round_trip(transport, addr.port(), tcp_addr).await.unwrap();
+        assert_eq!(session.resumed, None);
+    }
+
+    #[tokio::test]
+    async fn stream_round_trip() {
+        let (_broker, addr) = start().await;
+        let mut subscriber = TestClient::connect(addr, "subscriber").await;
+        subscriber.subscribe("stream", QoS::AtMostOnce).await;
+
+        let mut client = StreamMqttClient::new(
+            "127.0.0.1".to_string(),
+            "localhost".to_string(),
+            addr.port().to_string(),
+            Transport::from_str("tcp").unwrap(),
+            raw_mqtt::Version::V311,
+        );
+        client.connect().await.unwrap();
+        for _ in 0..100 {
+            client
+                .stream_publish("stream".to_string(), "x".to_string(), QoS::AtLeastOnce)
+                .await
+                .unwrap();
+        }
+        client.disconnect().await.unwrap();
+
+        for _ in 0..100 {
+            assert_eq!(publish_of(subscriber.recv().await).payload.as_ref(), b"x");
+        }
+        subscriber.assert_idle().await;
+    }
+
+    #[tokio::test]
+    async fn stream_subscription() {
+        let (_broker, addr) = start().await;
+        let mut subscriber = StreamMqttClient::new(
+            "127.0.0.1".to_string(),
+            "localhost".to_string(),
+            addr.port().to_string(),
+            Transport::from_str("tcp").unwrap(),
+            raw_mqtt::Version::V311,
+        );
+        subscriber.subscribe("sensors/+".to_string(), QoS::AtLeastOnce);
+        subscriber.connect().await.unwrap();
+        let messages = subscriber.messages();
+
+        let mut publisher = TestClient::connect(addr, "publisher").await;
+        for id in 0..10 {
+            publisher
+                .publish(&format!("sensors/{id}"), "x", QoS::AtLeastOnce, false)
+                .await;
+        }
+
+        for id in 0..10 {
+            let message = messages.recv().await.unwrap();
+            assert_eq!(message.publish.topic, format!("sensors/{id}"));
+            assert_eq!(message.publish.qos, QoS::AtLeastOnce);
+        }
+        subscriber.disconnect().await.unwrap();
+        assert!(messages.recv().await.is_err());
+    }
+}
@@ -0,0 +1,62 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use quinn::TransportConfig;
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+
+/// Self-signed certificate generated at startup, shared by the TLS and QUIC listeners.
+#[derive(Debug, Clone)]
+pub struct Certificate {
+    /// Certificate (DER)
+    pub der: Vec<u8>,
+    /// Certificate (PEM), to be trusted or pinned by clients
+    pub pem: String,
+    /// PKCS#8 private key (DER)
+    key: Vec<u8>,
+}
+
+impl Certificate {
+    pub fn self_signed(names: Vec<String>) -> Result<Certificate, rcgen::Error> {
+        let certificate = rcgen::generate_simple_self_signed(names)?;
+        Ok(Certificate {
+            der: certificate.serialize_der()?,
+            pem: certificate.serialize_pem()?,
+            key: certificate.serialize_private_key_der(),
+        })
+    }
+
+    /// Server configuration of TLS listeners (ALPN `mqtt`).
+    pub fn tls_config(&self) -> Result<Arc<rustls::ServerConfig>, Box<dyn Error>> {
+        let mut tls_config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from(self.der.clone())],
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(self.key.clone())),
+            )?;
+        tls_config.alpn_protocols = vec!["mqtt".as_bytes().to_vec()];
+
+        Ok(Arc::new(tls_config))
+    }
+
+    /// Server configuration of QUIC listeners (ALPN `mqtt`).
+    pub fn quic_config(&self) -> Result<quinn::ServerConfig, Box<dyn Error>> {
+        let mut tls_config = quinn_rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![quinn_rustls::Certificate(self.der.clone())],
+                quinn_rustls::PrivateKey(self.key.clone()),
+            )?;
+        tls_config.alpn_protocols = vec!["mqtt".as_bytes().to_vec()];
+
+        // Disable unsupported feature segmentation offload (as the client does)
+        let mut transport_config = TransportConfig::default();
+        transport_config.enable_segmentation_offload(false);
+
+        let mut server_config = quinn::ServerConfig::with_crypto(Arc::new(tls_config));
+        server_config.transport_config(Arc::new(transport_config));
+
+        Ok(server_config)
+    }
+}
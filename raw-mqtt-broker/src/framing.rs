@@ -0,0 +1,52 @@
+use bytes::BytesMut;
+use mqttbytes::v4::{Packet, PingResp};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use raw_mqtt::{parse_packet, Version};
+
+use crate::listener::ServiceError;
+
+/// Largest packet accepted by default (bytes), MQTT 3.1.1 allows up to 256 MB
+pub(crate) const DEFAULT_MAX_PACKET_SIZE: usize = 1024 * 1024;
+
+/// Read the next packet of at most `max_size` bytes, `None` at end of stream.
+pub(crate) async fn read_packet<R>(
+    rx_stream: &mut R,
+    buffer: &mut BytesMut,
+    max_size: usize,
+) -> Result<Option<Packet>, ServiceError>
+where
+    R: AsyncRead + Unpin,
+{
+    loop {
+        match parse_packet(buffer, max_size, &Version::V311) {
+            Ok(packet) => return Ok(Some(packet)),
+            Err(mqttbytes::Error::InsufficientBytes(_)) => {
+                if rx_stream.read_buf(buffer).await? == 0 {
+                    return Ok(None);
+                }
+            }
+            Err(e) => Err(format!("Malformed packet: {e}"))?,
+        }
+    }
+}
+
+pub(crate) fn write_packet(packet: &Packet, buffer: &mut BytesMut) {
+    let res = match packet {
+        Packet::Connect(connect) => connect.write(buffer),
+        Packet::ConnAck(conn_ack) => conn_ack.write(buffer),
+        Packet::Publish(publish) => publish.write(buffer),
+        Packet::PubAck(pub_ack) => pub_ack.write(buffer),
+        Packet::PubRec(pub_rec) => pub_rec.write(buffer),
+        Packet::PubRel(pub_rel) => pub_rel.write(buffer),
+        Packet::PubComp(pub_comp) => pub_comp.write(buffer),
+        Packet::Subscribe(subscribe) => subscribe.write(buffer),
+        Packet::SubAck(sub_ack) => sub_ack.write(buffer),
+        Packet::Unsubscribe(unsubscribe) => unsubscribe.write(buffer),
+        Packet::UnsubAck(unsub_ack) => unsub_ack.write(buffer),
+        Packet::PingReq => mqttbytes::v4::PingReq.write(buffer),
+        Packet::PingResp => PingResp.write(buffer),
+        Packet::Disconnect => mqttbytes::v4::Disconnect.write(buffer),
+    };
+    res.expect("Packet serialization failed");
+}
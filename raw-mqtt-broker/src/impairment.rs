@@ -0,0 +1,440 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal, Pareto};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Shape of the Pareto distribution (finite mean and variance)
+const PARETO_SHAPE: f64 = 3.0;
+/// Time to recover a lost TCP segment when no retransmission timeout is configured
+const DEFAULT_RETRANSMISSION_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Distribution of the delay variation around the mean delay.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum DelayDistribution {
+    /// Uniform within `mean ± jitter`
+    #[default]
+    Uniform,
+    /// Normal with standard deviation `jitter`
+    Normal,
+    /// Heavy-tailed (Pareto), mostly slightly below the mean with occasional long delays
+    Pareto,
+}
+
+impl FromStr for DelayDistribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "uniform" => Ok(DelayDistribution::Uniform),
+            "normal" => Ok(DelayDistribution::Normal),
+            "pareto" => Ok(DelayDistribution::Pareto),
+            _ => Err(format!("Unknown delay distribution: {s}")),
+        }
+    }
+}
+
+impl fmt::Display for DelayDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DelayDistribution::Uniform => write!(f, "uniform"),
+            DelayDistribution::Normal => write!(f, "normal"),
+            DelayDistribution::Pareto => write!(f, "pareto"),
+        }
+    }
+}
+
+/// Packet loss model.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum LossModel {
+    #[default]
+    None,
+    /// Independent losses with the given probability
+    Random(f64),
+    /// Bursty losses: a two-state Markov chain moving from the good to the bad state with
+    /// probability `p` and back with probability `r`, losing packets with probability
+    /// `loss_good` and `loss_bad` in each state
+    GilbertElliott {
+        p: f64,
+        r: f64,
+        loss_good: f64,
+        loss_bad: f64,
+    },
+}
+
+impl LossModel {
+    /// Long-run fraction of lost packets.
+    pub fn loss_rate(&self) -> f64 {
+        match *self {
+            LossModel::None => 0.0,
+            LossModel::Random(probability) => probability,
+            LossModel::GilbertElliott {
+                p,
+                r,
+                loss_good,
+                loss_bad,
+            } => {
+                let bad = if p + r > 0.0 { p / (p + r) } else { 0.0 };
+                (1.0 - bad) * loss_good + bad * loss_bad
+            }
+        }
+    }
+}
+
+/**
+ * Parse a loss model: `none`, a probability (`0.01`) for random losses, or
+ * `ge:P,R[,LOSS_GOOD,LOSS_BAD]` for Gilbert-Elliott losses (no loss in the good state and every
+ * packet lost in the bad state by default).
+ */
+impl FromStr for LossModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let probability = |value: &str| match value.trim().parse::<f64>() {
+            Ok(probability) if (0.0..=1.0).contains(&probability) => Ok(probability),
+            _ => Err(format!("Invalid probability: {value}")),
+        };
+
+        if s.eq_ignore_ascii_case("none") {
+            return Ok(LossModel::None);
+        }
+        match s.split_once(':') {
+            Some((model, params)) if model.eq_ignore_ascii_case("ge") => {
+                let params = params
+                    .split(',')
+                    .map(probability)
+                    .collect::<Result<Vec<f64>, String>>()?;
+                match params[..] {
+                    [p, r] => Ok(LossModel::GilbertElliott {
+                        p,
+                        r,
+                        loss_good: 0.0,
+                        loss_bad: 1.0,
+                    }),
+                    [p, r, loss_good, loss_bad] => Ok(LossModel::GilbertElliott {
+                        p,
+                        r,
+                        loss_good,
+                        loss_bad,
+                    }),
+                    _ => Err(format!("Expected ge:P,R[,LOSS_GOOD,LOSS_BAD], got {s}")),
+                }
+            }
+            Some((model, _)) => Err(format!("Unknown loss model: {model}")),
+            None => Ok(LossModel::Random(probability(s)?)),
+        }
+    }
+}
+
+/// Network conditions applied to one direction of a relayed connection.
+#[derive(Debug, Clone, Default)]
+pub struct Impairment {
+    /// Mean one-way delay
+    pub delay: Duration,
+    /// Delay variation, its meaning depends on the distribution
+    pub jitter: Duration,
+    pub distribution: DelayDistribution,
+    pub loss: LossModel,
+    /// Probability that a datagram skips the delay, overtaking the ones in flight (UDP only)
+    pub reorder: f64,
+    /// Bottleneck bandwidth in bytes per second (unlimited if not set)
+    pub bandwidth: Option<u64>,
+    /// Extra delay of a lost TCP segment, standing for its retransmission (200 ms if not set)
+    pub retransmission_timeout: Option<Duration>,
+    /// Seed of the random generator, for reproducible runs
+    pub seed: Option<u64>,
+}
+
+impl Impairment {
+    /// Whether packets are relayed untouched.
+    pub fn is_none(&self) -> bool {
+        self.delay.is_zero()
+            && self.jitter.is_zero()
+            && self.loss == LossModel::None
+            && self.reorder == 0.0
+            && self.bandwidth.is_none()
+    }
+}
+
+/// State of one impaired direction: random generator, loss state and bottleneck queue.
+#[derive(Debug)]
+pub struct Link {
+    impairment: Impairment,
+    rng: StdRng,
+    /// Gilbert-Elliott bad state
+    bad: bool,
+    /// When the bottleneck is done transmitting the queued packets
+    busy_until: Instant,
+    /// Latest delivery scheduled on a stream (deliveries never overtake each other)
+    last_delivery: Instant,
+}
+
+impl Link {
+    pub fn new(impairment: Impairment) -> Link {
+        let rng = match impairment.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let now = Instant::now();
+        Link {
+            impairment,
+            rng,
+            bad: false,
+            busy_until: now,
+            last_delivery: now,
+        }
+    }
+
+    /// Delivery time of a datagram of `len` bytes received at `now`, `None` if it is lost.
+    pub fn datagram(&mut self, now: Instant, len: usize) -> Option<Instant> {
+        if self.lost() {
+            return None;
+        }
+
+        let transmitted = self.transmit(now, len);
+        if self.impairment.reorder > 0.0 && self.rng.gen_bool(self.impairment.reorder) {
+            Some(transmitted)
+        } else {
+            Some(transmitted + self.sample_delay())
+        }
+    }
+
+    /// Delivery time of a chunk of `len` bytes of a byte stream received at `now`. Streams
+    /// cannot lose data: a loss delays the chunk by the retransmission timeout instead, and the
+    /// chunks behind it wait (head-of-line blocking).
+    pub fn stream(&mut self, now: Instant, len: usize) -> Instant {
+        let mut delivery = self.transmit(now, len) + self.sample_delay();
+        if self.lost() {
+            delivery += self
+                .impairment
+                .retransmission_timeout
+                .unwrap_or(DEFAULT_RETRANSMISSION_TIMEOUT);
+        }
+
+        self.last_delivery = delivery.max(self.last_delivery);
+        self.last_delivery
+    }
+
+    /// Time at which the bottleneck is done transmitting `len` more bytes.
+    fn transmit(&mut self, now: Instant, len: usize) -> Instant {
+        match self.impairment.bandwidth {
+            Some(bandwidth) => {
+                let start = now.max(self.busy_until);
+                self.busy_until = start + Duration::from_secs_f64(len as f64 / bandwidth as f64);
+                self.busy_until
+            }
+            None => now,
+        }
+    }
+
+    fn lost(&mut self) -> bool {
+        let probability = match self.impairment.loss {
+            LossModel::None => return false,
+            LossModel::Random(probability) => probability,
+            LossModel::GilbertElliott {
+                p,
+                r,
+                loss_good,
+                loss_bad,
+            } => {
+                self.bad = if self.bad {
+                    !self.rng.gen_bool(r)
+                } else {
+                    self.rng.gen_bool(p)
+                };
+                if self.bad {
+                    loss_bad
+                } else {
+                    loss_good
+                }
+            }
+        };
+        self.rng.gen_bool(probability)
+    }
+
+    fn sample_delay(&mut self) -> Duration {
+        let mean = self.impairment.delay.as_secs_f64();
+        let jitter = self.impairment.jitter.as_secs_f64();
+        if jitter == 0.0 {
+            return self.impairment.delay;
+        }
+
+        let delay = match self.impairment.distribution {
+            DelayDistribution::Uniform => mean + self.rng.gen_range(-jitter..=jitter),
+            DelayDistribution::Normal => Normal::new(mean, jitter).unwrap().sample(&mut self.rng),
+            DelayDistribution::Pareto => {
+                // Shifted so that the mean delay is kept, the variation has mean `jitter`
+                let scale = jitter * (PARETO_SHAPE - 1.0) / PARETO_SHAPE;
+                let variation = Pareto::new(scale, PARETO_SHAPE)
+                    .unwrap()
+                    .sample(&mut self.rng);
+                mean - jitter + variation
+            }
+        };
+        Duration::from_secs_f64(delay.max(0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: usize = 100_000;
+
+    fn link(impairment: Impairment) -> Link {
+        Link::new(Impairment {
+            seed: Some(42),
+            ..impairment
+        })
+    }
+
+    fn loss_rate(link: &mut Link) -> f64 {
+        let now = Instant::now();
+        let lost = (0..SAMPLES)
+            .filter(|_| link.datagram(now, 100).is_none())
+            .count();
+        lost as f64 / SAMPLES as f64
+    }
+
+    #[test]
+    fn parse_loss_model() {
+        assert_eq!(LossModel::from_str("none").unwrap(), LossModel::None);
+        assert_eq!(
+            LossModel::from_str("0.01").unwrap(),
+            LossModel::Random(0.01)
+        );
+        assert_eq!(
+            LossModel::from_str("ge:0.01,0.3").unwrap(),
+            LossModel::GilbertElliott {
+                p: 0.01,
+                r: 0.3,
+                loss_good: 0.0,
+                loss_bad: 1.0
+            }
+        );
+        assert_eq!(
+            LossModel::from_str("GE:0.1,0.2,0.01,0.5").unwrap(),
+            LossModel::GilbertElliott {
+                p: 0.1,
+                r: 0.2,
+                loss_good: 0.01,
+                loss_bad: 0.5
+            }
+        );
+        assert!(LossModel::from_str("1.5").is_err());
+        assert!(LossModel::from_str("ge:0.1").is_err());
+        assert!(LossModel::from_str("bursty:0.1").is_err());
+    }
+
+    #[test]
+    fn random_loss() {
+        let mut link = link(Impairment {
+            loss: LossModel::Random(0.1),
+            ..Impairment::default()
+        });
+        assert!((loss_rate(&mut link) - 0.1).abs() < 0.01);
+    }
+
+    #[test]
+    fn gilbert_elliott_loss() {
+        let loss = LossModel::GilbertElliott {
+            p: 0.01,
+            r: 0.3,
+            loss_good: 0.0,
+            loss_bad: 1.0,
+        };
+        let mut link = link(Impairment {
+            loss,
+            ..Impairment::default()
+        });
+        assert!((loss_rate(&mut link) - loss.loss_rate()).abs() < 0.01);
+    }
+
+    #[test]
+    fn delay_distributions() {
+        let now = Instant::now();
+        for distribution in [
+            DelayDistribution::Uniform,
+            DelayDistribution::Normal,
+            DelayDistribution::Pareto,
+        ] {
+            let mut link = link(Impairment {
+                delay: Duration::from_millis(50),
+                jitter: Duration::from_millis(10),
+                distribution,
+                ..Impairment::default()
+            });
+            let delays: Vec<Duration> = (0..SAMPLES)
+                .map(|_| link.datagram(now, 100).unwrap() - now)
+                .collect();
+            let mean = delays.iter().sum::<Duration>() / SAMPLES as u32;
+            assert!(
+                mean.abs_diff(Duration::from_millis(50)) < Duration::from_millis(1),
+                "{distribution}: mean {mean:?}"
+            );
+            if distribution == DelayDistribution::Uniform {
+                assert!(delays
+                    .iter()
+                    .all(|delay| *delay >= Duration::from_millis(40)
+                        && *delay <= Duration::from_millis(60)));
+            }
+        }
+    }
+
+    #[test]
+    fn bandwidth() {
+        let mut link = link(Impairment {
+            bandwidth: Some(1000),
+            ..Impairment::default()
+        });
+        let now = Instant::now();
+        assert_eq!(
+            link.datagram(now, 100),
+            Some(now + Duration::from_millis(100))
+        );
+        assert_eq!(
+            link.datagram(now, 100),
+            Some(now + Duration::from_millis(200))
+        );
+
+        // The bottleneck drains while idle
+        let later = now + Duration::from_secs(1);
+        assert_eq!(
+            link.datagram(later, 500),
+            Some(later + Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn reordering() {
+        let mut link = link(Impairment {
+            delay: Duration::from_millis(50),
+            reorder: 0.25,
+            ..Impairment::default()
+        });
+        let now = Instant::now();
+        let immediate = (0..SAMPLES)
+            .filter(|_| link.datagram(now, 100) == Some(now))
+            .count();
+        assert!((immediate as f64 / SAMPLES as f64 - 0.25).abs() < 0.01);
+    }
+
+    #[test]
+    fn stream_keeps_order() {
+        let mut link = link(Impairment {
+            delay: Duration::from_millis(50),
+            jitter: Duration::from_millis(40),
+            loss: LossModel::Random(0.1),
+            reorder: 0.5,
+            ..Impairment::default()
+        });
+        let now = Instant::now();
+        let deliveries: Vec<Instant> = (0..SAMPLES).map(|_| link.stream(now, 100)).collect();
+        assert!(deliveries.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        // Losses are retransmitted
+        assert!(*deliveries.last().unwrap() - now >= DEFAULT_RETRANSMISSION_TIMEOUT);
+    }
+}
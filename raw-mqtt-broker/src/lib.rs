@@ -0,0 +1,9 @@
+pub mod argument_parser;
+pub mod broker;
+pub mod certificate;
+mod framing;
+pub mod impairment;
+pub mod listener;
+pub mod proxy;
+pub mod reflector;
+pub mod topic;
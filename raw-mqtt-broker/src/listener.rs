@@ -0,0 +1,157 @@
+use log::{debug, info, warn};
+use std::error::Error;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{split, AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls;
+use tokio_rustls::TlsAcceptor;
+
+pub type ServiceError = Box<dyn Error + Send + Sync>;
+
+/// Receive half of a client connection
+pub type RxStream = Box<dyn AsyncRead + Unpin + Send>;
+/// Send half of a client connection
+pub type TxStream = Box<dyn AsyncWrite + Unpin + Send>;
+
+/// Server side of the MQTT connections accepted by the listeners.
+pub trait Service: Clone + Send + Sync + 'static {
+    /// Serve a single client connection until DISCONNECT or end of stream.
+    fn serve(
+        &self,
+        rx_stream: RxStream,
+        tx_stream: TxStream,
+    ) -> impl Future<Output = Result<(), ServiceError>> + Send;
+}
+
+/// Accept MQTT connections over TCP, returns the bound address.
+pub async fn listen_tcp<S: Service>(addr: SocketAddr, service: S) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    info!("Listening on {local_addr} (TCP)");
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer_addr)) => {
+                    let _ = stream.set_nodelay(true);
+                    let service = service.clone();
+                    tokio::spawn(async move {
+                        let (rx_stream, tx_stream) = split(stream);
+                        handle(
+                            &service,
+                            peer_addr,
+                            Box::new(rx_stream),
+                            Box::new(tx_stream),
+                        )
+                        .await;
+                    });
+                }
+                Err(e) => warn!("Failed to accept TCP connection: {e}"),
+            }
+        }
+    });
+
+    Ok(local_addr)
+}
+
+/// Accept MQTT connections over TLS, returns the bound address.
+pub async fn listen_tls<S: Service>(
+    addr: SocketAddr,
+    config: Arc<rustls::ServerConfig>,
+    service: S,
+) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    info!("Listening on {local_addr} (TLS)");
+
+    let acceptor = TlsAcceptor::from(config);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer_addr)) => {
+                    let _ = stream.set_nodelay(true);
+                    let acceptor = acceptor.clone();
+                    let service = service.clone();
+                    tokio::spawn(async move {
+                        match acceptor.accept(stream).await {
+                            Ok(tls_stream) => {
+                                let (rx_stream, tx_stream) = split(tls_stream);
+                                handle(
+                                    &service,
+                                    peer_addr,
+                                    Box::new(rx_stream),
+                                    Box::new(tx_stream),
+                                )
+                                .await;
+                            }
+                            Err(e) => debug!("TLS handshake with {peer_addr} failed: {e}"),
+                        }
+                    });
+                }
+                Err(e) => warn!("Failed to accept TLS connection: {e}"),
+            }
+        }
+    });
+
+    Ok(local_addr)
+}
+
+/// Accept MQTT connections over QUIC (one bidirectional stream per connection), returns the
+/// bound address.
+pub fn listen_quic<S: Service>(
+    addr: SocketAddr,
+    config: quinn::ServerConfig,
+    service: S,
+) -> io::Result<SocketAddr> {
+    let endpoint = quinn::Endpoint::server(config, addr)?;
+    let local_addr = endpoint.local_addr()?;
+    info!("Listening on {local_addr} (QUIC)");
+
+    tokio::spawn(async move {
+        while let Some(connecting) = endpoint.accept().await {
+            let service = service.clone();
+            tokio::spawn(async move {
+                let connection = match connecting.await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        debug!("QUIC handshake failed: {e}");
+                        return;
+                    }
+                };
+                let peer_addr = connection.remote_address();
+                match connection.accept_bi().await {
+                    Ok((tx_stream, rx_stream)) => {
+                        handle(
+                            &service,
+                            peer_addr,
+                            Box::new(rx_stream),
+                            Box::new(tx_stream),
+                        )
+                        .await;
+                        // Let the client close the connection, so that buffered data is
+                        // delivered
+                        connection.closed().await;
+                    }
+                    Err(e) => debug!("QUIC connection from {peer_addr} closed: {e}"),
+                }
+            });
+        }
+    });
+
+    Ok(local_addr)
+}
+
+async fn handle<S: Service>(
+    service: &S,
+    peer_addr: SocketAddr,
+    rx_stream: RxStream,
+    tx_stream: TxStream,
+) {
+    debug!("Connection from {peer_addr}");
+    if let Err(e) = service.serve(rx_stream, tx_stream).await {
+        warn!("Connection from {peer_addr} failed: {e}");
+    }
+}
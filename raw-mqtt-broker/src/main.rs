@@ -0,0 +1,115 @@
+use clap::Parser;
+use log::{debug, info, warn, LevelFilter};
+use std::error;
+
+use raw_mqtt::network::server_verification::CertificateFingerprints;
+use raw_mqtt_broker::argument_parser::{ImpairArgs, ListenArgs, MqttBrokerCli};
+use raw_mqtt_broker::broker::Broker;
+use raw_mqtt_broker::certificate::Certificate;
+use raw_mqtt_broker::listener::{listen_quic, listen_tcp, listen_tls, Service};
+use raw_mqtt_broker::proxy::{proxy_tcp, proxy_udp};
+use raw_mqtt_broker::reflector::Reflector;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn error::Error>> {
+    // Parse command line arguments
+    match MqttBrokerCli::parse() {
+        MqttBrokerCli::Serve(args) => {
+            init_logger(args.debug);
+            debug!("{:?}", args);
+            let mut broker = Broker::new();
+            broker.set_max_packet_size(args.listen.max_packet_size);
+            listen(&args.listen, broker).await
+        }
+        MqttBrokerCli::Reflect(args) => {
+            init_logger(args.debug);
+            debug!("{:?}", args);
+            info!("Reflecting messages ({} mode)", args.mode);
+            let mut reflector = Reflector::new(args.mode);
+            reflector.set_max_packet_size(args.listen.max_packet_size);
+            listen(&args.listen, reflector).await
+        }
+        MqttBrokerCli::Impair(args) => {
+            init_logger(args.debug);
+            debug!("{:?}", args);
+            impair(args).await
+        }
+    }
+}
+
+/// Start the impairment relays and run until interrupted.
+async fn impair(args: ImpairArgs) -> Result<(), Box<dyn error::Error>> {
+    if args.tcp.is_none() && args.udp.is_none() {
+        Err("Nothing to relay, set --tcp and/or --udp")?;
+    }
+
+    let (uplink, downlink) = args.impairments();
+    if uplink.is_none() {
+        warn!("No impairment configured, relaying untouched");
+    }
+    info!(
+        "Impairment: delay {:?} ({} jitter {:?}), loss {:?} ({:.2}%), reorder {}, bandwidth {}",
+        uplink.delay,
+        uplink.distribution,
+        uplink.jitter,
+        uplink.loss,
+        uplink.loss.loss_rate() * 100.0,
+        uplink.reorder,
+        args.bandwidth
+            .map_or("unlimited".to_string(), |kbits| format!("{kbits} kbit/s"))
+    );
+
+    if let Some((addr, target)) = args.tcp {
+        proxy_tcp(addr, target, uplink.clone(), downlink.clone()).await?;
+    }
+    if let Some((addr, target)) = args.udp {
+        proxy_udp(addr, target, uplink, downlink).await?;
+    }
+
+    tokio::signal::ctrl_c().await?;
+    info!("Shutting down");
+
+    Ok(())
+}
+
+fn init_logger(debug: bool) {
+    // Set log level
+    env_logger::builder()
+        .filter_level(if debug {
+            LevelFilter::Debug
+        } else {
+            LevelFilter::Info
+        })
+        .init();
+}
+
+/// Start the listeners and serve connections until interrupted.
+async fn listen<S: Service>(args: &ListenArgs, service: S) -> Result<(), Box<dyn error::Error>> {
+    listen_tcp(args.tcp, service.clone()).await?;
+
+    // Generate a certificate for the secure listeners (if any)
+    if args.tls.is_some() || args.quic.is_some() {
+        let certificate = Certificate::self_signed(args.names.clone())?;
+        let fingerprints = CertificateFingerprints::of(&certificate.der)?;
+        info!(
+            "Certificate fingerprint: {} (SPKI {})",
+            fingerprints.certificate, fingerprints.spki
+        );
+        if let Some(ref path) = args.cert_out {
+            std::fs::write(path, &certificate.pem)?;
+            info!("Saved certificate to {}", path.display());
+        }
+
+        if let Some(addr) = args.tls {
+            listen_tls(addr, certificate.tls_config()?, service.clone()).await?;
+        }
+        if let Some(addr) = args.quic {
+            listen_quic(addr, certificate.quic_config()?, service)?;
+        }
+    }
+
+    tokio::signal::ctrl_c().await?;
+    info!("Shutting down");
+
+    Ok(())
+}
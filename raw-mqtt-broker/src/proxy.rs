@@ -0,0 +1,312 @@
+use bytes::{Bytes, BytesMut};
+use log::{debug, info, warn};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, Instant};
+
+use crate::impairment::{Impairment, Link};
+
+/// Largest chunk of a TCP stream impaired as a whole
+const CHUNK_SIZE: usize = 16 * 1024;
+/// Chunks in flight per direction, the reader waits beyond
+const STREAM_QUEUE: usize = 1024;
+/// Largest UDP datagram
+const DATAGRAM_SIZE: usize = 64 * 1024;
+
+/// Relay TCP connections to `target`, impairing the client to target direction with `uplink`
+/// and the way back with `downlink`. Returns the bound address.
+pub async fn proxy_tcp(
+    addr: SocketAddr,
+    target: SocketAddr,
+    uplink: Impairment,
+    downlink: Impairment,
+) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    info!("Relaying TCP from {local_addr} to {target}");
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((client, peer_addr)) => {
+                    let uplink = uplink.clone();
+                    let downlink = downlink.clone();
+                    tokio::spawn(async move {
+                        let server = match TcpStream::connect(target).await {
+                            Ok(server) => server,
+                            Err(e) => {
+                                warn!("Failed to connect to {target}: {e}");
+                                return;
+                            }
+                        };
+                        debug!("Relaying TCP connection from {peer_addr}");
+                        let _ = client.set_nodelay(true);
+                        let _ = server.set_nodelay(true);
+
+                        let (client_rx, client_tx) = client.into_split();
+                        let (server_rx, server_tx) = server.into_split();
+                        let _ = tokio::join!(
+                            relay_stream(client_rx, server_tx, Link::new(uplink)),
+                            relay_stream(server_rx, client_tx, Link::new(downlink)),
+                        );
+                        debug!("TCP connection from {peer_addr} closed");
+                    });
+                }
+                Err(e) => warn!("Failed to accept TCP connection: {e}"),
+            }
+        }
+    });
+
+    Ok(local_addr)
+}
+
+/// Forward one direction of a stream, delivering every chunk at its scheduled time. The end of
+/// stream is forwarded once every chunk is delivered.
+async fn relay_stream<R, W>(mut rx_stream: R, mut tx_stream: W, mut link: Link) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (queue_tx, mut queue_rx) = mpsc::channel::<(Instant, Bytes)>(STREAM_QUEUE);
+    let writer = tokio::spawn(async move {
+        while let Some((delivery, chunk)) = queue_rx.recv().await {
+            sleep_until(delivery).await;
+            tx_stream.write_all(&chunk).await?;
+        }
+        tx_stream.shutdown().await
+    });
+
+    let mut buffer = BytesMut::with_capacity(CHUNK_SIZE);
+    while rx_stream.read_buf(&mut buffer).await? > 0 {
+        let chunk = buffer.split().freeze();
+        let delivery = link.stream(Instant::now(), chunk.len());
+        if queue_tx.send((delivery, chunk)).await.is_err() {
+            break;
+        }
+        buffer.reserve(CHUNK_SIZE);
+    }
+    drop(queue_tx);
+
+    writer.await?
+}
+
+/// Relay UDP datagrams (e.g. QUIC) to `target`, impairing the client to target direction with
+/// `uplink` and the way back with `downlink`. Every client address gets its own socket towards
+/// the target, kept until the proxy stops. Returns the bound address.
+pub async fn proxy_udp(
+    addr: SocketAddr,
+    target: SocketAddr,
+    uplink: Impairment,
+    downlink: Impairment,
+) -> io::Result<SocketAddr> {
+    let socket = Arc::new(UdpSocket::bind(addr).await?);
+    let local_addr = socket.local_addr()?;
+    info!("Relaying UDP from {local_addr} to {target}");
+
+    tokio::spawn(async move {
+        let mut flows: HashMap<SocketAddr, (Arc<UdpSocket>, Link)> = HashMap::new();
+        let mut buffer = vec![0_u8; DATAGRAM_SIZE];
+        loop {
+            let (len, peer_addr) = match socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(e) => {
+                    // e.g. ICMP port unreachable reported on the next receive
+                    debug!("UDP receive failed: {e}");
+                    continue;
+                }
+            };
+
+            let (upstream, link) = match flows.entry(peer_addr) {
+                Entry::Occupied(flow) => flow.into_mut(),
+                Entry::Vacant(flow) => {
+                    let bind_addr: SocketAddr = match target {
+                        SocketAddr::V4(_) => "0.0.0.0:0".parse().unwrap(),
+                        SocketAddr::V6(_) => "[::]:0".parse().unwrap(),
+                    };
+                    let upstream = match UdpSocket::bind(bind_addr).await {
+                        Ok(upstream) => Arc::new(upstream),
+                        Err(e) => {
+                            warn!("Failed to open a UDP socket: {e}");
+                            continue;
+                        }
+                    };
+                    debug!("Relaying UDP flow from {peer_addr}");
+                    tokio::spawn(relay_downlink(
+                        upstream.clone(),
+                        socket.clone(),
+                        peer_addr,
+                        Link::new(downlink.clone()),
+                    ));
+                    flow.insert((upstream, Link::new(uplink.clone())))
+                }
+            };
+            let datagram = Bytes::copy_from_slice(&buffer[..len]);
+            let delivery = link.datagram(Instant::now(), len);
+            send_datagram(upstream.clone(), datagram, target, delivery);
+        }
+    });
+
+    Ok(local_addr)
+}
+
+/// Forward datagrams from the target back to a client.
+async fn relay_downlink(
+    upstream: Arc<UdpSocket>,
+    socket: Arc<UdpSocket>,
+    peer_addr: SocketAddr,
+    mut link: Link,
+) {
+    let mut buffer = vec![0_u8; DATAGRAM_SIZE];
+    loop {
+        match upstream.recv_from(&mut buffer).await {
+            Ok((len, _)) => {
+                let datagram = Bytes::copy_from_slice(&buffer[..len]);
+                let delivery = link.datagram(Instant::now(), len);
+                send_datagram(socket.clone(), datagram, peer_addr, delivery);
+            }
+            // e.g. ICMP port unreachable reported on the next receive
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                debug!("UDP receive failed: {e}");
+            }
+            Err(e) => {
+                warn!("Relaying UDP flow to {peer_addr} stopped: {e}");
+                break;
+            }
+        }
+    }
+}
+
+/// Send a datagram at its delivery time (datagrams are reordered when their delivery times
+/// are), drop it if lost.
+fn send_datagram(
+    socket: Arc<UdpSocket>,
+    datagram: Bytes,
+    addr: SocketAddr,
+    delivery: Option<Instant>,
+) {
+    let Some(delivery) = delivery else {
+        return;
+    };
+    tokio::spawn(async move {
+        sleep_until(delivery).await;
+        if let Err(e) = socket.send_to(&datagram, addr).await {
+            debug!("UDP send to {addr} failed: {e}");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::broker::Broker;
+    use crate::certificate::Certificate;
+    use crate::impairment::LossModel;
+    use crate::listener::{listen_quic, listen_tcp};
+    use mqttbytes::QoS;
+    use raw_mqtt::client::simple_client::SimpleMqttClient;
+    use raw_mqtt::network::server_verification::Fingerprint;
+    use raw_mqtt::network::transport::{QuicConfig, Transport};
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    fn delay(millis: u64) -> Impairment {
+        Impairment {
+            delay: Duration::from_millis(millis),
+            ..Impairment::default()
+        }
+    }
+
+    async fn publish(transport: Transport, port: u16, count: usize) {
+        let mut client = SimpleMqttClient::new(
+            "127.0.0.1".to_string(),
+            "localhost".to_string(),
+            port.to_string(),
+            transport,
+            raw_mqtt::Version::V311,
+        );
+        client.connect().await.unwrap();
+        for _ in 0..count {
+            client
+                .publish("impaired".to_string(), "x".to_string(), QoS::AtLeastOnce)
+                .await
+                .unwrap();
+        }
+        client.disconnect().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn tcp_delay() {
+        let broker = listen_tcp("127.0.0.1:0".parse().unwrap(), Broker::new())
+            .await
+            .unwrap();
+        let proxy = proxy_tcp("127.0.0.1:0".parse().unwrap(), broker, delay(20), delay(30))
+            .await
+            .unwrap();
+
+        // CONNECT and PUBLISH round trips
+        let start = std::time::Instant::now();
+        publish(Transport::from_str("tcp").unwrap(), proxy.port(), 1).await;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn tcp_loss_is_retransmitted() {
+        let broker = listen_tcp("127.0.0.1:0".parse().unwrap(), Broker::new())
+            .await
+            .unwrap();
+        let retransmission_timeout = Duration::from_millis(10);
+        // Every chunk is lost once, so each way of each round trip waits for a retransmission
+        let lossy = Impairment {
+            loss: LossModel::Random(1.0),
+            retransmission_timeout: Some(retransmission_timeout),
+            ..Impairment::default()
+        };
+        let proxy = proxy_tcp("127.0.0.1:0".parse().unwrap(), broker, lossy.clone(), lossy)
+            .await
+            .unwrap();
+
+        // CONNECT and PUBLISH round trips
+        let start = std::time::Instant::now();
+        publish(Transport::from_str("tcp").unwrap(), proxy.port(), 20).await;
+        assert!(start.elapsed() >= 21 * 2 * retransmission_timeout);
+    }
+
+    #[tokio::test]
+    async fn quic_over_lossy_udp() {
+        let certificate = Certificate::self_signed(vec!["localhost".to_string()]).unwrap();
+        let broker = listen_quic(
+            "127.0.0.1:0".parse().unwrap(),
+            certificate.quic_config().unwrap(),
+            Broker::new(),
+        )
+        .unwrap();
+        let lossy = Impairment {
+            delay: Duration::from_millis(5),
+            jitter: Duration::from_millis(2),
+            loss: LossModel::Random(0.05),
+            reorder: 0.1,
+            seed: Some(7),
+            ..Impairment::default()
+        };
+        let proxy = proxy_udp("127.0.0.1:0".parse().unwrap(), broker, lossy.clone(), lossy)
+            .await
+            .unwrap();
+
+        let transport = Transport::QUIC(QuicConfig {
+            pins: vec![Fingerprint::of(&certificate.der)],
+            ..QuicConfig::default()
+        });
+        tokio::time::timeout(
+            Duration::from_secs(30),
+            publish(transport, proxy.port(), 20),
+        )
+        .await
+        .expect("QUIC did not recover from losses");
+    }
+}
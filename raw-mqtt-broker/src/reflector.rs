@@ -0,0 +1,302 @@
+use bytes::{BufMut, BytesMut};
+use log::{debug, info};
+use mqttbytes::v4::{
+    ConnAck, ConnectReturnCode, Packet, PubAck, PubComp, PubRec, Publish, SubAck,
+    SubscribeReasonCode,
+};
+use mqttbytes::QoS;
+use std::fmt;
+use std::str::FromStr;
+use tokio::io::AsyncWriteExt;
+
+use raw_mqtt::client::rtt::{timestamp, TIMESTAMP_LEN};
+
+use crate::framing::{read_packet, write_packet, DEFAULT_MAX_PACKET_SIZE};
+use crate::listener::{RxStream, Service, ServiceError, TxStream};
+
+/// What the reflector sends back for every PUBLISH (besides the ack expected by its QoS).
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum ReflectMode {
+    /// The whole payload, prefixed with the receive timestamp
+    #[default]
+    Echo,
+    /// The receive timestamp followed by the send timestamp only (beginning of the payload), to
+    /// keep the return path lightly loaded
+    Ack,
+}
+
+impl FromStr for ReflectMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "echo" => Ok(ReflectMode::Echo),
+            "ack" => Ok(ReflectMode::Ack),
+            _ => Err(format!("Unknown reflect mode: {s}")),
+        }
+    }
+}
+
+impl fmt::Display for ReflectMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReflectMode::Echo => write!(f, "echo"),
+            ReflectMode::Ack => write!(f, "ack"),
+        }
+    }
+}
+
+/// Reference peer to measure transport round-trip times without any broker processing: every
+/// PUBLISH is sent straight back to its sender (QoS 0, same topic), stamped with the time it was
+/// received (see `raw_mqtt::client::rtt`). Nothing is routed between clients.
+#[derive(Debug, Copy, Clone)]
+pub struct Reflector {
+    mode: ReflectMode,
+    max_packet_size: usize,
+}
+
+impl Reflector {
+    pub fn new(mode: ReflectMode) -> Reflector {
+        Reflector {
+            mode,
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+        }
+    }
+
+    /// Largest packet accepted (bytes), clients sending more are disconnected.
+    pub fn set_max_packet_size(&mut self, max_packet_size: usize) {
+        self.max_packet_size = max_packet_size;
+    }
+
+    fn reflect(&self, publish: &Publish, received: u128) -> Publish {
+        let payload = match self.mode {
+            ReflectMode::Echo => &publish.payload[..],
+            ReflectMode::Ack => &publish.payload[..publish.payload.len().min(TIMESTAMP_LEN)],
+        };
+
+        let mut reflected = BytesMut::with_capacity(TIMESTAMP_LEN + payload.len());
+        reflected.put(format!("{received:0TIMESTAMP_LEN$}").as_bytes());
+        reflected.put(payload);
+        Publish::from_bytes(publish.topic.clone(), QoS::AtMostOnce, reflected.freeze())
+    }
+}
+
+impl Service for Reflector {
+    async fn serve(
+        &self,
+        mut rx_stream: RxStream,
+        mut tx_stream: TxStream,
+    ) -> Result<(), ServiceError> {
+        let mut buffer = BytesMut::new();
+        let mut out = BytesMut::new();
+
+        // The first packet must be CONNECT
+        let connect = match read_packet(&mut rx_stream, &mut buffer, self.max_packet_size).await? {
+            Some(Packet::Connect(connect)) => connect,
+            Some(other) => Err(format!("Expected CONNECT, received {other:?}"))?,
+            None => return Ok(()),
+        };
+        write_packet(
+            &Packet::ConnAck(ConnAck::new(ConnectReturnCode::Success, false)),
+            &mut out,
+        );
+        tx_stream.write_all(&out).await?;
+        info!("Client {} connected", connect.client_id);
+
+        while let Some(packet) =
+            read_packet(&mut rx_stream, &mut buffer, self.max_packet_size).await?
+        {
+            out.clear();
+            match packet {
+                Packet::Publish(publish) => {
+                    let received = timestamp();
+                    match publish.qos {
+                        QoS::AtMostOnce => {}
+                        QoS::AtLeastOnce => {
+                            write_packet(&Packet::PubAck(PubAck::new(publish.pkid)), &mut out)
+                        }
+                        QoS::ExactlyOnce => {
+                            write_packet(&Packet::PubRec(PubRec::new(publish.pkid)), &mut out)
+                        }
+                    }
+                    write_packet(&Packet::Publish(self.reflect(&publish, received)), &mut out);
+                }
+                Packet::PubRel(pub_rel) => {
+                    write_packet(&Packet::PubComp(PubComp::new(pub_rel.pkid)), &mut out)
+                }
+                Packet::Subscribe(subscribe) => {
+                    // Accepted but nothing is ever forwarded
+                    let codes = subscribe
+                        .filters
+                        .iter()
+                        .map(|filter| SubscribeReasonCode::Success(filter.qos))
+                        .collect();
+                    write_packet(
+                        &Packet::SubAck(SubAck::new(subscribe.pkid, codes)),
+                        &mut out,
+                    );
+                }
+                Packet::PingReq => write_packet(&Packet::PingResp, &mut out),
+                Packet::Disconnect => break,
+                other => debug!("Ignoring {other:?}"),
+            }
+
+            if !out.is_empty() {
+                tx_stream.write_all(&out).await?;
+            }
+        }
+
+        tx_stream.shutdown().await?;
+        info!("Client {} disconnected", connect.client_id);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mqttbytes::v4::Connect;
+    use raw_mqtt::client::rtt::RttSample;
+    use raw_mqtt::client::stream_client::StreamMqttClient;
+    use raw_mqtt::network::transport::Transport;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio::io::{split, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::time::timeout;
+
+    use crate::listener::listen_tcp;
+
+    async fn start(mode: ReflectMode) -> SocketAddr {
+        listen_tcp("127.0.0.1:0".parse().unwrap(), Reflector::new(mode))
+            .await
+            .unwrap()
+    }
+
+    /// Send packets and collect every packet received until the reflector closes the stream.
+    async fn exchange(addr: SocketAddr, packets: Vec<Packet>) -> Vec<Packet> {
+        let (mut rx_stream, mut tx_stream) = split(TcpStream::connect(addr).await.unwrap());
+        let mut buffer = BytesMut::new();
+        for packet in packets {
+            write_packet(&packet, &mut buffer);
+        }
+        tx_stream.write_all(&buffer).await.unwrap();
+
+        let mut received = Vec::new();
+        let mut buffer = BytesMut::new();
+        while let Some(packet) = timeout(
+            Duration::from_secs(5),
+            read_packet(&mut rx_stream, &mut buffer, DEFAULT_MAX_PACKET_SIZE),
+        )
+        .await
+        .expect("Reflector did not close the stream")
+        .unwrap()
+        {
+            received.push(packet);
+        }
+        received
+    }
+
+    fn publish(payload: &str, qos: QoS, pkid: u16) -> Packet {
+        let mut publish = Publish::new("rtt", qos, payload);
+        publish.pkid = pkid;
+        Packet::Publish(publish)
+    }
+
+    fn reflected(packet: &Packet) -> &Publish {
+        match packet {
+            Packet::Publish(publish) => {
+                assert_eq!(publish.topic, "rtt");
+                assert_eq!(publish.qos, QoS::AtMostOnce);
+                publish
+            }
+            other => panic!("Unexpected packet: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn echo() {
+        let addr = start(ReflectMode::Echo).await;
+        let sent = format!("{}payload", timestamp());
+        let before = timestamp();
+
+        let received = exchange(
+            addr,
+            vec![
+                Packet::Connect(Connect::new("reflect")),
+                publish(&sent, QoS::AtMostOnce, 0),
+                publish(&sent, QoS::AtLeastOnce, 1),
+                publish(&sent, QoS::ExactlyOnce, 2),
+                Packet::PubRel(mqttbytes::v4::PubRel::new(2)),
+                Packet::PingReq,
+                Packet::Disconnect,
+            ],
+        )
+        .await;
+
+        assert!(matches!(received[0], Packet::ConnAck(_)));
+        assert_eq!(received[2], Packet::PubAck(PubAck::new(1)));
+        assert_eq!(received[4], Packet::PubRec(PubRec::new(2)));
+        assert_eq!(received[6], Packet::PubComp(PubComp::new(2)));
+        assert_eq!(received[7], Packet::PingResp);
+        assert_eq!(received.len(), 8);
+
+        for packet in [&received[1], &received[3], &received[5]] {
+            let payload = &reflected(packet).payload;
+            assert_eq!(&payload[TIMESTAMP_LEN..], sent.as_bytes());
+            let sample = RttSample::parse(payload, timestamp()).unwrap();
+            assert!(sample.reflected >= before);
+        }
+    }
+
+    #[tokio::test]
+    async fn ack() {
+        let addr = start(ReflectMode::Ack).await;
+        let sent = format!("{}{}", timestamp(), "x".repeat(1000));
+
+        let received = exchange(
+            addr,
+            vec![
+                Packet::Connect(Connect::new("reflect")),
+                publish(&sent, QoS::AtMostOnce, 0),
+                Packet::Disconnect,
+            ],
+        )
+        .await;
+
+        let payload = &reflected(&received[1]).payload;
+        assert_eq!(payload.len(), 2 * TIMESTAMP_LEN);
+        assert_eq!(&payload[TIMESTAMP_LEN..], &sent.as_bytes()[..TIMESTAMP_LEN]);
+    }
+
+    #[tokio::test]
+    async fn stream_client_rtt() {
+        let addr = start(ReflectMode::Echo).await;
+        let mut client = StreamMqttClient::new(
+            "127.0.0.1".to_string(),
+            "localhost".to_string(),
+            addr.port().to_string(),
+            Transport::from_str("tcp").unwrap(),
+            raw_mqtt::Version::V311,
+        );
+        client.set_rtt(true);
+        let samples = client.rtt_samples();
+
+        client.connect().await.unwrap();
+        for _ in 0..10 {
+            client
+                .stream_publish("rtt".to_string(), timestamp().to_string(), QoS::AtLeastOnce)
+                .await
+                .unwrap();
+        }
+        client.disconnect().await.unwrap();
+
+        let mut count = 0;
+        while let Ok(sample) = samples.recv().await {
+            assert!(sample.sent <= sample.reflected && sample.reflected <= sample.received);
+            count += 1;
+        }
+        assert_eq!(count, 10);
+    }
+}
@@ -0,0 +1,85 @@
+/// Check that a topic filter is well formed: `#` only as the last level and wildcards never
+/// mixed with other characters within a level.
+pub fn valid_filter(filter: &str) -> bool {
+    if filter.is_empty() {
+        return false;
+    }
+
+    let levels: Vec<&str> = filter.split('/').collect();
+    levels.iter().enumerate().all(|(i, level)| match *level {
+        "#" => i == levels.len() - 1,
+        "+" => true,
+        level => !level.contains(['#', '+']),
+    })
+}
+
+/// Check whether a topic name matches a topic filter, with `+` matching a single level and `#`
+/// every remaining level (including the parent one). Wildcards at the first level do not match
+/// topics starting with `$`.
+pub fn matches(filter: &str, topic: &str) -> bool {
+    if topic.starts_with('$') && filter.starts_with(['+', '#']) {
+        return false;
+    }
+
+    let mut filter_levels = filter.split('/');
+    let mut topic_levels = topic.split('/');
+    loop {
+        match (filter_levels.next(), topic_levels.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => {}
+            (Some(filter_level), Some(topic_level)) if filter_level == topic_level => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_validation() {
+        assert!(valid_filter("a/b/c"));
+        assert!(valid_filter("a/+/c"));
+        assert!(valid_filter("+"));
+        assert!(valid_filter("a/#"));
+        assert!(valid_filter("#"));
+        assert!(!valid_filter(""));
+        assert!(!valid_filter("a/#/c"));
+        assert!(!valid_filter("a/b#"));
+        assert!(!valid_filter("a/b+/c"));
+    }
+
+    #[test]
+    fn exact_match() {
+        assert!(matches("a/b", "a/b"));
+        assert!(!matches("a/b", "a/c"));
+        assert!(!matches("a/b", "a/b/c"));
+        assert!(!matches("a/b/c", "a/b"));
+    }
+
+    #[test]
+    fn single_level_wildcard() {
+        assert!(matches("a/+", "a/b"));
+        assert!(matches("a/+/c", "a/b/c"));
+        assert!(matches("a/+", "a/"));
+        assert!(!matches("a/+", "a/b/c"));
+        assert!(!matches("a/+", "a"));
+    }
+
+    #[test]
+    fn multi_level_wildcard() {
+        assert!(matches("#", "a/b/c"));
+        assert!(matches("a/#", "a/b/c"));
+        assert!(matches("a/#", "a"));
+        assert!(!matches("a/#", "b/c"));
+    }
+
+    #[test]
+    fn system_topics() {
+        assert!(!matches("#", "$SYS/uptime"));
+        assert!(!matches("+/uptime", "$SYS/uptime"));
+        assert!(matches("$SYS/#", "$SYS/uptime"));
+    }
+}
@@ -1,26 +1,59 @@
+use chrono::Utc;
 use clap::Parser;
-use log::{debug, info, LevelFilter};
+use log::{debug, info, warn, LevelFilter};
 use mqttbytes::QoS;
 use std::error;
 use std::str::FromStr;
+use std::time::Instant;
+use tokio::time::MissedTickBehavior;
 
+use raw_mqtt::client::rtt;
 use raw_mqtt::client::simple_client::SimpleMqttClient;
-use raw_mqtt::network::transport::Transport;
 use raw_mqtt::utility::argument_parser::{MqttCli, Request};
+use raw_mqtt::utility::output::{
+    ConnectionEvent, MessageEvent, MessageRecord, RunRecord, TransportSession,
+};
+use raw_mqtt::utility::summary::{short_duration, LatencyHistogram, PingSummary};
 use raw_mqtt::Version;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Box<dyn error::Error>> {
     // Parse command line arguments
-    let (request, args, message_payload) = match MqttCli::parse() {
+    let (request, args, message_payload, output_args, parameters) = match MqttCli::parse() {
         MqttCli::Publish(args) => {
+            let parameters = args.parameters();
             let payload = match args.size {
                 Some(size) => String::from_utf8(vec![127_u8; size]).unwrap(),
                 None => args.message.unwrap(),
             };
-            (Request::Publish, args.common_args, Some(payload))
+            (
+                Request::Publish,
+                args.common_args,
+                Some(payload),
+                Some(args.output_args),
+                parameters,
+            )
+        }
+        MqttCli::Subscribe(args) => {
+            let parameters = args.common_args.parameters();
+            (
+                Request::Subscribe,
+                args.common_args,
+                None,
+                Some(args.output_args),
+                parameters,
+            )
+        }
+        MqttCli::Ping(args) => {
+            let parameters = args.parameters();
+            (
+                args.request()?,
+                args.common_args,
+                None,
+                Some(args.output_args),
+                parameters,
+            )
         }
-        MqttCli::Subscribe(args) => (Request::Subscribe, args.common_args, None),
     };
 
     // Set log level
@@ -34,18 +67,16 @@ async fn main() -> Result<(), Box<dyn error::Error>> {
 
     debug!("{:?}", args);
 
+    // Write structured results (if requested)
+    let output = match output_args {
+        Some(ref output_args) => output_args.output()?,
+        None => None,
+    };
+    let started = Utc::now();
+
     let proto_version = Version::from_str(args.proto_version.as_str()).unwrap();
-    let mut transport = Transport::from_str(args.transport.as_str()).unwrap();
-    match transport {
-        Transport::TLS(ref mut config) => {
-            config.insecure = args.insecure;
-        }
-        Transport::QUIC(ref mut config) => {
-            config.insecure = args.insecure;
-        }
-        _ => {}
-    }
-    let qos = match { args.qos } {
+    let transport = args.transport()?;
+    let qos = match args.qos {
         0 => QoS::AtMostOnce,
         1 => QoS::AtLeastOnce,
         2 => QoS::ExactlyOnce,
@@ -53,27 +84,173 @@ async fn main() -> Result<(), Box<dyn error::Error>> {
     };
 
     let mut client = SimpleMqttClient::new(
-        args.host,
-        args.server_name,
+        args.host.clone(),
+        args.server_name.clone(),
         args.port.to_string(),
         transport,
-        proto_version
+        proto_version,
     );
+    // Checked for pings, at most u16::MAX
+    client.set_keep_alive(args.keep_alive.min(u16::MAX as u64) as u16);
 
+    let connecting = Instant::now();
     client.connect().await?;
+    let connected = connecting.elapsed();
+
+    // Report the negotiated TLS session (if requested)
+    let session = client.session_info();
+    let transport_session =
+        TransportSession::new(&args.transport, client.peer_addr(), session.as_ref());
+    let mut events = vec![ConnectionEvent::connected(
+        0,
+        client.client_id(),
+        connected,
+        transport_session.clone(),
+    )];
+    args.report_session(session.as_ref())?;
 
+    let mut messages = Vec::new();
+    let mut results = None;
+    let mut closed = false;
     match request {
         Request::Publish => {
             let message_payload = message_payload.unwrap();
             info!("Publishing message of size: {}", message_payload.len());
+            let publishing = Instant::now();
+            messages.push(MessageRecord::published(0, rtt::timestamp()));
             client.publish(args.topic, message_payload, qos).await?;
+            // The publish returns once acked (QoS 1)
+            if qos != QoS::AtMostOnce {
+                messages.push(MessageRecord::received(
+                    0,
+                    MessageEvent::Ack,
+                    rtt::timestamp(),
+                    publishing.elapsed(),
+                ));
+            }
         }
         Request::Subscribe => {
-            todo!("Subscribe not implemented")
+            client.subscribe(args.topic.clone(), qos).await?;
+            info!("Subscribed to {}, waiting for messages", args.topic);
+            let interrupted = tokio::signal::ctrl_c();
+            tokio::pin!(interrupted);
+            loop {
+                let message = tokio::select! {
+                    _ = &mut interrupted => break,
+                    message = client.receive() => message,
+                };
+                // Results are kept when the broker closes the connection
+                let message = match message {
+                    Ok(message) => message,
+                    Err(e) => {
+                        warn!("Stopped receiving: {e}");
+                        closed = true;
+                        break;
+                    }
+                };
+                info!(
+                    "Received message of size {} on {}",
+                    message.publish.payload.len(),
+                    message.publish.topic
+                );
+                messages.push(MessageRecord::delivered(
+                    0,
+                    &message.publish.payload,
+                    message.received,
+                ));
+            }
         }
+        Request::Ping {
+            interval,
+            count,
+            timeout,
+        } => {
+            info!(
+                "Pinging {} over {} every {interval:?}",
+                transport_session.peer.as_deref().unwrap_or("the broker"),
+                args.transport
+            );
+            let mut timer = tokio::time::interval(interval);
+            // A slow answer delays the next ping, no burst
+            timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let interrupted = tokio::signal::ctrl_c();
+            tokio::pin!(interrupted);
+
+            let mut rtts = LatencyHistogram::new();
+            let mut sent = 0;
+            while count == 0 || sent < count {
+                tokio::select! {
+                    _ = &mut interrupted => break,
+                    _ = timer.tick() => {}
+                }
+                // A ping interrupted before its answer is not counted
+                let answer = tokio::select! {
+                    _ = &mut interrupted => break,
+                    answer = tokio::time::timeout(timeout, client.ping()) => answer,
+                };
+                sent += 1;
+                match answer {
+                    Ok(rtt) => {
+                        let rtt = rtt?;
+                        info!(
+                            "PINGRESP: seq={sent} time={}",
+                            short_duration(rtt.as_nanos() as f64)
+                        );
+                        messages.push(MessageRecord::received(
+                            0,
+                            MessageEvent::Ping,
+                            rtt::timestamp(),
+                            rtt,
+                        ));
+                        rtts.record(rtt);
+                    }
+                    Err(_) => {
+                        // The connection is given up, as the keep-alive requires
+                        warn!(
+                            "No PINGRESP within {timeout:?} (seq={sent}), closing the connection"
+                        );
+                        break;
+                    }
+                }
+            }
+
+            let summary = PingSummary::new(sent, &rtts);
+            for line in summary.table() {
+                info!("{line}");
+            }
+            results = Some(summary);
+        }
+    }
+
+    let disconnecting = Instant::now();
+    if !closed {
+        client.disconnect().await?;
+    }
+    events.push(ConnectionEvent::disconnected(
+        0,
+        client.client_id(),
+        disconnecting.elapsed(),
+    ));
+
+    if let Some(output) = output {
+        let run = RunRecord::new(
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            parameters,
+            started,
+            Some(transport_session),
+            results.clone(),
+        );
+        output.write("events", &events)?;
+        output.write("messages", &messages)?;
+        output.write("run", &[run])?;
+        info!("Saved the results to {}", output.directory().display());
     }
 
-    client.disconnect().await?;
+    // Like ping, unanswered pings fail the run
+    if results.as_ref().is_some_and(|summary| summary.lost > 0) {
+        Err("Ping unanswered")?;
+    }
 
     Ok(())
 }
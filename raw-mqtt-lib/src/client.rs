@@ -1,3 +1,6 @@
+#[allow(clippy::module_inception)]
 pub mod client;
+pub mod metrics;
+pub mod rtt;
 pub mod simple_client;
 pub mod stream_client;
@@ -1,15 +1,34 @@
 use bytes::BytesMut;
-use log::info;
-use mqttbytes::v4::{Connect, ConnectReturnCode, Disconnect, Packet, Publish};
+use log::{debug, info};
+use mqttbytes::v4::{
+    Connect, ConnectReturnCode, Disconnect, Packet, PingReq, PubAck, PubComp, PubRec, Publish,
+    SubAck, Subscribe, SubscribeReasonCode,
+};
 use mqttbytes::QoS;
+use std::collections::VecDeque;
 use std::error::Error;
+use std::net::SocketAddr;
 use std::sync::atomic::{AtomicU16, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use crate::client::rtt;
 use crate::network::network::Network;
+use crate::network::session::SessionInfo;
 use crate::network::transport::{TcpConfig, Transport};
 use crate::{parse_packet, Version, ACK_PACKET_SIZE};
 
+/// Keep-alive sent on connect, in seconds
+const DEFAULT_KEEP_ALIVE: u16 = 10;
+
+/// Message received on a subscription.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub publish: Publish,
+    /// Receive time in nanoseconds since the Unix epoch
+    pub received: u128,
+}
+
 #[derive(Debug, Clone)]
 pub struct Client<T> {
     client_id: String,
@@ -17,8 +36,15 @@ pub struct Client<T> {
     host: String,
     server_name: String,
     port: String,
+    keep_alive: u16,
     pub(crate) version: Version,
     pub(crate) network: T,
+    /// Bytes received but not parsed yet (subscriptions only)
+    rx_buffer: BytesMut,
+    /// Messages received while waiting for a SUBACK (e.g. retained messages)
+    early: VecDeque<Message>,
+    /// Time of the last packet sent, for the keep-alive
+    last_sent: tokio::time::Instant,
 }
 
 impl<T> Default for Client<T>
@@ -32,8 +58,12 @@ where
             host: "localhost".to_string(),
             server_name: "localhost".to_string(),
             port: "1883".to_string(),
+            keep_alive: DEFAULT_KEEP_ALIVE,
             version: Version::V311,
             network: T::new(Transport::TCP(TcpConfig::default())),
+            rx_buffer: BytesMut::new(),
+            early: VecDeque::new(),
+            last_sent: tokio::time::Instant::now(),
         }
     }
 }
@@ -54,12 +84,35 @@ impl<T> Client<T> {
             host,
             server_name,
             port,
+            keep_alive: DEFAULT_KEEP_ALIVE,
             version,
             network: T::new(transport),
             pkid: Arc::new(AtomicU16::new(1)),
+            rx_buffer: BytesMut::new(),
+            early: VecDeque::new(),
+            last_sent: tokio::time::Instant::now(),
         }
     }
 
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    /// Replace the generated client id (mqtt-tool-<uuid>), must be set before connecting.
+    pub fn set_client_id(&mut self, client_id: String) {
+        self.client_id = client_id;
+    }
+
+    /// Longest time between two packets sent, in seconds (0 disables the keep-alive), must be
+    /// set before connecting. The broker closes the connection after 1.5 times this time.
+    pub fn set_keep_alive(&mut self, keep_alive: u16) {
+        self.keep_alive = keep_alive;
+    }
+
+    pub(crate) fn keep_alive(&self) -> u16 {
+        self.keep_alive
+    }
+
     pub async fn connect(&mut self) -> Result<(), Box<dyn Error>>
     where
         T: Network,
@@ -76,7 +129,8 @@ impl<T> Client<T> {
             }
             Version::V311 => {
                 let mut send_buffer = BytesMut::new();
-                let conn_packet = Connect::new(&self.client_id);
+                let mut conn_packet = Connect::new(&self.client_id);
+                conn_packet.keep_alive = self.keep_alive;
                 conn_packet
                     .write(&mut send_buffer)
                     .expect("Packet serialization failed");
@@ -88,7 +142,7 @@ impl<T> Client<T> {
         };
 
         // Send connect message
-        self.network.send(send_buffer.as_ref()).await?;
+        self.send(send_buffer.as_ref()).await?;
 
         // Wait for connection ack message
         let mut recv_buffer = self.network.recv(ACK_PACKET_SIZE).await?;
@@ -98,7 +152,8 @@ impl<T> Client<T> {
             Version::V31 => {
                 todo!("MQTT v3.1 not supported yet")
             }
-            Version::V311 => parse_packet(&mut recv_buffer, 1024, &self.version).unwrap(),
+            Version::V311 => parse_packet(&mut recv_buffer, 1024, &self.version)
+                .map_err(|e| format!("Malformed connection ack: {e}"))?,
             Version::V5 => {
                 todo!("MQTT v5 not supported yet")
             }
@@ -156,14 +211,15 @@ impl<T> Client<T> {
         pub_req
             .write(&mut send_buffer)
             .expect("Serialization failed");
-        self.network.send(send_buffer.as_ref()).await?;
+        self.send(send_buffer.as_ref()).await?;
 
         match qos {
             QoS::AtMostOnce => Ok(()),
             QoS::AtLeastOnce => {
                 // Wait for publish ack
                 let mut recv_buffer = self.network.recv(4).await?;
-                let packet = parse_packet(&mut recv_buffer, 1024, &self.version).unwrap();
+                let packet = parse_packet(&mut recv_buffer, 1024, &self.version)
+                    .map_err(|e| format!("Malformed publish ack: {e}"))?;
                 info!("Publish ack: {packet:?}");
 
                 // Check received message
@@ -187,6 +243,195 @@ impl<T> Client<T> {
         }
     }
 
+    /// Subscribe to a topic filter and wait for the SUBACK. Messages delivered before it (e.g.
+    /// retained ones) are acked and kept for `receive`.
+    pub async fn subscribe(&mut self, filter: String, qos: QoS) -> Result<(), Box<dyn Error>>
+    where
+        T: Network,
+    {
+        let sub_req = self.subscribe_packet(&filter, qos);
+
+        // Send subscribe message
+        let mut send_buffer = BytesMut::new();
+        sub_req
+            .write(&mut send_buffer)
+            .expect("Packet serialization failed");
+        self.send(send_buffer.as_ref()).await?;
+
+        // Wait for subscribe ack, the broker may deliver messages first
+        loop {
+            let (packet, received) = self.next_packet().await?;
+            match packet {
+                Packet::SubAck(sub_ack) => {
+                    info!("Subscribe ack: {sub_ack:?}");
+                    return check_sub_ack(&sub_req, &sub_ack);
+                }
+                Packet::Publish(publish) => {
+                    let message = self.accept(publish, received).await?;
+                    self.early.push_back(message);
+                }
+                other => Err(format!("Unexpected message: {:?}", other))?,
+            }
+        }
+    }
+
+    /// Subscribe message for a topic filter, with the next packet id.
+    pub(crate) fn subscribe_packet(&self, filter: &str, qos: QoS) -> Subscribe {
+        let mut sub_req = match self.version {
+            Version::V31 => {
+                todo!("MQTT v3.1 not supported yet")
+            }
+            Version::V311 => Subscribe::new(filter, qos),
+            Version::V5 => {
+                todo!("MQTT v5 not supported yet")
+            }
+        };
+
+        // Set packet id
+        while sub_req.pkid == 0 {
+            sub_req.pkid = self.pkid.fetch_add(1, Ordering::SeqCst);
+        }
+        sub_req
+    }
+
+    /// Wait for the next message on the subscriptions and ack it. A PINGREQ is sent whenever
+    /// nothing was sent for the keep-alive, the broker would close an idle connection.
+    pub async fn receive(&mut self) -> Result<Message, Box<dyn Error>>
+    where
+        T: Network,
+    {
+        if let Some(message) = self.early.pop_front() {
+            return Ok(message);
+        }
+
+        loop {
+            let next = if self.keep_alive == 0 {
+                Some(self.next_packet().await?)
+            } else {
+                let idle = self.last_sent + Duration::from_secs(self.keep_alive as u64);
+                tokio::time::timeout_at(idle, self.next_packet())
+                    .await
+                    .ok()
+                    .transpose()?
+            };
+            let Some((packet, received)) = next else {
+                let mut send_buffer = BytesMut::new();
+                PingReq
+                    .write(&mut send_buffer)
+                    .expect("Packet serialization failed");
+                self.send(send_buffer.as_ref()).await?;
+                continue;
+            };
+
+            match packet {
+                Packet::Publish(publish) => return self.accept(publish, received).await,
+                Packet::PubRel(pub_rel) => {
+                    let mut send_buffer = BytesMut::new();
+                    PubComp::new(pub_rel.pkid)
+                        .write(&mut send_buffer)
+                        .expect("Packet serialization failed");
+                    self.send(send_buffer.as_ref()).await?;
+                }
+                Packet::PingResp => debug!("Keep-alive answered"),
+                other => Err(format!("Unexpected message: {:?}", other))?,
+            }
+        }
+    }
+
+    /// Ack a message delivered above QoS 0.
+    async fn accept(&mut self, publish: Publish, received: u128) -> Result<Message, Box<dyn Error>>
+    where
+        T: Network,
+    {
+        let mut send_buffer = BytesMut::new();
+        match publish.qos {
+            QoS::AtMostOnce => Ok(0),
+            QoS::AtLeastOnce => PubAck::new(publish.pkid).write(&mut send_buffer),
+            QoS::ExactlyOnce => PubRec::new(publish.pkid).write(&mut send_buffer),
+        }
+        .expect("Packet serialization failed");
+        if !send_buffer.is_empty() {
+            self.send(send_buffer.as_ref()).await?;
+        }
+        Ok(Message { publish, received })
+    }
+
+    /// Next packet whatever its size, with its receive time. Nothing is lost if cancelled.
+    async fn next_packet(&mut self) -> Result<(Packet, u128), Box<dyn Error>>
+    where
+        T: Network,
+    {
+        loop {
+            match parse_packet(&mut self.rx_buffer, usize::MAX, &self.version) {
+                Ok(packet) => return Ok((packet, rtt::timestamp())),
+                Err(mqttbytes::Error::InsufficientBytes(_)) => {
+                    if self.network.recv_buf(&mut self.rx_buffer).await? == 0 {
+                        Err("Connection closed")?;
+                    }
+                }
+                Err(e) => Err(format!("Malformed packet: {e}"))?,
+            }
+        }
+    }
+
+    /// Send a packet, the keep-alive restarts.
+    async fn send(&mut self, buffer: &[u8]) -> Result<(), Box<dyn Error>>
+    where
+        T: Network,
+    {
+        self.network.send(buffer).await?;
+        self.last_sent = tokio::time::Instant::now();
+        Ok(())
+    }
+
+    /// Send a PINGREQ and wait for the PINGRESP, returning the round-trip time.
+    pub async fn ping(&mut self) -> Result<Duration, Box<dyn Error>>
+    where
+        T: Network,
+    {
+        let mut send_buffer = BytesMut::new();
+        match self.version {
+            Version::V31 => {
+                todo!("MQTT v3.1 not supported yet")
+            }
+            Version::V311 => {
+                PingReq
+                    .write(&mut send_buffer)
+                    .expect("Packet serialization failed");
+            }
+            Version::V5 => {
+                todo!("MQTT v5 not supported yet")
+            }
+        };
+        let sent = Instant::now();
+        self.send(send_buffer.as_ref()).await?;
+
+        // Wait for ping response (fixed header only)
+        let mut recv_buffer = self.network.recv(2).await?;
+        let rtt = sent.elapsed();
+        let packet = parse_packet(&mut recv_buffer, 1024, &self.version)
+            .map_err(|e| format!("Malformed ping response: {e}"))?;
+
+        match packet {
+            Packet::PingResp => Ok(rtt),
+            other => Err(format!("Unexpected message: {:?}", other))?,
+        }
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr>
+    where
+        T: Network,
+    {
+        self.network.peer_addr()
+    }
+
+    pub fn session_info(&self) -> Option<SessionInfo>
+    where
+        T: Network,
+    {
+        self.network.session_info()
+    }
+
     pub async fn disconnect(&mut self) -> Result<(), Box<dyn Error>>
     where
         T: Network,
@@ -205,10 +450,249 @@ impl<T> Client<T> {
                 todo!("MQTT v5 not supported yet")
             }
         };
-        self.network.send(send_buffer.as_ref()).await.unwrap();
+        self.send(send_buffer.as_ref()).await.unwrap();
 
         info!("Disconnected from broker");
 
         Ok(())
     }
 }
+
+/// Check the SUBACK of a single topic filter subscription.
+pub(crate) fn check_sub_ack(sub_req: &Subscribe, sub_ack: &SubAck) -> Result<(), Box<dyn Error>> {
+    if sub_ack.pkid != sub_req.pkid {
+        Err(format!(
+            "Subscribe failed, received different ack < {:} {:} >",
+            sub_req.pkid, sub_ack.pkid
+        ))?
+    }
+    match sub_ack.return_codes.first() {
+        Some(SubscribeReasonCode::Success(_)) => Ok(()),
+        _ => Err(format!(
+            "Subscription to {} refused",
+            sub_req.filters[0].path
+        ))?,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::memory::{MemoryConfig, MockPeer, MockReply};
+    use crate::network::simple_network::SimpleNetwork;
+    use std::time::Duration;
+    use tokio::time::{timeout, Instant};
+
+    /// Retained PUBLISH (QoS 1, packet id 9) delivered before the SUBACK of packet id 1
+    fn retained_then_sub_ack() -> Vec<u8> {
+        let mut bytes = vec![0x33, 15, 0, 9];
+        bytes.extend_from_slice(b"sensors/1");
+        bytes.extend_from_slice(&[0, 9, b'o', b'n']);
+        bytes.extend_from_slice(&[0x90, 3, 0, 1, 1]);
+        bytes
+    }
+
+    fn client(peer: &MockPeer) -> Client<SimpleNetwork> {
+        let transport = Transport::Memory(MemoryConfig {
+            peer: peer.clone(),
+            ..MemoryConfig::default()
+        });
+        Client::new(
+            "localhost".to_string(),
+            "localhost".to_string(),
+            "1883".to_string(),
+            transport,
+            Version::V311,
+        )
+    }
+
+    #[tokio::test]
+    async fn publish_qos1() {
+        let peer = MockPeer::new();
+        let mut client = client(&peer);
+
+        client.connect().await.unwrap();
+        client
+            .publish("test".to_string(), "hello".to_string(), QoS::AtLeastOnce)
+            .await
+            .unwrap();
+
+        let received = peer.received();
+        assert!(matches!(received[0], Packet::Connect(_)));
+        match &received[1] {
+            Packet::Publish(publish) => {
+                assert_eq!(publish.topic, "test");
+                assert_eq!(publish.payload.as_ref(), b"hello");
+                assert_eq!(publish.pkid, 1);
+            }
+            other => panic!("Unexpected packet: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribe() {
+        let peer = MockPeer::new();
+        let mut client = client(&peer);
+
+        client.connect().await.unwrap();
+        client
+            .subscribe("sensors/+".to_string(), QoS::AtLeastOnce)
+            .await
+            .unwrap();
+
+        match &peer.received()[1] {
+            Packet::Subscribe(subscribe) => {
+                assert_eq!(subscribe.filters[0].path, "sensors/+");
+                assert_eq!(subscribe.filters[0].qos, QoS::AtLeastOnce);
+            }
+            other => panic!("Unexpected packet: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn subscription_refused() {
+        // SUBACK with the failure return code
+        let peer =
+            MockPeer::new().script([MockReply::Ack, MockReply::Raw(vec![0x90, 3, 0, 1, 0x80])]);
+        let mut client = client(&peer);
+
+        client.connect().await.unwrap();
+        let err = client
+            .subscribe("#".to_string(), QoS::AtMostOnce)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("refused"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn publish_before_sub_ack() {
+        let peer =
+            MockPeer::new().script([MockReply::Ack, MockReply::Raw(retained_then_sub_ack())]);
+        let mut client = client(&peer);
+
+        client.connect().await.unwrap();
+        client
+            .subscribe("sensors/+".to_string(), QoS::AtLeastOnce)
+            .await
+            .unwrap();
+        let message = client.receive().await.unwrap();
+        assert_eq!(message.publish.topic, "sensors/1");
+        assert!(message.publish.retain);
+        client.disconnect().await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(peer.received().contains(&Packet::PubAck(PubAck::new(9))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn receive_keeps_alive() {
+        let peer = MockPeer::new();
+        let mut client = client(&peer);
+        client.set_keep_alive(5);
+
+        client.connect().await.unwrap();
+        client
+            .subscribe("sensors/+".to_string(), QoS::AtMostOnce)
+            .await
+            .unwrap();
+        assert!(timeout(Duration::from_secs(12), client.receive())
+            .await
+            .is_err());
+
+        let pings = peer
+            .received()
+            .into_iter()
+            .filter(|packet| *packet == Packet::PingReq);
+        assert_eq!(pings.count(), 2);
+    }
+
+    #[tokio::test]
+    async fn ping() {
+        let peer = MockPeer::new().script([
+            MockReply::Ack,
+            MockReply::Delay(Duration::from_millis(20)),
+            MockReply::Raw(vec![0xE0, 0]),
+        ]);
+        let mut client = client(&peer);
+        client.set_keep_alive(30);
+
+        client.connect().await.unwrap();
+        assert!(client.ping().await.unwrap() >= Duration::from_millis(20));
+        // A DISCONNECT instead of the PINGRESP
+        let err = client.ping().await.unwrap_err();
+        assert!(err.to_string().starts_with("Unexpected message"), "{err}");
+
+        let received = peer.received();
+        match &received[0] {
+            Packet::Connect(connect) => assert_eq!(connect.keep_alive, 30),
+            other => panic!("Unexpected packet: {other:?}"),
+        }
+        assert_eq!(received[1], Packet::PingReq);
+    }
+
+    #[tokio::test]
+    async fn connection_refused() {
+        // CONNACK with return code 5 (not authorized)
+        let peer = MockPeer::new().script([MockReply::Raw(vec![0x20, 2, 0, 5])]);
+        let mut client = client(&peer);
+
+        let err = client.connect().await.unwrap_err();
+        assert!(err.to_string().contains("NotAuthorized"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn malformed_ack() {
+        // Reserved packet type 15
+        let peer = MockPeer::new().script([MockReply::Ack, MockReply::Raw(vec![0xF0, 2, 0, 1])]);
+        let mut client = client(&peer);
+
+        client.connect().await.unwrap();
+        let err = client
+            .publish("test".to_string(), "hello".to_string(), QoS::AtLeastOnce)
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().starts_with("Malformed publish ack"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn unexpected_ack() {
+        // PUBACK of another packet id
+        let peer = MockPeer::new().script([MockReply::Ack, MockReply::Raw(vec![0x40, 2, 0, 7])]);
+        let mut client = client(&peer);
+
+        client.connect().await.unwrap();
+        let err = client
+            .publish("test".to_string(), "hello".to_string(), QoS::AtLeastOnce)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("received different ack"), "{err}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn delayed_ack() {
+        let peer =
+            MockPeer::new().script([MockReply::Ack, MockReply::Delay(Duration::from_secs(5))]);
+        let mut client = client(&peer);
+
+        client.connect().await.unwrap();
+        let start = Instant::now();
+        client
+            .publish("test".to_string(), "hello".to_string(), QoS::AtLeastOnce)
+            .await
+            .unwrap();
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dropped_ack() {
+        let peer = MockPeer::new().script([MockReply::Ack, MockReply::Drop]);
+        let mut client = client(&peer);
+
+        client.connect().await.unwrap();
+        let publish = client.publish("test".to_string(), "hello".to_string(), QoS::AtLeastOnce);
+        assert!(timeout(Duration::from_secs(60), publish).await.is_err());
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::network::stats::{PathSampler, TransportStats};
+use crate::utility::summary::LatencyHistogram;
+
+/// Counters of a stream client, updated by the client and its network tasks.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    pub(crate) sent: AtomicU64,
+    pub(crate) acked: AtomicU64,
+    pub(crate) dropped: AtomicU64,
+    pub(crate) replaced: AtomicU64,
+    pub(crate) lost: AtomicU64,
+    pub(crate) connects: AtomicU64,
+    pub(crate) ack_latency: Mutex<LatencyHistogram>,
+    /// Path statistics of the open connection, none once closed
+    pub(crate) sampler: Mutex<Option<PathSampler>>,
+}
+
+/// Live statistics of a stream client, readable from any task while it runs.
+#[derive(Debug, Clone, Default)]
+pub struct ClientMetrics {
+    pub(crate) counters: Arc<Counters>,
+    pub(crate) queued: Arc<AtomicUsize>,
+    pub(crate) pending: Arc<AtomicUsize>,
+}
+
+/// Statistics of a client at one point in time, counted since the client was created.
+#[derive(Debug, Clone, Default)]
+pub struct MetricsSnapshot {
+    /// Messages handed to the transport
+    pub sent: u64,
+    /// PUBACK and PUBREC received
+    pub acked: u64,
+    /// Messages dropped before reaching the client (e.g. skipped by the pacer)
+    pub dropped: u64,
+    /// Messages replaced in the send queue before being sent (LIFO queue)
+    pub replaced: u64,
+    /// Acks and reflected messages given up
+    pub lost: u64,
+    /// Connections after the first one
+    pub reconnects: u64,
+    /// Messages waiting in the send queue
+    pub queued: usize,
+    /// Acks and reflected messages still expected
+    pub in_flight: usize,
+    /// Every ack latency so far (ack latency tracking only)
+    pub ack_latency: LatencyHistogram,
+    /// Smoothed round-trip time of the transport, none when closed or not reported (memory)
+    pub transport_rtt: Option<Duration>,
+}
+
+impl ClientMetrics {
+    /**
+     * Count messages dropped before reaching the client, e.g. skipped by the pacer.
+     */
+    pub fn add_dropped(&self, messages: u64) {
+        self.counters.dropped.fetch_add(messages, Ordering::SeqCst);
+    }
+
+    /**
+     * Current statistics, the transport RTT is sampled now.
+     */
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let counters = &self.counters;
+        // Sampled under the lock, the connection cannot be closed meanwhile
+        let transport_rtt = counters
+            .sampler
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|sampler| sampler.sample().ok())
+            .map(|sample| match sample.stats {
+                TransportStats::Tcp(stats) => stats.rtt,
+                TransportStats::Quic(stats) => stats.rtt,
+            });
+
+        MetricsSnapshot {
+            sent: counters.sent.load(Ordering::SeqCst),
+            acked: counters.acked.load(Ordering::SeqCst),
+            dropped: counters.dropped.load(Ordering::SeqCst),
+            replaced: counters.replaced.load(Ordering::SeqCst),
+            lost: counters.lost.load(Ordering::SeqCst),
+            reconnects: counters.connects.load(Ordering::SeqCst).saturating_sub(1),
+            queued: self.queued.load(Ordering::SeqCst),
+            in_flight: self.pending.load(Ordering::SeqCst),
+            ack_latency: counters.ack_latency.lock().unwrap().clone(),
+            transport_rtt,
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Length of the decimal timestamps (nanoseconds since the Unix epoch) prefixed to payloads.
+pub const TIMESTAMP_LEN: usize = 19;
+
+/// Length of the publisher index and sequence number following the send timestamp in the
+/// payloads of scenarios.
+pub const SEQUENCE_LEN: usize = 20;
+
+/// Current time in nanoseconds since the Unix epoch.
+pub fn timestamp() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_nanos()
+}
+
+/// Send timestamp at the start of a payload (as published, not reflected).
+pub fn sent_timestamp(payload: &[u8]) -> Option<u128> {
+    parse_timestamp(payload.get(..TIMESTAMP_LEN)?)
+}
+
+/// Publisher index and sequence number of a message, in decimal (8 and 12 digits).
+pub fn sequence_header(client: usize, sequence: u64) -> String {
+    format!("{client:08}{sequence:012}")
+}
+
+/// Publisher index and sequence number following the send timestamp of a payload.
+pub fn sequence(payload: &[u8]) -> Option<(usize, u64)> {
+    let header =
+        std::str::from_utf8(payload.get(TIMESTAMP_LEN..TIMESTAMP_LEN + SEQUENCE_LEN)?).ok()?;
+    Some((header[..8].parse().ok()?, header[8..].parse().ok()?))
+}
+
+/// Round trip of a message through the reflector, timestamps in nanoseconds since the Unix epoch.
+///
+/// The reflected payload starts with the reflector receive timestamp, followed by (at least the
+/// beginning of) the original payload, which starts with the send timestamp.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RttSample {
+    pub sent: u128,
+    pub reflected: u128,
+    pub received: u128,
+}
+
+impl RttSample {
+    pub fn parse(payload: &[u8], received: u128) -> Option<RttSample> {
+        let reflected = parse_timestamp(payload.get(..TIMESTAMP_LEN)?)?;
+        let sent = parse_timestamp(payload.get(TIMESTAMP_LEN..2 * TIMESTAMP_LEN)?)?;
+        Some(RttSample {
+            sent,
+            reflected,
+            received,
+        })
+    }
+
+    pub fn rtt(&self) -> Duration {
+        Duration::from_nanos(self.received.saturating_sub(self.sent) as u64)
+    }
+
+    /// Client to reflector delay in nanoseconds, only meaningful with synchronized clocks
+    /// (negative if the reflector clock is behind).
+    pub fn upstream(&self) -> i128 {
+        self.reflected as i128 - self.sent as i128
+    }
+
+    /// Reflector to client delay in nanoseconds, only meaningful with synchronized clocks
+    /// (negative if the reflector clock is ahead).
+    pub fn downstream(&self) -> i128 {
+        self.received as i128 - self.reflected as i128
+    }
+}
+
+fn parse_timestamp(digits: &[u8]) -> Option<u128> {
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reflected_payload() {
+        let payload = b"1700000000000500000170000000000000000012345";
+        let sample = RttSample::parse(payload, 1_700_000_000_001_500_000).unwrap();
+
+        assert_eq!(sample.sent, 1_700_000_000_000_000_000);
+        assert_eq!(sample.reflected, 1_700_000_000_000_500_000);
+        assert_eq!(sample.rtt(), Duration::from_micros(1500));
+        assert_eq!(sample.upstream(), 500_000);
+        assert_eq!(sample.downstream(), 1_000_000);
+    }
+
+    #[test]
+    fn parse_sent_timestamp() {
+        assert_eq!(
+            sent_timestamp(b"1700000000000000000\x7f\x7f"),
+            Some(1_700_000_000_000_000_000)
+        );
+        assert_eq!(sent_timestamp(b"170000000000"), None);
+    }
+
+    #[test]
+    fn parse_sequence() {
+        let payload = format!("1700000000000000000{}\x7f", sequence_header(3, 42));
+        assert_eq!(payload.len(), TIMESTAMP_LEN + SEQUENCE_LEN + 1);
+        assert_eq!(sequence(payload.as_bytes()), Some((3, 42)));
+        assert_eq!(sequence(b"1700000000000000000\x7f\x7f"), None);
+    }
+
+    #[test]
+    fn reject_short_or_invalid_payload() {
+        assert!(RttSample::parse(b"1700000000000500000", 0).is_none());
+        assert!(RttSample::parse(b"17000000000005000001700000000x00000000", 0).is_none());
+    }
+}
@@ -1,24 +1,19 @@
 use mqttbytes::QoS;
 use std::error::Error;
+use std::net::SocketAddr;
+use std::time::Duration;
 
-use crate::client::client::Client;
+use crate::client::client::{Client, Message};
+use crate::network::session::SessionInfo;
 use crate::network::simple_network::SimpleNetwork;
 use crate::network::transport::Transport;
 use crate::Version;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct SimpleMqttClient {
     _client: Client<SimpleNetwork>,
 }
 
-impl Default for SimpleMqttClient {
-    fn default() -> SimpleMqttClient {
-        SimpleMqttClient {
-            _client: Client::default(),
-        }
-    }
-}
-
 impl SimpleMqttClient {
     pub fn new(
         host: String,
@@ -32,6 +27,14 @@ impl SimpleMqttClient {
         }
     }
 
+    pub fn client_id(&self) -> &str {
+        self._client.client_id()
+    }
+
+    pub fn set_keep_alive(&mut self, keep_alive: u16) {
+        self._client.set_keep_alive(keep_alive);
+    }
+
     pub async fn connect(&mut self) -> Result<(), Box<dyn Error>> {
         self._client.connect().await
     }
@@ -45,6 +48,26 @@ impl SimpleMqttClient {
         self._client.publish(topic, payload, qos).await
     }
 
+    pub async fn subscribe(&mut self, filter: String, qos: QoS) -> Result<(), Box<dyn Error>> {
+        self._client.subscribe(filter, qos).await
+    }
+
+    pub async fn receive(&mut self) -> Result<Message, Box<dyn Error>> {
+        self._client.receive().await
+    }
+
+    pub async fn ping(&mut self) -> Result<Duration, Box<dyn Error>> {
+        self._client.ping().await
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self._client.peer_addr()
+    }
+
+    pub fn session_info(&self) -> Option<SessionInfo> {
+        self._client.session_info()
+    }
+
     pub async fn disconnect(&mut self) -> Result<(), Box<dyn Error>> {
         self._client.disconnect().await
     }
@@ -1,32 +1,79 @@
 use bytes::BytesMut;
-use log::debug;
-use mqttbytes::v4::Publish;
+use log::{debug, warn};
+use mqttbytes::v4::{Packet, PingReq, PubAck, PubComp, PubRec, PubRel, Publish, SubAck};
 use mqttbytes::QoS;
+use std::collections::HashMap;
 use std::error::Error;
-use std::sync::atomic::{AtomicU16, Ordering};
-use std::sync::Arc;
-use tokio::task::yield_now;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
 
-use crate::client::client::Client;
+pub use crate::client::client::Message;
+
+use crate::client::client::{check_sub_ack, Client};
+use crate::client::metrics::{ClientMetrics, Counters};
+use crate::client::rtt::{self, RttSample};
 use crate::network::channel_network::ChannelNetwork;
 use crate::network::network::Network;
+use crate::network::session::SessionInfo;
+use crate::network::stats::StatsSample;
 use crate::network::transport::Transport;
 use crate::{parse_packet, Version, ACK_PACKET_SIZE};
 
+/// Longest wait on disconnect for the acks and reflected messages still missing
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+/// Polling interval of the missing acks and reflected messages on disconnect
+const DRAIN_POLL: Duration = Duration::from_millis(1);
+
 #[derive(Debug, Clone)]
 pub struct StreamMqttClient {
     _client: Client<ChannelNetwork>,
-    pending_requests: Arc<AtomicU16>,
+    pending_requests: Arc<AtomicUsize>,
     cancellation_tkn: CancellationToken,
+    stats_interval: Option<Duration>,
+    stats_tx: async_channel::Sender<StatsSample>,
+    stats_rx: async_channel::Receiver<StatsSample>,
+    rtt: bool,
+    rtt_tx: async_channel::Sender<RttSample>,
+    rtt_rx: async_channel::Receiver<RttSample>,
+    subscriptions: Vec<(String, QoS)>,
+    messages_tx: async_channel::Sender<Message>,
+    messages_rx: async_channel::Receiver<Message>,
+    /// Publish time of the messages waiting for an ack (ack latency tracking only)
+    in_flight: Option<Arc<Mutex<HashMap<u16, Instant>>>>,
+    ack_latency_tx: async_channel::Sender<Duration>,
+    ack_latency_rx: async_channel::Receiver<Duration>,
+    /// Live statistics, shared with the network tasks
+    counters: Arc<Counters>,
 }
 
 impl Default for StreamMqttClient {
     fn default() -> StreamMqttClient {
+        let (stats_tx, stats_rx) = async_channel::unbounded();
+        let (rtt_tx, rtt_rx) = async_channel::unbounded();
+        let (messages_tx, messages_rx) = async_channel::unbounded();
+        let (ack_latency_tx, ack_latency_rx) = async_channel::unbounded();
         StreamMqttClient {
             _client: Client::default(),
-            pending_requests: Arc::new(AtomicU16::new(0)),
+            pending_requests: Arc::new(AtomicUsize::new(0)),
             cancellation_tkn: CancellationToken::new(),
+            stats_interval: None,
+            stats_tx,
+            stats_rx,
+            rtt: false,
+            rtt_tx,
+            rtt_rx,
+            subscriptions: Vec::new(),
+            messages_tx,
+            messages_rx,
+            in_flight: None,
+            ack_latency_tx,
+            ack_latency_rx,
+            counters: Arc::default(),
         }
     }
 }
@@ -39,10 +86,27 @@ impl StreamMqttClient {
         transport: Transport,
         version: Version,
     ) -> StreamMqttClient {
+        let (stats_tx, stats_rx) = async_channel::unbounded();
+        let (rtt_tx, rtt_rx) = async_channel::unbounded();
+        let (messages_tx, messages_rx) = async_channel::unbounded();
+        let (ack_latency_tx, ack_latency_rx) = async_channel::unbounded();
         StreamMqttClient {
             _client: Client::new(host, server_name, port, transport, version),
-            pending_requests: Arc::new(AtomicU16::new(0)),
+            pending_requests: Arc::new(AtomicUsize::new(0)),
             cancellation_tkn: CancellationToken::new(),
+            stats_interval: None,
+            stats_tx,
+            stats_rx,
+            rtt: false,
+            rtt_tx,
+            rtt_rx,
+            subscriptions: Vec::new(),
+            messages_tx,
+            messages_rx,
+            in_flight: None,
+            ack_latency_tx,
+            ack_latency_rx,
+            counters: Arc::default(),
         }
     }
 
@@ -51,28 +115,194 @@ impl StreamMqttClient {
      */
     pub async fn connect(&mut self) -> Result<(), Box<dyn Error>> {
         self._client.connect().await?;
+        self.counters.connects.fetch_add(1, Ordering::SeqCst);
+        *self.counters.sampler.lock().unwrap() = self._client.network.sampler();
+
+        // Subscribe once the receiver runs, the broker may deliver messages before the SUBACK
+        let mut subscribes = Vec::new();
+        let mut sub_acks: HashMap<u16, oneshot::Sender<SubAck>> = HashMap::new();
+        for (filter, qos) in &self.subscriptions {
+            let sub_req = self._client.subscribe_packet(filter, *qos);
+            let (sub_ack_tx, sub_ack_rx) = oneshot::channel();
+            sub_acks.insert(sub_req.pkid, sub_ack_tx);
+            subscribes.push((sub_req, sub_ack_rx));
+        }
 
         let pending_requests = self.pending_requests.clone();
         let mut recv_network = self._client.network.clone();
         let version = self._client.version;
         let cancellation_tkn = self.cancellation_tkn.clone();
+        let rtt_tx = self.rtt.then(|| self.rtt_tx.clone());
+        let messages_tx = self.messages_tx.clone();
+        let keep_alive = Duration::from_secs(self._client.keep_alive() as u64);
+        let in_flight = self.in_flight.clone();
+        let ack_latency_tx = self.ack_latency_tx.clone();
+        let counters = self.counters.clone();
 
-        // Spawn receiver task (for acks)
+        // Spawn receiver task (for acks, reflected and subscribed messages and keep-alive)
         tokio::spawn(async move {
+            // A PINGREQ every keep-alive, the broker would close a connection idle for too long
+            let mut ping_timer = tokio::time::interval_at(Instant::now() + keep_alive, keep_alive);
             'rx_loop: loop {
-                tokio::select! {
+                let recv_buffer = tokio::select! {
                     _ = cancellation_tkn.cancelled() => {
                         break 'rx_loop;
                     },
+                    _ = ping_timer.tick(), if !keep_alive.is_zero() => None,
+                    // The error is not Send, it must not live across the awaits below
                     recv_buffer = recv_network.recv(ACK_PACKET_SIZE) => {
-                        let packet = parse_packet(&mut recv_buffer.unwrap(), 1024, &version).unwrap();
+                        Some(recv_buffer.map_err(|e| e.to_string()))
+                    }
+                };
+                let Some(recv_buffer) = recv_buffer else {
+                    let mut ping = BytesMut::new();
+                    PingReq
+                        .write(&mut ping)
+                        .expect("Packet serialization failed");
+                    if recv_network.send(&ping).await.is_err() {
+                        debug!("Failed to send keep-alive");
+                    }
+                    continue;
+                };
+                let received = rtt::timestamp();
+                let packet = match recv_buffer
+                    .map(|mut buffer| parse_packet(&mut buffer, usize::MAX, &version))
+                {
+                    Ok(Ok(packet)) => packet,
+                    Ok(Err(e)) => {
+                        warn!("Malformed packet received: {e}");
+                        continue;
+                    }
+                    Err(e) => {
+                        debug!("Receiver stopped: {e}");
+                        // The connection is lost, no more messages on the subscriptions
+                        messages_tx.close();
+                        break 'rx_loop;
+                    }
+                };
+                match packet {
+                    Packet::PubAck(PubAck { pkid }) | Packet::PubRec(PubRec { pkid }) => {
                         debug!("Received ack: {:?}", packet);
-                        pending_requests.fetch_sub(1, Ordering::SeqCst);
+                        if matches!(packet, Packet::PubRec(_)) {
+                            // QoS 2 goes on with PUBREL, pending until the PUBCOMP
+                            let mut rel = BytesMut::new();
+                            PubRel::new(pkid)
+                                .write(&mut rel)
+                                .expect("Packet serialization failed");
+                            if recv_network.send(&rel).await.is_err() {
+                                debug!("Failed to release message {pkid}");
+                            }
+                        } else {
+                            release(&pending_requests);
+                        }
+                        counters.acked.fetch_add(1, Ordering::SeqCst);
+                        // The ack latency ends at the PUBACK (QoS 1) or PUBREC (QoS 2)
+                        let published = in_flight
+                            .as_ref()
+                            .and_then(|in_flight| in_flight.lock().unwrap().remove(&pkid));
+                        if let Some(published) = published {
+                            let latency = published.elapsed();
+                            counters.ack_latency.lock().unwrap().record(latency);
+                            // Unbounded, only fails once closed on disconnect
+                            let _ = ack_latency_tx.try_send(latency);
+                        }
+                    }
+                    Packet::Publish(publish) => {
+                        // Ack deliveries above QoS 0 (subscriptions)
+                        let mut ack = BytesMut::new();
+                        match publish.qos {
+                            QoS::AtMostOnce => Ok(0),
+                            QoS::AtLeastOnce => PubAck::new(publish.pkid).write(&mut ack),
+                            QoS::ExactlyOnce => PubRec::new(publish.pkid).write(&mut ack),
+                        }
+                        .expect("Packet serialization failed");
+                        if !ack.is_empty() && recv_network.send(&ack).await.is_err() {
+                            debug!("Failed to ack message {}", publish.pkid);
+                        }
+
+                        match rtt_tx {
+                            Some(ref rtt_tx) => {
+                                match RttSample::parse(&publish.payload, received) {
+                                    Some(sample) => {
+                                        // Unbounded, only fails once closed on disconnect
+                                        let _ = rtt_tx.try_send(sample);
+                                    }
+                                    None => warn!("Reflected message without timestamps"),
+                                }
+                                release(&pending_requests);
+                            }
+                            None => {
+                                debug!("Received message on {}", publish.topic);
+                                // Unbounded, only fails once closed on disconnect
+                                let _ = messages_tx.try_send(Message { publish, received });
+                            }
+                        }
                     }
+                    Packet::PubComp(pub_comp) => {
+                        debug!("Received completion: {:?}", pub_comp);
+                        release(&pending_requests);
+                    }
+                    Packet::PubRel(pub_rel) => {
+                        let mut comp = BytesMut::new();
+                        PubComp::new(pub_rel.pkid)
+                            .write(&mut comp)
+                            .expect("Packet serialization failed");
+                        if recv_network.send(&comp).await.is_err() {
+                            debug!("Failed to complete message {}", pub_rel.pkid);
+                        }
+                    }
+                    Packet::SubAck(sub_ack) => match sub_acks.remove(&sub_ack.pkid) {
+                        Some(sub_ack_tx) => {
+                            let _ = sub_ack_tx.send(sub_ack);
+                        }
+                        None => debug!("Unexpected subscribe ack: {:?}", sub_ack),
+                    },
+                    other => debug!("Received: {:?}", other),
                 }
             }
         });
 
+        // Spawn transport statistics sampler task (if supported by the transport)
+        if let (Some(sampler), Some(interval)) =
+            (self._client.network.sampler(), self.stats_interval)
+        {
+            let stats_tx = self.stats_tx.clone();
+            let cancellation_tkn = self.cancellation_tkn.clone();
+            tokio::spawn(async move {
+                let mut interval_timer = tokio::time::interval(interval);
+                'stats_loop: loop {
+                    tokio::select! {
+                        _ = cancellation_tkn.cancelled() => {
+                            break 'stats_loop;
+                        },
+                        _ = interval_timer.tick() => {
+                            match sampler.sample() {
+                                Ok(sample) => {
+                                    if stats_tx.send(sample).await.is_err() {
+                                        break 'stats_loop;
+                                    }
+                                }
+                                Err(e) => debug!("Failed to sample transport statistics: {e}"),
+                            }
+                        }
+                    }
+                }
+            });
+        }
+
+        for (sub_req, sub_ack_rx) in subscribes {
+            let mut send_buffer = BytesMut::new();
+            sub_req
+                .write(&mut send_buffer)
+                .expect("Packet serialization failed");
+            self._client.network.send(&send_buffer).await?;
+            let sub_ack = sub_ack_rx
+                .await
+                .map_err(|_| "Connection closed before the subscribe ack")?;
+            debug!("Subscribe ack: {sub_ack:?}");
+            check_sub_ack(&sub_req, &sub_ack)?;
+        }
+
         Ok(())
     }
 
@@ -88,16 +318,48 @@ impl StreamMqttClient {
         self._client.publish(topic, payload, qos).await
     }
 
+    /**
+     * Broker address actually used by the connection.
+     */
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self._client.peer_addr()
+    }
+
+    /**
+     * TLS session negotiated with the broker (version, cipher suite, ALPN, SNI and certificate
+     * chain), none for plain TCP.
+     */
+    pub fn session_info(&self) -> Option<SessionInfo> {
+        self._client.session_info()
+    }
+
     /**
      * Disconnect from broker and stop network tasks. The client cannot be reused after this call.
      */
     pub async fn disconnect(&mut self) -> Result<(), Box<dyn Error>> {
-        while self.pending_requests.load(Ordering::SeqCst) > 0 {
-            yield_now().await;
+        // Give up on the acks and reflected messages lost on the way (e.g. without reflector)
+        let deadline = Instant::now() + DRAIN_TIMEOUT;
+        while self.pending_requests.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+            tokio::time::sleep(DRAIN_POLL).await;
+        }
+        let abandoned = self.abandon_pending();
+        if abandoned > 0 {
+            warn!("{abandoned} acks or reflected messages missing after {DRAIN_TIMEOUT:?}");
         }
         debug!("{:?}", self.pending_requests);
+
+        // Take a last transport statistics sample before closing the connection
+        if let Some(Ok(sample)) = self._client.network.sampler().map(|s| s.sample()) {
+            let _ = self.stats_tx.send(sample).await;
+        }
+        self.counters.sampler.lock().unwrap().take();
+
         self._client.disconnect().await?;
         self.cancellation_tkn.cancel();
+        self.stats_tx.close();
+        self.rtt_tx.close();
+        self.messages_tx.close();
+        self.ack_latency_tx.close();
         self._client.network.close().await
     }
 
@@ -126,22 +388,478 @@ impl StreamMqttClient {
             .write(&mut send_buffer)
             .expect("Packet serialization failed");
 
+        // The ack latency includes the time spent in the send queue
+        let track = self.in_flight.as_ref().filter(|_| pub_req.pkid != 0);
+        if let Some(in_flight) = track {
+            in_flight
+                .lock()
+                .unwrap()
+                .insert(pub_req.pkid, Instant::now());
+        }
+
+        // On error the message has been dropped (LIFO queue), nothing to track
         let res = network.send(send_buffer.as_ref()).await;
-        match res {
-            Ok(_) => {
-                if qos != QoS::AtMostOnce {
-                    self.pending_requests.fetch_add(1, Ordering::SeqCst);
-                }
-            }
-            _ => (), // Do nothing, the message has been dropped (LIFO queue)
+        if let (Err(_), Some(in_flight)) = (&res, track) {
+            in_flight.lock().unwrap().remove(&pub_req.pkid);
+        }
+        if res.is_err() {
+            self.counters.replaced.fetch_add(1, Ordering::SeqCst);
+        }
+        if res.is_ok() {
+            self.counters.sent.fetch_add(1, Ordering::SeqCst);
+            // Wait for the ack (if any) and the reflected message (in RTT mode) on disconnect
+            let expected = (qos != QoS::AtMostOnce) as usize + self.rtt as usize;
+            self.pending_requests.fetch_add(expected, Ordering::SeqCst);
         }
         Ok(())
     }
 
+    /**
+     * Client id sent on connect.
+     */
+    pub fn client_id(&self) -> &str {
+        self._client.client_id()
+    }
+
+    /**
+     * Replace the generated client id, e.g. to tell clients apart on the broker. Set before
+     * connecting.
+     */
+    pub fn set_client_id(&mut self, client_id: String) {
+        self._client.set_client_id(client_id);
+    }
+
+    /**
+     * Longest time between two keep-alive packets, in seconds (0 disables the keep-alive). Set
+     * before connecting.
+     */
+    pub fn set_keep_alive(&mut self, keep_alive: u16) {
+        self._client.set_keep_alive(keep_alive);
+    }
+
     /**
      * Set the queue size for the network.
      */
     pub fn set_queue(&mut self, queue: i64) {
         self._client.network.set_queue(queue);
     }
+
+    /**
+     * Set the sampling interval of transport statistics. A last sample is always taken on
+     * disconnect.
+     */
+    pub fn set_stats_interval(&mut self, interval: Duration) {
+        self.stats_interval = Some(interval);
+    }
+
+    /**
+     * Measure round-trip times against the reflector (raw-mqtt-broker reflect): every message
+     * sent is expected to come back stamped with the reflector receive time. Payloads must
+     * start with the send timestamp (see `rtt::TIMESTAMP_LEN`). Set before connecting.
+     */
+    pub fn set_rtt(&mut self, rtt: bool) {
+        self.rtt = rtt;
+    }
+
+    /**
+     * Receiver of round-trip time samples (RTT mode only). The channel is closed on disconnect.
+     */
+    pub fn rtt_samples(&self) -> async_channel::Receiver<RttSample> {
+        self.rtt_rx.clone()
+    }
+
+    /**
+     * Measure the latency of every QoS 1 and 2 message, from publishing to its PUBACK (QoS 1)
+     * or PUBREC (QoS 2, the PUBREL and PUBCOMP exchange is left out). Set before connecting.
+     */
+    pub fn set_ack_latency(&mut self, ack_latency: bool) {
+        self.in_flight = ack_latency.then(Arc::default);
+    }
+
+    /**
+     * Receiver of ack latencies (ack latency tracking only). The channel is closed on
+     * disconnect.
+     */
+    pub fn ack_latencies(&self) -> async_channel::Receiver<Duration> {
+        self.ack_latency_rx.clone()
+    }
+
+    /**
+     * Messages waiting in the send queue, growing when the transport cannot keep up.
+     */
+    pub fn queued(&self) -> usize {
+        self._client.network.queued()
+    }
+
+    /**
+     * Acks and reflected messages still expected.
+     */
+    pub fn pending(&self) -> usize {
+        self.pending_requests.load(Ordering::SeqCst)
+    }
+
+    /**
+     * Stop waiting for the acks and reflected messages still pending (e.g. lost by the broker)
+     * so that `disconnect` returns right away, returning how many were given up. Late ones are
+     * ignored.
+     */
+    pub fn abandon_pending(&self) -> usize {
+        let abandoned = self.pending_requests.swap(0, Ordering::SeqCst);
+        self.counters
+            .lost
+            .fetch_add(abandoned as u64, Ordering::SeqCst);
+        abandoned
+    }
+
+    /**
+     * Messages replaced in the send queue before being sent (LIFO queue).
+     */
+    pub fn replaced(&self) -> usize {
+        self.counters.replaced.load(Ordering::SeqCst) as usize
+    }
+
+    /**
+     * Live statistics of the client (counters, queue depth, in-flight count, ack latencies and
+     * transport RTT), readable from any task while it runs, e.g. by a metrics exporter. The ack
+     * latency histogram requires ack latency tracking.
+     */
+    pub fn metrics(&self) -> ClientMetrics {
+        ClientMetrics {
+            counters: self.counters.clone(),
+            queued: self._client.network.queue_counter(),
+            pending: self.pending_requests.clone(),
+        }
+    }
+
+    /**
+     * Subscribe to a topic filter on connect, received messages are delivered by `messages()`
+     * (and acked). Set before connecting.
+     */
+    pub fn subscribe(&mut self, filter: String, qos: QoS) {
+        self.subscriptions.push((filter, qos));
+    }
+
+    /**
+     * Receiver of the messages received on subscriptions. The channel is closed on disconnect,
+     * or once the connection is lost.
+     */
+    pub fn messages(&self) -> async_channel::Receiver<Message> {
+        self.messages_rx.clone()
+    }
+
+    /**
+     * Receiver of transport statistics samples. The channel is closed on disconnect.
+     */
+    pub fn stats(&self) -> async_channel::Receiver<StatsSample> {
+        self.stats_rx.clone()
+    }
+}
+
+/// Count an expected ack or reflected message in, unless it was abandoned.
+fn release(pending_requests: &AtomicUsize) {
+    let _ = pending_requests.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+        pending.checked_sub(1)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::memory::{MemoryConfig, MockPeer, MockReply};
+    use mqttbytes::v4::Packet;
+
+    fn client(peer: &MockPeer, queue: i64) -> StreamMqttClient {
+        let transport = Transport::Memory(MemoryConfig {
+            peer: peer.clone(),
+            ..MemoryConfig::default()
+        });
+        let mut client = StreamMqttClient::new(
+            "localhost".to_string(),
+            "localhost".to_string(),
+            "1883".to_string(),
+            transport,
+            Version::V311,
+        );
+        client.set_queue(queue);
+        client
+    }
+
+    fn published(peer: &MockPeer) -> Vec<u16> {
+        peer.received()
+            .into_iter()
+            .filter_map(|packet| match packet {
+                Packet::Publish(publish) => Some(publish.pkid),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn payloads(peer: &MockPeer) -> Vec<String> {
+        peer.received()
+            .into_iter()
+            .filter_map(|packet| match packet {
+                Packet::Publish(publish) => {
+                    Some(String::from_utf8_lossy(&publish.payload).to_string())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Wait for `payload` to reach the peer, the send queue is not flushed on disconnect.
+    async fn wait_sent(peer: &MockPeer, payload: &str) {
+        let sent = async {
+            while payloads(peer).last().map(String::as_str) != Some(payload) {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), sent)
+            .await
+            .expect("Message not sent");
+    }
+
+    #[tokio::test]
+    async fn stream_publish_waits_for_acks() {
+        // Late acks must be awaited on disconnect
+        let peer = MockPeer::new().script([
+            MockReply::Ack,
+            MockReply::Delay(Duration::from_millis(20)),
+            MockReply::Delay(Duration::from_millis(20)),
+        ]);
+        let mut client = client(&peer, -1);
+
+        client.connect().await.unwrap();
+        for _ in 0..10 {
+            client
+                .stream_publish("test".to_string(), "hello".to_string(), QoS::AtLeastOnce)
+                .await
+                .unwrap();
+        }
+        client.disconnect().await.unwrap();
+
+        assert_eq!(published(&peer), (1..=10).collect::<Vec<u16>>());
+        assert_eq!(client.pending_requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rtt_without_reflector() {
+        // Acked but never reflected, disconnect gives up after the drain timeout
+        let peer = MockPeer::new();
+        let mut client = client(&peer, -1);
+        client.set_rtt(true);
+
+        client.connect().await.unwrap();
+        client
+            .stream_publish("test".to_string(), "hello".to_string(), QoS::AtLeastOnce)
+            .await
+            .unwrap();
+        let started = Instant::now();
+        client.disconnect().await.unwrap();
+
+        assert!(started.elapsed() >= DRAIN_TIMEOUT);
+        assert_eq!(client.pending_requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keep_alive_pings() {
+        let peer = MockPeer::new();
+        let mut client = client(&peer, -1);
+        client.set_keep_alive(10);
+
+        client.connect().await.unwrap();
+        tokio::time::sleep(Duration::from_secs(25)).await;
+        client.disconnect().await.unwrap();
+
+        let received = peer.received();
+        assert!(matches!(&received[0], Packet::Connect(connect) if connect.keep_alive == 10));
+        let pings = received
+            .iter()
+            .filter(|packet| matches!(packet, Packet::PingReq))
+            .count();
+        assert_eq!(pings, 2);
+    }
+
+    #[tokio::test]
+    async fn publish_before_sub_ack() {
+        // Retained PUBLISH (QoS 1, packet id 9), then the SUBACK
+        let mut reply = vec![0x33, 15, 0, 9];
+        reply.extend_from_slice(b"sensors/1");
+        reply.extend_from_slice(&[0, 9, b'o', b'n', 0x90, 3, 0, 1, 1]);
+        let peer = MockPeer::new().script([MockReply::Ack, MockReply::Raw(reply)]);
+        let mut client = client(&peer, -1);
+        client.subscribe("sensors/+".to_string(), QoS::AtLeastOnce);
+        let messages = client.messages();
+
+        client.connect().await.unwrap();
+        let message = messages.recv().await.unwrap();
+        assert_eq!(message.publish.topic, "sensors/1");
+        assert!(message.publish.retain);
+        client.disconnect().await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(peer.received().contains(&Packet::PubAck(PubAck::new(9))));
+    }
+
+    #[tokio::test]
+    async fn subscription_refused() {
+        let peer =
+            MockPeer::new().script([MockReply::Ack, MockReply::Raw(vec![0x90, 3, 0, 1, 0x80])]);
+        let mut client = client(&peer, -1);
+        client.subscribe("#".to_string(), QoS::AtMostOnce);
+
+        let err = client.connect().await.unwrap_err();
+        assert!(err.to_string().contains("refused"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn abandon_lost_acks() {
+        // CONNACK, then one ack lost and one late
+        let peer = MockPeer::new().script([
+            MockReply::Ack,
+            MockReply::Ack,
+            MockReply::Drop,
+            MockReply::Delay(Duration::from_millis(50)),
+        ]);
+        let mut client = client(&peer, -1);
+
+        client.connect().await.unwrap();
+        for _ in 0..3 {
+            client
+                .stream_publish("test".to_string(), "hello".to_string(), QoS::AtLeastOnce)
+                .await
+                .unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(client.abandon_pending(), 2);
+
+        // The late ack must not wrap the counter around
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(client.pending(), 0);
+        client.disconnect().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ack_latencies() {
+        let peer = MockPeer::new().script([
+            MockReply::Ack,
+            MockReply::Delay(Duration::from_millis(20)),
+            MockReply::Ack,
+        ]);
+        let mut client = client(&peer, -1);
+        client.set_ack_latency(true);
+        let latencies = client.ack_latencies();
+
+        client.connect().await.unwrap();
+        for qos in [QoS::AtLeastOnce, QoS::AtMostOnce, QoS::ExactlyOnce] {
+            client
+                .stream_publish("test".to_string(), "hello".to_string(), qos)
+                .await
+                .unwrap();
+        }
+
+        // No ack for QoS 0
+        assert!(latencies.recv().await.unwrap() >= Duration::from_millis(20));
+        assert!(latencies.recv().await.is_ok());
+        assert_eq!(client.queued(), 0);
+        client.disconnect().await.unwrap();
+        assert!(latencies.recv().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn exactly_once_completes() {
+        // CONNACK, then a late PUBREC and a later PUBCOMP
+        let peer = MockPeer::new().script([
+            MockReply::Ack,
+            MockReply::Delay(Duration::from_millis(20)),
+            MockReply::Delay(Duration::from_millis(50)),
+        ]);
+        let mut client = client(&peer, -1);
+        client.set_ack_latency(true);
+        let latencies = client.ack_latencies();
+
+        client.connect().await.unwrap();
+        client
+            .stream_publish("test".to_string(), "hello".to_string(), QoS::ExactlyOnce)
+            .await
+            .unwrap();
+
+        // Measured at the PUBREC, still pending until the PUBCOMP
+        assert!(latencies.recv().await.unwrap() >= Duration::from_millis(20));
+        assert_eq!(client.pending(), 1);
+        let completed = async {
+            while client.pending() > 0 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), completed)
+            .await
+            .expect("PUBCOMP not awaited");
+        client.disconnect().await.unwrap();
+
+        assert!(peer.received().contains(&Packet::PubRel(PubRel::new(1))));
+        assert_eq!(client.metrics().snapshot().lost, 0);
+    }
+
+    #[tokio::test]
+    async fn metrics() {
+        // CONNACK, then one ack lost
+        let peer = MockPeer::new().script([
+            MockReply::Ack,
+            MockReply::Ack,
+            MockReply::Ack,
+            MockReply::Drop,
+        ]);
+        let mut client = client(&peer, -1);
+        client.set_ack_latency(true);
+        let metrics = client.metrics();
+
+        client.connect().await.unwrap();
+        for _ in 0..3 {
+            client
+                .stream_publish("test".to_string(), "hello".to_string(), QoS::AtLeastOnce)
+                .await
+                .unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.sent, snapshot.acked), (3, 2));
+        assert_eq!((snapshot.in_flight, snapshot.queued), (1, 0));
+        assert_eq!(snapshot.ack_latency.len(), 2);
+
+        client.abandon_pending();
+        metrics.add_dropped(5);
+        client.disconnect().await.unwrap();
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.lost, snapshot.dropped), (1, 5));
+        assert_eq!((snapshot.in_flight, snapshot.reconnects), (0, 0));
+        assert!(snapshot.transport_rtt.is_none());
+    }
+
+    #[tokio::test]
+    async fn stream_publish_qos0() {
+        for queue in [-1, 0, 1, 1024] {
+            let peer = MockPeer::new();
+            let mut client = client(&peer, queue);
+
+            client.connect().await.unwrap();
+            for i in 0..10 {
+                client
+                    .stream_publish("test".to_string(), i.to_string(), QoS::AtMostOnce)
+                    .await
+                    .unwrap();
+            }
+            wait_sent(&peer, "9").await;
+            client.disconnect().await.unwrap();
+
+            let payloads = payloads(&peer);
+            if queue == 0 {
+                // LIFO, older messages are replaced while the latest one waits to be sent
+                assert!(!payloads.is_empty() && payloads.len() <= 10);
+            } else {
+                let expected: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+                assert_eq!(payloads, expected, "queue {queue}");
+            }
+            assert!(published(&peer).iter().all(|pkid| *pkid == 0));
+        }
+    }
 }
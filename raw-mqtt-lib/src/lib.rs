@@ -12,7 +12,8 @@ pub mod utility;
 
 pub const ACK_PACKET_SIZE: usize = 4;
 
-pub(crate) fn parse_packet(
+/// Parse the next MQTT packet from the stream, `InsufficientBytes` if the frame is incomplete.
+pub fn parse_packet(
     stream: &mut BytesMut,
     max_size: usize,
     version: &Version,
@@ -27,7 +28,7 @@ pub(crate) fn parse_packet(
             let packet = stream.split_to(fixed_header.frame_length());
             let packet_type = fixed_header.packet_type()?;
 
-            return match packet_type {
+            match packet_type {
                 PacketType::PingReq => Ok(Packet::PingReq),
                 PacketType::PingResp => Ok(Packet::PingResp),
                 PacketType::Disconnect => Ok(Packet::Disconnect),
@@ -66,7 +67,7 @@ pub(crate) fn parse_packet(
 
                     Ok(packet)
                 }
-            };
+            }
         }
         Version::V5 => {
             todo!("V3.1.1 not supported yet");
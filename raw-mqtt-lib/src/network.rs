@@ -1,6 +1,13 @@
 mod channel;
 pub(crate) mod channel_network;
+pub mod key_log;
+pub mod memory;
+#[allow(clippy::module_inception)]
 pub(crate) mod network;
-mod server_verification;
+pub mod proxy;
+pub mod server_verification;
+pub mod session;
 pub(crate) mod simple_network;
+pub mod socket;
+pub mod stats;
 pub mod transport;
@@ -136,3 +136,85 @@ impl UnboundedReceiver {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn buffer(content: &str) -> BytesMut {
+        BytesMut::from(content.as_bytes())
+    }
+
+    #[tokio::test]
+    async fn lifo_keeps_latest() {
+        let lifo = SingleLifoQueue::new();
+        let mut sender = ChannelSender::Lifo(lifo.clone());
+        let mut receiver = ChannelReceiver::Lifo(lifo);
+
+        assert!(matches!(sender.send(buffer("a")).await, Ok(Added)));
+        assert!(matches!(sender.send(buffer("b")).await, Ok(Replaced)));
+        assert_eq!(receiver.receive().await.unwrap(), buffer("b"));
+
+        // Emptied after receive
+        assert!(matches!(sender.send(buffer("c")).await, Ok(Added)));
+        assert_eq!(receiver.receive().await.unwrap(), buffer("c"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lifo_receive_waits() {
+        let lifo = SingleLifoQueue::new();
+        let mut receiver = ChannelReceiver::Lifo(lifo.clone());
+
+        let receive = tokio::time::timeout(Duration::from_secs(1), receiver.receive());
+        assert!(receive.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn bounded_fifo_order() {
+        let (tx, rx) = tokio::sync::mpsc::channel(2);
+        let mut sender = ChannelSender::Bounded(BoundedSender::new(tx));
+        let mut receiver = ChannelReceiver::Bounded(BoundedReceiver::new(rx));
+
+        let producer = tokio::spawn(async move {
+            for content in ["a", "b", "c", "d"] {
+                assert!(matches!(sender.send(buffer(content)).await, Ok(Added)));
+            }
+        });
+
+        for content in ["a", "b", "c", "d"] {
+            assert_eq!(receiver.receive().await.unwrap(), buffer(content));
+        }
+        producer.await.unwrap();
+
+        // Closed once the sender is dropped
+        assert!(receiver.receive().await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bounded_send_waits_when_full() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let mut sender = ChannelSender::Bounded(BoundedSender::new(tx));
+
+        assert!(matches!(sender.send(buffer("a")).await, Ok(Added)));
+        let send = tokio::time::timeout(Duration::from_secs(1), sender.send(buffer("b")));
+        assert!(send.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn unbounded_fifo_order() {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut sender = ChannelSender::Unbounded(UnboundedSender::new(tx));
+        let mut receiver = ChannelReceiver::Unbounded(UnboundedReceiver::new(rx));
+
+        for content in ["a", "b", "c"] {
+            assert!(matches!(sender.send(buffer(content)).await, Ok(Added)));
+        }
+        drop(sender);
+
+        for content in ["a", "b", "c"] {
+            assert_eq!(receiver.receive().await.unwrap(), buffer(content));
+        }
+        assert!(receiver.receive().await.is_err());
+    }
+}
@@ -4,16 +4,26 @@ use crate::network::channel::{
 };
 use async_trait::async_trait;
 use bytes::BytesMut;
+use log::warn;
+use mqttbytes::check;
 use std::error::Error;
 use std::fmt::Debug;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
 
+use crate::network::memory::Memory;
 use crate::network::network::Network;
+use crate::network::session::SessionInfo;
+use crate::network::stats::PathSampler;
 use crate::network::transport::{Quic, Tcp, Tls, Transport};
 
 const DEFAULT_QUEUE: i64 = 1024;
+/// Largest remaining length allowed by MQTT 3.1.1
+const MAX_PACKET_SIZE: usize = 268_435_455;
 
 #[derive(Debug, Clone)]
 pub struct ChannelNetwork {
@@ -22,13 +32,19 @@ pub struct ChannelNetwork {
     transport: Transport,
     queue: i64,
     to_sender: Option<ChannelSender>,
+    /// Messages waiting in the send queue
+    queued: Arc<AtomicUsize>,
     from_receiver: Option<async_channel::Receiver<BytesMut>>,
+    sampler: Option<PathSampler>,
+    peer_addr: Option<SocketAddr>,
+    session: Option<SessionInfo>,
 }
 
 fn spawn_sender(
     tracker: TaskTracker,
     token: CancellationToken,
     mut from_producer: ChannelReceiver,
+    queued: Arc<AtomicUsize>,
     mut tx_stream: impl AsyncWriteExt + Unpin + Send + 'static,
 ) {
     tracker.spawn(async move {
@@ -38,6 +54,7 @@ fn spawn_sender(
                     break 'main;
                 },
                 Ok(buf) = from_producer.receive() => {
+                    queued.fetch_sub(1, Ordering::SeqCst);
                     tx_stream.write_all(&buf).await.unwrap();
                 }
             }
@@ -52,14 +69,38 @@ fn spawn_receiver(
     mut rx_stream: impl AsyncReadExt + Unpin + Send + 'static,
 ) {
     tracker.spawn(async move {
-        let mut buffer = vec![0_u8; 4];
+        let mut buffer = BytesMut::new();
         'main: loop {
+            // Forward every complete MQTT frame
+            loop {
+                match check(buffer.iter(), MAX_PACKET_SIZE) {
+                    Ok(header) => {
+                        let frame = buffer.split_to(header.frame_length());
+                        if to_consumer.send(frame).await.is_err() {
+                            break 'main;
+                        }
+                    }
+                    Err(mqttbytes::Error::InsufficientBytes(_)) => break,
+                    Err(e) => {
+                        warn!("Malformed packet received: {e}");
+                        break 'main;
+                    }
+                }
+            }
+
             tokio::select! {
                 _ = token.cancelled() => {
                     break 'main;
                 },
-                _ = rx_stream.read_exact(&mut buffer) => {
-                    to_consumer.send(BytesMut::from(buffer.as_slice())).await.unwrap();
+                res = rx_stream.read_buf(&mut buffer) => {
+                    match res {
+                        Ok(0) => break 'main,
+                        Ok(_) => {}
+                        Err(e) => {
+                            warn!("Failed to receive: {e}");
+                            break 'main;
+                        }
+                    }
                 }
             }
         }
@@ -78,15 +119,19 @@ impl Network for ChannelNetwork {
             transport,
             queue: DEFAULT_QUEUE,
             to_sender: None,
+            queued: Arc::new(AtomicUsize::new(0)),
             from_receiver: None,
+            sampler: None,
+            peer_addr: None,
+            session: None,
         }
     }
 
     async fn connect(
         &mut self,
-        host: &String,
-        port: &String,
-        server_name: &String,
+        host: &str,
+        port: &str,
+        server_name: &str,
     ) -> Result<(), Box<dyn Error>> {
         let from_producer: ChannelReceiver;
         let to_sender: ChannelSender;
@@ -114,15 +159,21 @@ impl Network for ChannelNetwork {
             (to_consumer, from_receiver) = async_channel::unbounded();
         }
 
-        match self.transport {
+        match &self.transport {
             Transport::TCP(config) => {
-                let tcp = Tcp::new(host, port, config.nagle).await?;
+                let tcp = Tcp::new(host, port, config).await?;
+                self.peer_addr = Some(tcp.peer_addr);
+                #[cfg(target_os = "linux")]
+                {
+                    self.sampler = Some(tcp.sampler);
+                }
 
                 // Sender task
                 spawn_sender(
                     self.tracker.clone(),
                     self.cancellation_token.clone(),
                     from_producer,
+                    self.queued.clone(),
                     tcp.tx_stream,
                 );
 
@@ -135,13 +186,20 @@ impl Network for ChannelNetwork {
                 );
             }
             Transport::TLS(config) => {
-                let tls = Tls::new(host, port, config.nagle, config.insecure, server_name).await?;
+                let tls = Tls::new(host, port, config, server_name).await?;
+                self.peer_addr = Some(tls.peer_addr);
+                self.session = Some(tls.session);
+                #[cfg(target_os = "linux")]
+                {
+                    self.sampler = Some(tls.sampler);
+                }
 
                 // Sender task
                 spawn_sender(
                     self.tracker.clone(),
                     self.cancellation_token.clone(),
                     from_producer,
+                    self.queued.clone(),
                     tls.tx_stream,
                 );
 
@@ -154,13 +212,17 @@ impl Network for ChannelNetwork {
                 );
             }
             Transport::QUIC(config) => {
-                let quic = Quic::new(host, port, config.insecure, &server_name).await?;
+                let quic = Quic::new(host, port, config, server_name).await?;
+                self.peer_addr = Some(quic.peer_addr);
+                self.session = Some(quic.session);
+                self.sampler = Some(PathSampler::Quic(quic.connection));
 
                 // Sender task
                 spawn_sender(
                     self.tracker.clone(),
                     self.cancellation_token.clone(),
                     from_producer,
+                    self.queued.clone(),
                     quic.tx_stream,
                 );
 
@@ -172,6 +234,26 @@ impl Network for ChannelNetwork {
                     quic.rx_stream,
                 );
             }
+            Transport::Memory(config) => {
+                let memory = Memory::new(config);
+
+                // Sender task
+                spawn_sender(
+                    self.tracker.clone(),
+                    self.cancellation_token.clone(),
+                    from_producer,
+                    self.queued.clone(),
+                    memory.tx_stream,
+                );
+
+                // Receiver task
+                spawn_receiver(
+                    self.tracker.clone(),
+                    self.cancellation_token.clone(),
+                    to_consumer,
+                    memory.rx_stream,
+                );
+            }
         };
 
         self.from_receiver = Some(from_receiver);
@@ -183,7 +265,12 @@ impl Network for ChannelNetwork {
     async fn send(&mut self, tx_buffer: &[u8]) -> Result<(), Box<dyn Error>> {
         match self.to_sender {
             Some(ref mut tx_stream) => {
+                // Counted before sending, the sender task may dequeue it right away
+                self.queued.fetch_add(1, Ordering::SeqCst);
                 let res = tx_stream.send(BytesMut::from(tx_buffer)).await;
+                if !matches!(res, Ok(ChannelResult::Added)) {
+                    self.queued.fetch_sub(1, Ordering::SeqCst);
+                }
                 match res {
                     Ok(ChannelResult::Added) => Ok(()),
                     Ok(ChannelResult::Replaced) => Err("Replaced into queue")?,
@@ -194,20 +281,36 @@ impl Network for ChannelNetwork {
         }
     }
 
-    async fn recv(&mut self, size: usize) -> Result<BytesMut, Box<dyn Error>> {
-        let mut rx_buffer = vec![0_u8; size];
+    /// Receive the next MQTT frame (whatever its size).
+    async fn recv(&mut self, _size: usize) -> Result<BytesMut, Box<dyn Error>> {
+        match self.from_receiver {
+            Some(ref mut rx_stream) => match rx_stream.recv().await {
+                Ok(frame) => Ok(frame),
+                Err(_) => Err("Connection closed")?,
+            },
+            None => Err("No receive stream available")?,
+        }
+    }
 
+    async fn recv_buf(&mut self, buffer: &mut BytesMut) -> Result<usize, Box<dyn Error>> {
         match self.from_receiver {
-            Some(ref mut rx_stream) => {
-                rx_buffer = rx_stream.recv().await.unwrap().to_vec();
-            }
-            None => Err("No send stream available")?,
+            Some(ref mut rx_stream) => match rx_stream.recv().await {
+                Ok(frame) => {
+                    buffer.extend_from_slice(&frame);
+                    Ok(frame.len())
+                }
+                Err(_) => Ok(0),
+            },
+            None => Err("No receive stream available")?,
         }
+    }
 
-        let mut buffer = BytesMut::new();
-        buffer.extend_from_slice(rx_buffer.as_slice());
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
 
-        Ok(buffer)
+    fn session_info(&self) -> Option<SessionInfo> {
+        self.session.clone()
     }
 }
 
@@ -222,4 +325,46 @@ impl ChannelNetwork {
     pub fn set_queue(&mut self, queue: i64) {
         self.queue = queue;
     }
+
+    /// Messages waiting in the send queue (growing when the transport cannot keep up).
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Shared count of queued messages, kept across connections.
+    pub(crate) fn queue_counter(&self) -> Arc<AtomicUsize> {
+        self.queued.clone()
+    }
+
+    pub(crate) fn sampler(&self) -> Option<PathSampler> {
+        self.sampler.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn close_closes_the_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port().to_string();
+        let mut network = ChannelNetwork::new(Transport::from_str("tcp").unwrap());
+        network
+            .connect("127.0.0.1", &port, "localhost")
+            .await
+            .unwrap();
+        let (mut peer, _) = listener.accept().await.unwrap();
+
+        // Closed while the network (and its statistics sampler) is still around
+        network.close().await.unwrap();
+        let mut buffer = [0_u8; 16];
+        let read = tokio::time::timeout(Duration::from_secs(1), peer.read(&mut buffer)).await;
+        assert_eq!(read.unwrap().unwrap(), 0);
+        #[cfg(target_os = "linux")]
+        assert!(network.sampler().unwrap().sample().is_err());
+    }
 }
@@ -0,0 +1,54 @@
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use log::warn;
+use tokio_rustls::rustls;
+
+/// TLS secrets logger writing the NSS key log format (as `SSLKEYLOGFILE`), so that packet
+/// captures of TLS and QUIC connections can be decrypted.
+#[derive(Debug)]
+pub struct KeyLogWriter {
+    file: Mutex<File>,
+}
+
+impl KeyLogWriter {
+    pub fn open(path: &Path) -> io::Result<Arc<Self>> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(Arc::new(Self {
+            file: Mutex::new(file),
+        }))
+    }
+
+    fn write(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        let mut line = format!("{label} ");
+        for byte in client_random {
+            write!(line, "{byte:02x}").unwrap();
+        }
+        line.push(' ');
+        for byte in secret {
+            write!(line, "{byte:02x}").unwrap();
+        }
+        line.push('\n');
+
+        // Single write so that concurrent connections do not interleave lines
+        if let Err(e) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            warn!("Failed to write TLS key log: {e}");
+        }
+    }
+}
+
+impl rustls::KeyLog for KeyLogWriter {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        self.write(label, client_random, secret);
+    }
+}
+
+impl quinn_rustls::KeyLog for KeyLogWriter {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        self.write(label, client_random, secret);
+    }
+}
@@ -0,0 +1,163 @@
+use bytes::BytesMut;
+use log::debug;
+use mqttbytes::v4::{
+    ConnAck, ConnectReturnCode, Packet, PingResp, PubAck, PubComp, PubRec, SubAck,
+    SubscribeReasonCode,
+};
+use mqttbytes::QoS;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{duplex, split, AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+
+use crate::{parse_packet, Version};
+
+const DEFAULT_PIPE_SIZE: usize = 64 * 1024;
+
+/// How the mock peer answers a packet expecting a reply (CONNECT, PUBLISH with QoS > 0,
+/// PUBREL, SUBSCRIBE, PINGREQ).
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockReply {
+    /// Answer with the matching ack (same packet id)
+    Ack,
+    /// Answer with the matching ack after a delay
+    Delay(Duration),
+    /// Do not answer
+    Drop,
+    /// Answer with these bytes instead of the ack (e.g. a refused CONNACK or a malformed frame)
+    Raw(Vec<u8>),
+}
+
+/// Scriptable broker on the other end of an in-memory transport. Replies are taken from the
+/// script in order, packets are acked once the script is exhausted. Clones share the same
+/// script and record of received packets.
+#[derive(Debug, Clone, Default)]
+pub struct MockPeer {
+    script: Arc<Mutex<VecDeque<MockReply>>>,
+    received: Arc<Mutex<Vec<Packet>>>,
+}
+
+impl MockPeer {
+    pub fn new() -> MockPeer {
+        MockPeer::default()
+    }
+
+    /// Append replies to the script.
+    pub fn script(self, replies: impl IntoIterator<Item = MockReply>) -> MockPeer {
+        self.script.lock().unwrap().extend(replies);
+        self
+    }
+
+    /// Packets received so far, in order.
+    pub fn received(&self) -> Vec<Packet> {
+        self.received.lock().unwrap().clone()
+    }
+
+    fn next_reply(&self) -> MockReply {
+        self.script
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or(MockReply::Ack)
+    }
+
+    async fn run(self, mut stream: DuplexStream) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut buffer = BytesMut::new();
+        loop {
+            // Parse every complete frame, read more bytes otherwise
+            let packet = match parse_packet(&mut buffer, usize::MAX, &Version::V311) {
+                Ok(packet) => packet,
+                Err(mqttbytes::Error::InsufficientBytes(_)) => {
+                    if stream.read_buf(&mut buffer).await? == 0 {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                Err(e) => Err(format!("Malformed packet from client: {e}"))?,
+            };
+            debug!("Mock peer received: {packet:?}");
+            self.received.lock().unwrap().push(packet.clone());
+
+            let mut ack = BytesMut::new();
+            let res = match packet {
+                Packet::Connect(_) => {
+                    ConnAck::new(ConnectReturnCode::Success, false).write(&mut ack)
+                }
+                Packet::Publish(publish) => match publish.qos {
+                    QoS::AtMostOnce => continue,
+                    QoS::AtLeastOnce => PubAck::new(publish.pkid).write(&mut ack),
+                    QoS::ExactlyOnce => PubRec::new(publish.pkid).write(&mut ack),
+                },
+                Packet::PubRel(pub_rel) => PubComp::new(pub_rel.pkid).write(&mut ack),
+                Packet::Subscribe(subscribe) => {
+                    let codes = subscribe
+                        .filters
+                        .iter()
+                        .map(|filter| SubscribeReasonCode::Success(filter.qos))
+                        .collect();
+                    SubAck::new(subscribe.pkid, codes).write(&mut ack)
+                }
+                Packet::PingReq => PingResp.write(&mut ack),
+                Packet::Disconnect => return Ok(()),
+                _ => continue,
+            };
+            res.expect("Packet serialization failed");
+
+            match self.next_reply() {
+                MockReply::Ack => stream.write_all(&ack).await?,
+                MockReply::Delay(delay) => {
+                    tokio::time::sleep(delay).await;
+                    stream.write_all(&ack).await?;
+                }
+                MockReply::Drop => {}
+                MockReply::Raw(bytes) => stream.write_all(&bytes).await?,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryConfig {
+    pub peer: MockPeer,
+    /// Capacity of the in-memory pipe in each direction (bytes)
+    pub pipe_size: usize,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        MemoryConfig {
+            peer: MockPeer::default(),
+            pipe_size: DEFAULT_PIPE_SIZE,
+        }
+    }
+}
+
+/// In-memory connection to a mock peer, no socket is opened.
+#[derive(Debug)]
+pub struct Memory {
+    pub(crate) rx_stream: ReadHalf<DuplexStream>,
+    pub(crate) tx_stream: WriteHalf<DuplexStream>,
+}
+
+impl Memory {
+    pub fn new(config: &MemoryConfig) -> Memory {
+        let (client, server) = duplex(config.pipe_size);
+
+        // The mock peer stops when the client disconnects or closes the pipe
+        let peer = config.peer.clone();
+        tokio::spawn(async move {
+            if let Err(e) = peer.run(server).await {
+                debug!("Mock peer stopped: {e}");
+            }
+        });
+
+        // Split into parse_packet and write halves
+        let (rx_stream, tx_stream) = split(client);
+
+        Memory {
+            rx_stream,
+            tx_stream,
+        }
+    }
+}
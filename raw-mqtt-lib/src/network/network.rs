@@ -1,17 +1,26 @@
+use crate::network::session::SessionInfo;
 use crate::network::transport::Transport;
 use async_trait::async_trait;
 use bytes::BytesMut;
 use std::error::Error;
+use std::net::SocketAddr;
 
 #[async_trait]
 pub trait Network {
     fn new(transport: Transport) -> Self;
     async fn connect(
         &mut self,
-        host: &String,
-        port: &String,
-        server_name: &String,
+        host: &str,
+        port: &str,
+        server_name: &str,
     ) -> Result<(), Box<dyn Error>>;
     async fn send(&mut self, tx_buffer: &[u8]) -> Result<(), Box<dyn Error>>;
     async fn recv(&mut self, size: usize) -> Result<BytesMut, Box<dyn Error>>;
+    /// Append the bytes available (waiting for at least one) to the buffer, 0 once the
+    /// connection is closed. Nothing is lost if cancelled (e.g. on a timeout).
+    async fn recv_buf(&mut self, buffer: &mut BytesMut) -> Result<usize, Box<dyn Error>>;
+    /// Broker address actually used by the connection
+    fn peer_addr(&self) -> Option<SocketAddr>;
+    /// TLS session negotiated with the broker, `None` for plain TCP
+    fn session_info(&self) -> Option<SessionInfo>;
 }
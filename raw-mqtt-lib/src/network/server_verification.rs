@@ -1,8 +1,13 @@
 use quinn_rustls::Certificate;
-use std::fmt::Debug;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::SystemTime;
 use tokio_rustls::rustls;
+use tokio_rustls::rustls::crypto::WebPkiSupportedAlgorithms;
+use x509_parser::prelude::{FromDer, X509Certificate};
 
 use tokio_rustls::rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified};
 use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
@@ -117,3 +122,276 @@ impl quinn_rustls::client::ServerCertVerifier for QuinnSkipServerVerification {
         false
     }
 }
+
+/// SHA-256 fingerprint of a certificate or of its public key (SPKI), written as
+/// `sha256:<hex>`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Fingerprint(pub [u8; 32]);
+
+impl Fingerprint {
+    pub fn of(data: &[u8]) -> Fingerprint {
+        Fingerprint(Sha256::digest(data).into())
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = String;
+
+    fn from_str(fingerprint: &str) -> Result<Fingerprint, Self::Err> {
+        let hex = fingerprint
+            .strip_prefix("sha256:")
+            .ok_or("Fingerprint must start with sha256:")?
+            .replace(':', "");
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err("Fingerprint must be 32 hex encoded bytes".to_string());
+        }
+
+        let mut digest = [0_u8; 32];
+        for (i, byte) in digest.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+                .map_err(|_| "Invalid hex digit in fingerprint")?;
+        }
+        Ok(Fingerprint(digest))
+    }
+}
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "sha256:")?;
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Fingerprints of a DER encoded certificate and of its public key.
+#[derive(Debug, Copy, Clone)]
+pub struct CertificateFingerprints {
+    pub certificate: Fingerprint,
+    pub spki: Fingerprint,
+}
+
+impl CertificateFingerprints {
+    pub fn of(der: &[u8]) -> Result<CertificateFingerprints, String> {
+        let (_, certificate) = X509Certificate::from_der(der)
+            .map_err(|e| format!("Invalid server certificate: {e}"))?;
+        Ok(CertificateFingerprints {
+            certificate: Fingerprint::of(der),
+            spki: Fingerprint::of(certificate.public_key().raw),
+        })
+    }
+
+    fn matches(&self, pins: &[Fingerprint]) -> bool {
+        pins.iter()
+            .any(|pin| *pin == self.certificate || *pin == self.spki)
+    }
+}
+
+/// Accept a server only when its leaf certificate or SPKI fingerprint matches a pin. Handshake
+/// signatures are still verified against the pinned certificate.
+#[derive(Debug)]
+pub struct PinnedServerVerification {
+    pins: Vec<Fingerprint>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl PinnedServerVerification {
+    pub fn new(pins: Vec<Fingerprint>) -> Arc<Self> {
+        Arc::new(Self {
+            pins,
+            algorithms: rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        })
+    }
+}
+
+impl rustls::client::danger::ServerCertVerifier for PinnedServerVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let fingerprints = CertificateFingerprints::of(end_entity).map_err(Error::General)?;
+        if fingerprints.matches(&self.pins) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(Error::General(pin_mismatch(&fingerprints)))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/// Certificate pinning verifier for QUIC connections (handshake signatures are verified by the
+/// default webpki implementation).
+pub struct QuinnPinnedServerVerification {
+    pins: Vec<Fingerprint>,
+}
+
+impl QuinnPinnedServerVerification {
+    pub fn new(pins: Vec<Fingerprint>) -> Arc<Self> {
+        Arc::new(Self { pins })
+    }
+}
+
+impl quinn_rustls::client::ServerCertVerifier for QuinnPinnedServerVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &quinn_rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<quinn_rustls::client::ServerCertVerified, quinn_rustls::Error> {
+        let fingerprints =
+            CertificateFingerprints::of(&end_entity.0).map_err(quinn_rustls::Error::General)?;
+        if fingerprints.matches(&self.pins) {
+            Ok(quinn_rustls::client::ServerCertVerified::assertion())
+        } else {
+            Err(quinn_rustls::Error::General(pin_mismatch(&fingerprints)))
+        }
+    }
+
+    fn request_scts(&self) -> bool {
+        false
+    }
+}
+
+fn pin_mismatch(fingerprints: &CertificateFingerprints) -> String {
+    format!(
+        "Certificate pin mismatch, server presented {} (SPKI {})",
+        fingerprints.certificate, fingerprints.spki
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quinn_rustls::client::ServerCertVerifier as _;
+    use rustls::client::danger::ServerCertVerifier as _;
+
+    /// Self-signed certificate (DER) and its fingerprints.
+    fn certificate() -> (Vec<u8>, CertificateFingerprints) {
+        let der = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+            .unwrap()
+            .serialize_der()
+            .unwrap();
+        let fingerprints = CertificateFingerprints::of(&der).unwrap();
+        (der, fingerprints)
+    }
+
+    fn verify(pins: Vec<Fingerprint>, der: &[u8]) -> Result<(), Error> {
+        PinnedServerVerification::new(pins)
+            .verify_server_cert(
+                &CertificateDer::from(der.to_vec()),
+                &[],
+                &ServerName::try_from("localhost").unwrap(),
+                &[],
+                UnixTime::now(),
+            )
+            .map(|_| ())
+    }
+
+    fn verify_quinn(pins: Vec<Fingerprint>, der: &[u8]) -> Result<(), quinn_rustls::Error> {
+        QuinnPinnedServerVerification::new(pins)
+            .verify_server_cert(
+                &Certificate(der.to_vec()),
+                &[],
+                &quinn_rustls::ServerName::try_from("localhost").unwrap(),
+                &mut std::iter::empty(),
+                &[],
+                SystemTime::now(),
+            )
+            .map(|_| ())
+    }
+
+    #[test]
+    fn fingerprint_round_trip() {
+        let fingerprint = Fingerprint::of(b"hello");
+        let text = fingerprint.to_string();
+        assert!(text.starts_with("sha256:2cf24dba"));
+        assert_eq!(Fingerprint::from_str(&text).unwrap(), fingerprint);
+
+        // Colon separated bytes, any case
+        let hex: Vec<String> = fingerprint
+            .0
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect();
+        let separated = format!("sha256:{}", hex.join(":"));
+        assert_eq!(Fingerprint::from_str(&separated).unwrap(), fingerprint);
+    }
+
+    #[test]
+    fn malformed_fingerprints() {
+        let hex = "ab".repeat(32);
+        for fingerprint in [
+            hex.clone(),
+            format!("sha1:{hex}"),
+            format!("sha256:{}", &hex[..62]),
+            format!("sha256:{hex}00"),
+            format!("sha256:zz{}", &hex[2..]),
+            format!("sha256:\u{e9}{}", &hex[2..]),
+        ] {
+            assert!(
+                Fingerprint::from_str(&fingerprint).is_err(),
+                "{fingerprint}"
+            );
+        }
+    }
+
+    #[test]
+    fn certificate_fingerprints() {
+        let (der, fingerprints) = certificate();
+        assert_eq!(fingerprints.certificate, Fingerprint::of(&der));
+        assert_ne!(fingerprints.spki, fingerprints.certificate);
+        assert!(CertificateFingerprints::of(b"not a certificate").is_err());
+    }
+
+    #[test]
+    fn pinned_verification() {
+        let (der, fingerprints) = certificate();
+        let (_, other) = certificate();
+
+        assert!(verify(vec![fingerprints.certificate], &der).is_ok());
+        assert!(verify(vec![other.spki, fingerprints.spki], &der).is_ok());
+        let err = verify(vec![other.certificate, other.spki], &der).unwrap_err();
+        assert!(err.to_string().contains("pin mismatch"), "{err}");
+        assert!(verify(vec![fingerprints.certificate], b"not a certificate").is_err());
+    }
+
+    #[test]
+    fn quinn_pinned_verification() {
+        let (der, fingerprints) = certificate();
+        let (_, other) = certificate();
+
+        assert!(verify_quinn(vec![fingerprints.certificate], &der).is_ok());
+        assert!(verify_quinn(vec![fingerprints.spki], &der).is_ok());
+        let err = verify_quinn(vec![other.certificate, other.spki], &der).unwrap_err();
+        assert!(err.to_string().contains("pin mismatch"), "{err}");
+    }
+}
@@ -0,0 +1,92 @@
+use base64::Engine;
+use std::fmt;
+
+use crate::network::server_verification::CertificateFingerprints;
+
+/// Parameters negotiated by the TLS handshake of a TLS or QUIC connection.
+#[derive(Debug, Clone, Default)]
+pub struct SessionInfo {
+    /// Protocol version, e.g. `TLSv1_3`
+    pub protocol_version: String,
+    /// Cipher suite, `None` if not reported (quinn does not expose it)
+    pub cipher_suite: Option<String>,
+    /// Application protocol selected by the server
+    pub alpn: Option<String>,
+    /// Server name sent in the SNI extension (not sent for IP addresses)
+    pub server_name: Option<String>,
+    /// Whether the session was resumed, `None` if not reported (quinn does not expose it)
+    pub resumed: Option<bool>,
+    /// Certificate chain presented by the broker (DER, leaf first)
+    pub certificates: Vec<Vec<u8>>,
+}
+
+impl SessionInfo {
+    /// Certificate chain presented by the broker, PEM encoded.
+    pub fn chain_pem(&self) -> String {
+        let mut pem = String::new();
+        for certificate in &self.certificates {
+            let encoded = base64::engine::general_purpose::STANDARD.encode(certificate);
+            pem.push_str("-----BEGIN CERTIFICATE-----\n");
+            for line in encoded.as_bytes().chunks(64) {
+                pem.push_str(std::str::from_utf8(line).unwrap());
+                pem.push('\n');
+            }
+            pem.push_str("-----END CERTIFICATE-----\n");
+        }
+        pem
+    }
+}
+
+impl fmt::Display for SessionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, cipher suite {}, ALPN {}, SNI {}, resumed {}, {} certificate(s)",
+            self.protocol_version,
+            self.cipher_suite.as_deref().unwrap_or("unknown"),
+            self.alpn.as_deref().unwrap_or("none"),
+            self.server_name.as_deref().unwrap_or("none"),
+            self.resumed
+                .map_or("unknown".to_string(), |resumed| resumed.to_string()),
+            self.certificates.len()
+        )?;
+        if let Some(leaf) = self.certificates.first() {
+            if let Ok(fingerprints) = CertificateFingerprints::of(leaf) {
+                write!(
+                    f,
+                    ", leaf {} (SPKI {})",
+                    fingerprints.certificate, fingerprints.spki
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain_pem() {
+        let session = SessionInfo {
+            certificates: vec![vec![0_u8; 60], vec![1, 2, 3]],
+            ..SessionInfo::default()
+        };
+
+        let pem = session.chain_pem();
+        let lines: Vec<&str> = pem.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "-----BEGIN CERTIFICATE-----",
+                &"A".repeat(64),
+                &"A".repeat(16),
+                "-----END CERTIFICATE-----",
+                "-----BEGIN CERTIFICATE-----",
+                "AQID",
+                "-----END CERTIFICATE-----",
+            ]
+        );
+    }
+}
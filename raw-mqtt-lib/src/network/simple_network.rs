@@ -3,16 +3,21 @@
 use async_trait::async_trait;
 use bytes::BytesMut;
 use std::error::Error;
+use std::net::SocketAddr;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+use crate::network::memory::{Memory, MemoryConfig};
 use crate::network::network::Network;
+use crate::network::session::SessionInfo;
 use crate::network::transport::{Quic, QuicConfig, Tcp, TcpConfig, Tls, TlsConfig, Transport};
 
 #[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum SimpleNetwork {
     TCP(Option<Tcp>, TcpConfig),
     TLS(Option<Tls>, TlsConfig),
     QUIC(Option<Quic>, QuicConfig),
+    Memory(Option<Memory>, MemoryConfig),
 }
 #[async_trait]
 impl Network for SimpleNetwork {
@@ -21,25 +26,28 @@ impl Network for SimpleNetwork {
             Transport::TCP(config) => SimpleNetwork::TCP(None, config),
             Transport::TLS(config) => SimpleNetwork::TLS(None, config),
             Transport::QUIC(config) => SimpleNetwork::QUIC(None, config),
+            Transport::Memory(config) => SimpleNetwork::Memory(None, config),
         }
     }
 
     async fn connect(
         &mut self,
-        host: &String,
-        port: &String,
-        server_name: &String,
+        host: &str,
+        port: &str,
+        server_name: &str,
     ) -> Result<(), Box<dyn Error>> {
         match self {
             SimpleNetwork::TCP(tcp, config) => {
-                *tcp = Some(Tcp::new(host, port, config.nagle).await?);
+                *tcp = Some(Tcp::new(host, port, config).await?);
             }
             SimpleNetwork::TLS(tls, config) => {
-                *tls =
-                    Some(Tls::new(host, port, config.nagle, config.insecure, &server_name).await?);
+                *tls = Some(Tls::new(host, port, config, server_name).await?);
             }
             SimpleNetwork::QUIC(quic, config) => {
-                *quic = Some(Quic::new(host, port, config.insecure, &server_name).await?);
+                *quic = Some(Quic::new(host, port, config, server_name).await?);
+            }
+            SimpleNetwork::Memory(memory, config) => {
+                *memory = Some(Memory::new(config));
             }
         }
         Ok(())
@@ -50,6 +58,7 @@ impl Network for SimpleNetwork {
             SimpleNetwork::TCP(Some(tcp), _) => tcp.tx_stream.write_all(tx_buffer).await?,
             SimpleNetwork::TLS(Some(tls), _) => tls.tx_stream.write_all(tx_buffer).await?,
             SimpleNetwork::QUIC(Some(quic), _) => quic.tx_stream.write_all(tx_buffer).await?,
+            SimpleNetwork::Memory(Some(memory), _) => memory.tx_stream.write_all(tx_buffer).await?,
             _ => Err("No send stream available")?,
         }
 
@@ -69,6 +78,9 @@ impl Network for SimpleNetwork {
             SimpleNetwork::QUIC(Some(quic), _) => {
                 quic.rx_stream.read_exact(&mut rx_buffer).await?;
             }
+            SimpleNetwork::Memory(Some(memory), _) => {
+                memory.rx_stream.read_exact(&mut rx_buffer).await?;
+            }
             _ => Err("No send stream available")?,
         }
 
@@ -77,4 +89,33 @@ impl Network for SimpleNetwork {
 
         Ok(buffer)
     }
+
+    async fn recv_buf(&mut self, buffer: &mut BytesMut) -> Result<usize, Box<dyn Error>> {
+        let size = match self {
+            SimpleNetwork::TCP(Some(tcp), _) => tcp.rx_stream.read_buf(buffer).await?,
+            SimpleNetwork::TLS(Some(tls), _) => tls.rx_stream.read_buf(buffer).await?,
+            SimpleNetwork::QUIC(Some(quic), _) => quic.rx_stream.read_buf(buffer).await?,
+            SimpleNetwork::Memory(Some(memory), _) => memory.rx_stream.read_buf(buffer).await?,
+            _ => Err("No receive stream available")?,
+        };
+
+        Ok(size)
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        match self {
+            SimpleNetwork::TCP(Some(tcp), _) => Some(tcp.peer_addr),
+            SimpleNetwork::TLS(Some(tls), _) => Some(tls.peer_addr),
+            SimpleNetwork::QUIC(Some(quic), _) => Some(quic.peer_addr),
+            _ => None,
+        }
+    }
+
+    fn session_info(&self) -> Option<SessionInfo> {
+        match self {
+            SimpleNetwork::TLS(Some(tls), _) => Some(tls.session.clone()),
+            SimpleNetwork::QUIC(Some(quic), _) => Some(quic.session.clone()),
+            _ => None,
+        }
+    }
 }
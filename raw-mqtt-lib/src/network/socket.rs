@@ -0,0 +1,552 @@
+use std::error::Error;
+use std::future::Future;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::{debug, info};
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use tokio::net::{lookup_host, TcpSocket, TcpStream};
+
+use crate::network::proxy::Proxy;
+
+/// Delay between connection attempts in Happy Eyeballs mode (RFC 8305, section 8).
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum AddressFamily {
+    #[default]
+    Any,
+    V4,
+    V6,
+}
+
+/// How the resolved broker addresses are tried.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum ConnectStrategy {
+    /// Only the first resolved address
+    First,
+    /// Every resolved address in turn, until one succeeds
+    #[default]
+    Sequential,
+    /// Staggered parallel attempts alternating address families (RFC 8305)
+    HappyEyeballs,
+}
+
+impl FromStr for ConnectStrategy {
+    type Err = String;
+
+    fn from_str(strategy: &str) -> Result<Self, Self::Err> {
+        match strategy {
+            "first" => Ok(ConnectStrategy::First),
+            "sequential" => Ok(ConnectStrategy::Sequential),
+            "happy-eyeballs" => Ok(ConnectStrategy::HappyEyeballs),
+            _ => Err("Invalid connection strategy".to_string()),
+        }
+    }
+}
+
+/// Local side of a connection and address selection, applied to every transport.
+#[derive(Debug, Clone, Default)]
+pub struct AddressConfig {
+    /// Local address (and port) to bind before connecting
+    pub bind: Option<SocketAddr>,
+    /// Network interface to bind to (`SO_BINDTODEVICE`, Linux only)
+    pub interface: Option<String>,
+    pub family: AddressFamily,
+    pub strategy: ConnectStrategy,
+}
+
+impl AddressConfig {
+    /// Local address to bind when connecting to `peer`.
+    pub(crate) fn local_for(&self, peer: &SocketAddr) -> SocketAddr {
+        match self.bind {
+            Some(bind) => bind,
+            None if peer.is_ipv4() => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            None => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        }
+    }
+}
+
+/**
+ * Resolve `host:port`, keeping only the addresses allowed by the requested family and by the
+ * bind address (if any).
+ */
+pub(crate) async fn resolve(
+    host: &str,
+    port: &str,
+    address: &AddressConfig,
+) -> io::Result<Vec<SocketAddr>> {
+    let port: u16 = port
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid port"))?;
+
+    // Resolve as (host, port) so that IPv6 literals need no brackets
+    let addrs: Vec<SocketAddr> = lookup_host((host, port))
+        .await?
+        .filter(|addr| match address.family {
+            AddressFamily::Any => true,
+            AddressFamily::V4 => addr.is_ipv4(),
+            AddressFamily::V6 => addr.is_ipv6(),
+        })
+        .filter(|addr| match address.bind {
+            Some(bind) => bind.is_ipv4() == addr.is_ipv4(),
+            None => true,
+        })
+        .collect();
+
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No usable address for {host}:{port}"),
+        ));
+    }
+    debug!("Resolved {host}:{port} to {addrs:?}");
+
+    Ok(addrs)
+}
+
+/**
+ * Connect to one of `addrs` following `strategy`. Returns the established connection together
+ * with the address actually used.
+ */
+pub(crate) async fn connect_any<T, E, F, Fut>(
+    addrs: Vec<SocketAddr>,
+    strategy: ConnectStrategy,
+    connect: F,
+) -> Result<(T, SocketAddr), E>
+where
+    F: Fn(SocketAddr) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    match strategy {
+        ConnectStrategy::First => {
+            let addr = addrs[0];
+            Ok((connect(addr).await?, addr))
+        }
+        ConnectStrategy::Sequential => {
+            let mut last_error = None;
+            for addr in addrs {
+                match connect(addr).await {
+                    Ok(connection) => return Ok((connection, addr)),
+                    Err(e) => {
+                        debug!("Connection to {addr} failed");
+                        last_error = Some(e);
+                    }
+                }
+            }
+            Err(last_error.unwrap())
+        }
+        ConnectStrategy::HappyEyeballs => {
+            let mut pending = interleave(addrs).into_iter();
+            let mut attempts = FuturesUnordered::new();
+            let mut last_error = None;
+
+            let attempt = |addr: SocketAddr| {
+                let connection = connect(addr);
+                async move { (addr, connection.await) }
+            };
+
+            loop {
+                // Start a new attempt if none is running
+                if attempts.is_empty() {
+                    match pending.next() {
+                        Some(addr) => attempts.push(attempt(addr)),
+                        None => return Err(last_error.unwrap()),
+                    }
+                }
+
+                let more = pending.len() > 0;
+                tokio::select! {
+                    Some((addr, res)) = attempts.next() => match res {
+                        Ok(connection) => return Ok((connection, addr)),
+                        Err(e) => {
+                            // Failed attempts start the next one right away
+                            debug!("Connection to {addr} failed");
+                            last_error = Some(e);
+                            if let Some(addr) = pending.next() {
+                                attempts.push(attempt(addr));
+                            }
+                        }
+                    },
+                    _ = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY), if more => {
+                        attempts.push(attempt(pending.next().unwrap()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Alternate address families, starting with the family of the first resolved address.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_v6 = addrs[0].is_ipv6();
+    let (mut primary, mut secondary): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_v6);
+
+    let mut interleaved = Vec::with_capacity(primary.len() + secondary.len());
+    primary.reverse();
+    secondary.reverse();
+    loop {
+        match (primary.pop(), secondary.pop()) {
+            (None, None) => break,
+            (a, b) => interleaved.extend(a.into_iter().chain(b)),
+        }
+    }
+    interleaved
+}
+
+/// TCP keepalive timers (`SO_KEEPALIVE`, `TCP_KEEPIDLE`, `TCP_KEEPINTVL`, `TCP_KEEPCNT`).
+#[derive(Debug, Copy, Clone)]
+pub struct KeepaliveConfig {
+    pub idle: Duration,
+    pub interval: Option<Duration>,
+    pub retries: Option<u32>,
+}
+
+/// Socket options applied to TCP based transports before connecting. Unset options keep the
+/// system defaults.
+#[derive(Debug, Clone, Default)]
+pub struct SocketConfig {
+    /// `SO_SNDBUF` size in bytes
+    pub send_buffer: Option<usize>,
+    /// `SO_RCVBUF` size in bytes
+    pub recv_buffer: Option<usize>,
+    /// `TCP_NOTSENT_LOWAT` threshold in bytes (Linux only)
+    pub notsent_lowat: Option<u32>,
+    /// `TCP_CONGESTION` algorithm name, e.g. `cubic` or `bbr` (Linux only)
+    pub congestion: Option<String>,
+    pub keepalive: Option<KeepaliveConfig>,
+    /// `TCP_USER_TIMEOUT` (Linux only)
+    pub user_timeout: Option<Duration>,
+    /// IP TOS byte (DSCP << 2 | ECN), set as traffic class on IPv6 sockets
+    pub tos: Option<u32>,
+    pub connect_timeout: Option<Duration>,
+}
+
+/**
+ * Open a TCP connection to `host:port`, with the socket options in `config` applied before
+ * connecting, tunnelled through `proxy` if given. Returns the stream and the address actually
+ * connected to (the proxy address when tunnelling).
+ */
+pub(crate) async fn connect(
+    host: &str,
+    port: &str,
+    nagle: bool,
+    config: &SocketConfig,
+    address: &AddressConfig,
+    proxy: Option<&Proxy>,
+) -> Result<(TcpStream, SocketAddr), Box<dyn Error>> {
+    let connect = async {
+        let addrs = match proxy {
+            Some(proxy) => resolve(&proxy.host, &proxy.port.to_string(), address).await?,
+            None => resolve(host, port, address).await?,
+        };
+        let (mut tcp_stream, addr) = connect_any(addrs, address.strategy, |addr| {
+            connect_addr(addr, nagle, config, address)
+        })
+        .await?;
+
+        if let Some(proxy) = proxy {
+            let port = port
+                .parse()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid port"))?;
+            proxy.tunnel(&mut tcp_stream, host, port).await?;
+        }
+        Ok::<(TcpStream, SocketAddr), io::Error>((tcp_stream, addr))
+    };
+
+    let (tcp_stream, addr) = match config.connect_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, connect)
+            .await
+            .map_err(|_| "Connection timed out")??,
+        None => connect.await?,
+    };
+    match proxy {
+        Some(_) => info!("Connected to {host}:{port} through proxy {addr}"),
+        None => info!("Connected to {addr}"),
+    }
+
+    Ok((tcp_stream, addr))
+}
+
+async fn connect_addr(
+    addr: SocketAddr,
+    nagle: bool,
+    config: &SocketConfig,
+    address: &AddressConfig,
+) -> io::Result<TcpStream> {
+    let tcp_socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    apply(&tcp_socket, addr, config)?;
+
+    // Pin the connection to a local interface and/or address
+    if let Some(ref interface) = address.interface {
+        bind_device(&SockRef::from(&tcp_socket), interface)?;
+    }
+    if let Some(bind) = address.bind {
+        // A fixed port is shared by the parallel attempts (Happy Eyeballs) and reconnections
+        if bind.port() != 0 {
+            tcp_socket.set_reuseaddr(true)?;
+        }
+        tcp_socket.bind(bind)?;
+    }
+
+    let tcp_stream = tcp_socket.connect(addr).await?;
+
+    // Enable/Disable Nagle's algorithm
+    tcp_stream.set_nodelay(!nagle)?;
+
+    Ok(tcp_stream)
+}
+
+fn apply(tcp_socket: &TcpSocket, addr: SocketAddr, config: &SocketConfig) -> io::Result<()> {
+    let socket = SockRef::from(tcp_socket);
+
+    // Buffer sizes must be set before connecting to affect the window scale
+    if let Some(size) = config.send_buffer {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = config.recv_buffer {
+        socket.set_recv_buffer_size(size)?;
+    }
+
+    if let Some(keepalive) = config.keepalive {
+        let mut params = TcpKeepalive::new().with_time(keepalive.idle);
+        if let Some(interval) = keepalive.interval {
+            params = params.with_interval(interval);
+        }
+        if let Some(retries) = keepalive.retries {
+            params = params.with_retries(retries);
+        }
+        socket.set_tcp_keepalive(&params)?;
+    }
+
+    if let Some(tos) = config.tos {
+        if addr.is_ipv4() {
+            socket.set_tos(tos)?;
+        } else {
+            set_tclass_v6(&socket, tos)?;
+        }
+    }
+
+    if let Some(lowat) = config.notsent_lowat {
+        set_notsent_lowat(&socket, lowat)?;
+    }
+    if let Some(ref congestion) = config.congestion {
+        set_congestion(&socket, congestion)?;
+    }
+    if let Some(timeout) = config.user_timeout {
+        set_user_timeout(&socket, timeout)?;
+    }
+
+    Ok(())
+}
+
+/**
+ * Build a UDP socket bound to `local`, and optionally to a network interface, for QUIC endpoints.
+ */
+pub(crate) fn udp_socket(
+    local: SocketAddr,
+    address: &AddressConfig,
+) -> io::Result<std::net::UdpSocket> {
+    let socket = Socket::new(Domain::for_address(local), Type::DGRAM, Some(Protocol::UDP))?;
+    if let Some(ref interface) = address.interface {
+        bind_device(&SockRef::from(&socket), interface)?;
+    }
+    socket.bind(&local.into())?;
+    Ok(socket.into())
+}
+
+#[cfg(target_os = "linux")]
+fn bind_device(socket: &SockRef, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(target_os = "linux")]
+fn set_tclass_v6(socket: &SockRef, tclass: u32) -> io::Result<()> {
+    socket.set_tclass_v6(tclass)
+}
+
+#[cfg(target_os = "linux")]
+fn set_notsent_lowat(socket: &SockRef, lowat: u32) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_NOTSENT_LOWAT,
+            &lowat as *const u32 as *const libc::c_void,
+            std::mem::size_of::<u32>() as libc::socklen_t,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_congestion(socket: &SockRef, congestion: &str) -> io::Result<()> {
+    socket.set_tcp_congestion(congestion.as_bytes())
+}
+
+#[cfg(target_os = "linux")]
+fn set_user_timeout(socket: &SockRef, timeout: Duration) -> io::Result<()> {
+    socket.set_tcp_user_timeout(Some(timeout))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_device(_socket: &SockRef, _interface: &str) -> io::Result<()> {
+    Err(unsupported("SO_BINDTODEVICE"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_tclass_v6(_socket: &SockRef, _tclass: u32) -> io::Result<()> {
+    Err(unsupported("IPV6_TCLASS"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_notsent_lowat(_socket: &SockRef, _lowat: u32) -> io::Result<()> {
+    Err(unsupported("TCP_NOTSENT_LOWAT"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_congestion(_socket: &SockRef, _congestion: &str) -> io::Result<()> {
+    Err(unsupported("TCP_CONGESTION"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_user_timeout(_socket: &SockRef, _timeout: Duration) -> io::Result<()> {
+    Err(unsupported("TCP_USER_TIMEOUT"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unsupported(option: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{option} is not supported on this platform"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn options_read_back() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port().to_string();
+        let config = SocketConfig {
+            send_buffer: Some(65536),
+            // DSCP EF (46), not ECN capable
+            tos: Some(46 << 2),
+            ..SocketConfig::default()
+        };
+
+        let address = AddressConfig::default();
+        let (tcp_stream, _) = connect("127.0.0.1", &port, false, &config, &address, None)
+            .await
+            .unwrap();
+        let socket = SockRef::from(&tcp_stream);
+
+        // Linux doubles the requested size for bookkeeping overhead
+        assert!(socket.send_buffer_size().unwrap() >= 65536);
+        assert!(socket.nodelay().unwrap());
+        assert_eq!(socket.tos().unwrap(), 46 << 2);
+    }
+
+    #[tokio::test]
+    async fn fixed_bind_port_shared() {
+        let first = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let second = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        // Free local port to bind both connections to
+        let bind = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let address = AddressConfig {
+            bind: Some(bind),
+            ..AddressConfig::default()
+        };
+        let config = SocketConfig::default();
+
+        let first_addr = first.local_addr().unwrap();
+        let second_addr = second.local_addr().unwrap();
+        let first_stream = connect_addr(first_addr, false, &config, &address)
+            .await
+            .unwrap();
+        let second_stream = connect_addr(second_addr, false, &config, &address)
+            .await
+            .unwrap();
+        assert_eq!(first_stream.local_addr().unwrap(), bind);
+        assert_eq!(second_stream.local_addr().unwrap(), bind);
+    }
+
+    #[test]
+    fn interleave_families() {
+        let addrs: Vec<SocketAddr> = ["[::1]:1", "[::2]:1", "[::3]:1", "10.0.0.1:1", "10.0.0.2:1"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+        let expected: Vec<SocketAddr> =
+            ["[::1]:1", "10.0.0.1:1", "[::2]:1", "10.0.0.2:1", "[::3]:1"]
+                .iter()
+                .map(|addr| addr.parse().unwrap())
+                .collect();
+        assert_eq!(interleave(addrs), expected);
+
+        // Single family, order kept
+        let addrs: Vec<SocketAddr> =
+            vec!["10.0.0.2:1".parse().unwrap(), "10.0.0.1:1".parse().unwrap()];
+        assert_eq!(interleave(addrs.clone()), addrs);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn happy_eyeballs_attempt_delay() {
+        let addrs: Vec<SocketAddr> =
+            vec!["[::1]:1".parse().unwrap(), "10.0.0.1:1".parse().unwrap()];
+
+        // The first attempt hangs, the second one starts after the attempt delay
+        let started = tokio::time::Instant::now();
+        let (_, addr) = connect_any(
+            addrs.clone(),
+            ConnectStrategy::HappyEyeballs,
+            |addr| async move {
+                if addr.is_ipv6() {
+                    std::future::pending::<()>().await;
+                }
+                Ok::<_, io::Error>(())
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(addr, addrs[1]);
+        assert_eq!(started.elapsed(), CONNECTION_ATTEMPT_DELAY);
+
+        // A failed attempt starts the next one right away
+        let started = tokio::time::Instant::now();
+        let (_, addr) = connect_any(
+            addrs.clone(),
+            ConnectStrategy::HappyEyeballs,
+            |addr| async move {
+                match addr.is_ipv6() {
+                    true => Err(io::Error::from(io::ErrorKind::ConnectionRefused)),
+                    false => Ok(()),
+                }
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(addr, addrs[1]);
+        assert_eq!(started.elapsed(), Duration::ZERO);
+    }
+}
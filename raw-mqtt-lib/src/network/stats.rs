@@ -0,0 +1,284 @@
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::fd::RawFd;
+use std::pin::Pin;
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(target_os = "linux")]
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{split, AsyncRead, AsyncWrite, ReadBuf, ReadHalf, WriteHalf};
+
+/// Path statistics of a QUIC connection, as reported by quinn.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct QuicStats {
+    /// Smoothed round-trip time
+    pub rtt: Duration,
+    /// Congestion window (bytes)
+    pub cwnd: u64,
+    pub congestion_events: u64,
+    pub lost_packets: u64,
+    pub lost_bytes: u64,
+    pub sent_packets: u64,
+    pub sent_bytes: u64,
+    pub recv_bytes: u64,
+    pub sent_datagrams: u64,
+    pub recv_datagrams: u64,
+}
+
+/// Kernel-side path statistics of a TCP connection, read with `getsockopt(TCP_INFO)`.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TcpStats {
+    /// Smoothed round-trip time
+    pub rtt: Duration,
+    /// Round-trip time variance
+    pub rttvar: Duration,
+    /// Congestion window (segments)
+    pub cwnd: u32,
+    pub mss: u32,
+    pub retransmits: u32,
+    pub unacked: u32,
+    /// Delivery rate (bytes per second)
+    pub delivery_rate: u64,
+    pub sent_bytes: u64,
+    pub acked_bytes: u64,
+    pub recv_bytes: u64,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum TransportStats {
+    Tcp(TcpStats),
+    Quic(QuicStats),
+}
+
+/// A single transport statistics sample, timestamped in nanoseconds since the Unix epoch.
+#[derive(Debug, Copy, Clone)]
+pub struct StatsSample {
+    pub timestamp: u128,
+    pub stats: TransportStats,
+}
+
+/// Handle kept by the network to read path statistics of an open connection.
+#[derive(Debug, Clone)]
+pub(crate) enum PathSampler {
+    /// Descriptor of the socket, forgotten before the socket closes
+    #[cfg(target_os = "linux")]
+    Tcp(Arc<SocketFd>),
+    Quic(quinn::Connection),
+}
+
+/// Descriptor of a TCP socket, known while the stream halves owning the socket are alive.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub(crate) struct SocketFd {
+    fd: RwLock<Option<RawFd>>,
+    /// Stream halves still holding the socket open
+    halves: AtomicUsize,
+}
+
+/// Half of a stream over a TCP socket, owning the socket with the other half. The last half
+/// dropped forgets the descriptor of the sampler before the socket closes, so that a sample
+/// never reads a closed (or reused) descriptor.
+#[derive(Debug)]
+pub(crate) struct SocketHalf<S> {
+    stream: S,
+    #[cfg(target_os = "linux")]
+    socket: Arc<SocketFd>,
+}
+
+/// Read and write halves of a stream over a TCP socket.
+pub(crate) type SocketHalves<S> = (SocketHalf<ReadHalf<S>>, SocketHalf<WriteHalf<S>>);
+
+/// Split a stream over the TCP socket of descriptor `fd`, with the sampler of the socket.
+#[cfg(target_os = "linux")]
+pub(crate) fn split_socket<S>(stream: S, fd: RawFd) -> (SocketHalves<S>, PathSampler)
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let socket = Arc::new(SocketFd {
+        fd: RwLock::new(Some(fd)),
+        halves: AtomicUsize::new(2),
+    });
+    let (rx_stream, tx_stream) = split(stream);
+    let halves = (
+        SocketHalf {
+            stream: rx_stream,
+            socket: socket.clone(),
+        },
+        SocketHalf {
+            stream: tx_stream,
+            socket: socket.clone(),
+        },
+    );
+    (halves, PathSampler::Tcp(socket))
+}
+
+/// Split a stream over a TCP socket (not sampled).
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn split_socket<S>(stream: S) -> SocketHalves<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (rx_stream, tx_stream) = split(stream);
+    (
+        SocketHalf { stream: rx_stream },
+        SocketHalf { stream: tx_stream },
+    )
+}
+
+#[cfg(target_os = "linux")]
+impl<S> Drop for SocketHalf<S> {
+    fn drop(&mut self) {
+        // The stream is dropped right after, closing the socket if it is the last half
+        if self.socket.halves.fetch_sub(1, Ordering::SeqCst) == 1 {
+            *self.socket.fd.write().unwrap() = None;
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for SocketHalf<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for SocketHalf<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+impl PathSampler {
+    /// Current statistics, an error once the connection is closed (TCP).
+    pub(crate) fn sample(&self) -> io::Result<StatsSample> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_nanos();
+
+        let stats = match self {
+            #[cfg(target_os = "linux")]
+            PathSampler::Tcp(socket) => {
+                // Held while sampling, the socket cannot be closed meanwhile
+                let fd = socket.fd.read().unwrap();
+                let Some(fd) = *fd else {
+                    return Err(io::Error::new(io::ErrorKind::NotConnected, "Socket closed"));
+                };
+                TransportStats::Tcp(tcp_info(fd)?)
+            }
+            PathSampler::Quic(connection) => {
+                let stats = connection.stats();
+                TransportStats::Quic(QuicStats {
+                    rtt: connection.rtt(),
+                    cwnd: stats.path.cwnd,
+                    congestion_events: stats.path.congestion_events,
+                    lost_packets: stats.path.lost_packets,
+                    lost_bytes: stats.path.lost_bytes,
+                    sent_packets: stats.path.sent_packets,
+                    sent_bytes: stats.udp_tx.bytes,
+                    recv_bytes: stats.udp_rx.bytes,
+                    sent_datagrams: stats.udp_tx.datagrams,
+                    recv_datagrams: stats.udp_rx.datagrams,
+                })
+            }
+        };
+
+        Ok(StatsSample { timestamp, stats })
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn tcp_info(fd: RawFd) -> io::Result<TcpStats> {
+    let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+
+    // Older kernels fill only a prefix of the structure, the rest is left zeroed
+    let res = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut info as *mut libc::tcp_info as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(TcpStats {
+        rtt: Duration::from_micros(info.tcpi_rtt as u64),
+        rttvar: Duration::from_micros(info.tcpi_rttvar as u64),
+        cwnd: info.tcpi_snd_cwnd,
+        mss: info.tcpi_snd_mss,
+        retransmits: info.tcpi_total_retrans,
+        unacked: info.tcpi_unacked,
+        delivery_rate: info.tcpi_delivery_rate,
+        sent_bytes: info.tcpi_bytes_sent,
+        acked_bytes: info.tcpi_bytes_acked,
+        recv_bytes: info.tcpi_bytes_received,
+    })
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn closed_socket_not_sampled() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let stream = TcpStream::connect(address).await.unwrap();
+        let (mut peer, _) = listener.accept().await.unwrap();
+        let fd = stream.as_raw_fd();
+        let ((mut rx_stream, tx_stream), sampler) = split_socket(stream, fd);
+
+        // Payload and FIN fully received
+        peer.write_all(b"hello").await.unwrap();
+        peer.shutdown().await.unwrap();
+        let mut received = Vec::new();
+        rx_stream.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"hello");
+        match sampler.sample().unwrap().stats {
+            TransportStats::Tcp(stats) => assert_eq!(stats.recv_bytes, 6),
+            other => panic!("Unexpected statistics: {other:?}"),
+        }
+
+        // One half left, the socket is still open
+        drop(rx_stream);
+        assert!(sampler.sample().is_ok());
+
+        // Close the socket and give its descriptor number to another connection
+        drop(tx_stream);
+        let other = TcpStream::connect(address).await.unwrap();
+        let _accepted = listener.accept().await.unwrap();
+        let reused = (other.as_raw_fd() != fd).then(|| {
+            assert_eq!(unsafe { libc::dup2(other.as_raw_fd(), fd) }, fd);
+            unsafe { OwnedFd::from_raw_fd(fd) }
+        });
+
+        let error = sampler.sample().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotConnected);
+        drop(reused);
+    }
+}
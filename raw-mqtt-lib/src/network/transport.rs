@@ -1,43 +1,116 @@
 use std::error::Error;
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::net::SocketAddr;
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
 
-use quinn::{Endpoint, RecvStream, SendStream, TransportConfig};
+use log::{debug, info};
+use quinn::{Connection, Endpoint, EndpointConfig, RecvStream, SendStream, TransportConfig};
 
-use tokio::io::{split, ReadHalf, WriteHalf};
+use tokio::io::{ReadHalf, WriteHalf};
 use tokio::net::TcpStream;
 use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls;
 use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::HandshakeKind;
 use tokio_rustls::TlsConnector;
 
-use crate::network::server_verification::{QuinnSkipServerVerification, SkipServerVerification};
-
-#[derive(Debug, Copy, Clone)]
+use crate::network::key_log::KeyLogWriter;
+use crate::network::memory::MemoryConfig;
+use crate::network::proxy::Proxy;
+use crate::network::server_verification::{
+    Fingerprint, PinnedServerVerification, QuinnPinnedServerVerification,
+    QuinnSkipServerVerification, SkipServerVerification,
+};
+use crate::network::session::SessionInfo;
+use crate::network::socket::{self, AddressConfig, ConnectStrategy, SocketConfig};
+#[cfg(target_os = "linux")]
+use crate::network::stats::PathSampler;
+use crate::network::stats::{split_socket, SocketHalf};
+
+#[derive(Debug, Clone)]
 pub enum Transport {
     TCP(TcpConfig),
     TLS(TlsConfig),
     QUIC(QuicConfig),
+    /// In-memory pipe to a scripted mock peer (for tests)
+    Memory(MemoryConfig),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+impl FromStr for TlsVersion {
+    type Err = String;
+
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        match version {
+            "1.2" => Ok(TlsVersion::Tls12),
+            "1.3" => Ok(TlsVersion::Tls13),
+            _ => Err("Invalid TLS version".to_string()),
+        }
+    }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct TcpConfig {
     pub nagle: bool,
+    pub socket: SocketConfig,
+    pub address: AddressConfig,
+    pub proxy: Option<Proxy>,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct TlsConfig {
     pub insecure: bool,
+    /// Accepted server certificate or SPKI fingerprints, replacing CA verification
+    pub pins: Vec<Fingerprint>,
+    /// File where TLS secrets are logged (NSS key log format)
+    pub keylog: Option<PathBuf>,
+    /// Allowed TLS versions (defaults if empty, QUIC requires TLS 1.3)
+    pub versions: Vec<TlsVersion>,
+    /// Allowed cipher suites by name, e.g. `TLS13_AES_128_GCM_SHA256` (defaults if empty)
+    pub cipher_suites: Vec<String>,
+    /// Allowed key exchange groups by name, e.g. `X25519` (defaults if empty)
+    pub kx_groups: Vec<String>,
+    /// ALPN protocols offered to the server
+    pub alpn: Vec<String>,
     pub nagle: bool,
+    pub socket: SocketConfig,
+    pub address: AddressConfig,
+    pub proxy: Option<Proxy>,
 }
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct QuicConfig {
     pub insecure: bool,
+    /// Accepted server certificate or SPKI fingerprints, replacing CA verification
+    pub pins: Vec<Fingerprint>,
+    /// File where TLS secrets are logged (NSS key log format)
+    pub keylog: Option<PathBuf>,
+    /// Allowed TLS versions (defaults if empty, QUIC requires TLS 1.3)
+    pub versions: Vec<TlsVersion>,
+    /// Allowed cipher suites by name, e.g. `TLS13_AES_128_GCM_SHA256` (defaults if empty)
+    pub cipher_suites: Vec<String>,
+    /// Allowed key exchange groups by name, e.g. `X25519` (defaults if empty)
+    pub kx_groups: Vec<String>,
+    /// ALPN protocols offered to the server
+    pub alpn: Vec<String>,
+    pub address: AddressConfig,
 }
 
 impl Default for TcpConfig {
     fn default() -> TcpConfig {
-        TcpConfig { nagle: true }
+        TcpConfig {
+            nagle: true,
+            socket: SocketConfig::default(),
+            address: AddressConfig::default(),
+            proxy: None,
+        }
     }
 }
 
@@ -45,28 +118,28 @@ impl Default for TlsConfig {
     fn default() -> TlsConfig {
         TlsConfig {
             insecure: false,
+            pins: Vec::new(),
+            keylog: None,
+            versions: Vec::new(),
+            cipher_suites: Vec::new(),
+            kx_groups: Vec::new(),
+            alpn: Vec::new(),
             nagle: true,
+            socket: SocketConfig::default(),
+            address: AddressConfig::default(),
+            proxy: None,
         }
     }
 }
 
-impl Default for QuicConfig {
-    fn default() -> QuicConfig {
-        QuicConfig { insecure: false }
-    }
-}
-
 impl FromStr for Transport {
     type Err = String;
 
     fn from_str(transport: &str) -> Result<Transport, Self::Err> {
         match transport {
-            "tcp" => Ok(Transport::TCP(TcpConfig { nagle: true })),
-            "tls" => Ok(Transport::TLS(TlsConfig {
-                insecure: false,
-                nagle: true,
-            })),
-            "quic" => Ok(Transport::QUIC(QuicConfig { insecure: false })),
+            "tcp" => Ok(Transport::TCP(TcpConfig::default())),
+            "tls" => Ok(Transport::TLS(TlsConfig::default())),
+            "quic" => Ok(Transport::QUIC(QuicConfig::default())),
             _ => Err("Invalid transport protocol".to_string()),
         }
     }
@@ -74,44 +147,21 @@ impl FromStr for Transport {
 
 #[derive(Debug)]
 pub struct Quic {
+    pub(crate) connection: Connection,
     pub(crate) tx_stream: SendStream,
     pub(crate) rx_stream: RecvStream,
+    pub(crate) peer_addr: SocketAddr,
+    pub(crate) session: SessionInfo,
 }
 
 impl Quic {
     pub async fn new(
-        host: &String,
-        port: &String,
-        insecure: bool,
-        sever_name: &String,
+        host: &str,
+        port: &str,
+        config: &QuicConfig,
+        sever_name: &str,
     ) -> Result<Quic, Box<dyn Error>> {
-        // Set server certificate verification
-        let mut tls_config = if insecure {
-            // If insecure skip server verification
-            quinn_rustls::ClientConfig::builder()
-                .with_safe_defaults()
-                .with_custom_certificate_verifier(QuinnSkipServerVerification::new())
-                .with_no_client_auth()
-        } else {
-            let mut roots = quinn_rustls::RootCertStore::empty();
-            for root in
-                rustls_native_certs::load_native_certs().expect("Failed to load native certs")
-            {
-                roots
-                    .add(&quinn_rustls::Certificate(root.to_vec()))
-                    .expect("Failed to add root certificate");
-            }
-            quinn_rustls::ClientConfig::builder()
-                .with_safe_defaults()
-                .with_root_certificates(roots)
-                .with_no_client_auth()
-        };
-
-        // Set ALPN field
-        let mut alpn: Vec<Vec<u8>> = Vec::new();
-        alpn.push("mqtt".as_bytes().to_vec());
-        tls_config.alpn_protocols = alpn;
-
+        let tls_config = quic_tls_config(config)?;
         let mut client_config = quinn::ClientConfig::new(Arc::new(tls_config));
 
         // Disable unsupported feature segmentation offload
@@ -119,105 +169,357 @@ impl Quic {
         transport_config.enable_segmentation_offload(false);
         client_config.transport_config(Arc::new(transport_config));
 
-        let endpoint =
-            Endpoint::client("0.0.0.0:0".parse().unwrap()).expect("Failed to build endpoint");
+        // Parallel attempts cannot share a fixed UDP port, each one binds its own endpoint
+        let fixed_port = config.address.bind.is_some_and(|bind| bind.port() != 0);
+        if fixed_port && config.address.strategy == ConnectStrategy::HappyEyeballs {
+            Err("A fixed local port cannot be bound with the happy-eyeballs strategy over QUIC")?;
+        }
 
         // Resolve host address
-        let socket_addr: Vec<SocketAddr> = format!("{host}:{port}")
-            .to_socket_addrs()
-            .expect("Failed to resolve host")
-            .collect();
-
-        let connection = endpoint
-            .connect_with(client_config, socket_addr[0], sever_name.as_str())
-            .expect("Connection failed")
-            .await?;
+        let addrs = socket::resolve(host, port, &config.address).await?;
+
+        let (connection, peer_addr) = socket::connect_any(addrs, config.address.strategy, |addr| {
+            let client_config = client_config.clone();
+            async move {
+                // Bind an endpoint socket of the peer family (to a local address and/or
+                // interface if requested)
+                let endpoint = Endpoint::new(
+                    EndpointConfig::default(),
+                    None,
+                    socket::udp_socket(config.address.local_for(&addr), &config.address)?,
+                    Arc::new(quinn::TokioRuntime),
+                )?;
+                let connection = endpoint
+                    .connect_with(client_config, addr, sever_name)?
+                    .await?;
+                Ok::<Connection, Box<dyn Error + Send + Sync>>(connection)
+            }
+        })
+        .await
+        .map_err(|e| -> Box<dyn Error> { e })?;
+        info!("Connected to {peer_addr}");
+
+        // Record negotiated parameters (QUIC always runs TLS 1.3) and the server certificate
+        // chain (DER)
+        let alpn = connection
+            .handshake_data()
+            .and_then(|data| data.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
+            .and_then(|data| data.protocol);
+        let session = SessionInfo {
+            protocol_version: format!("{:?}", quinn_rustls::ProtocolVersion::TLSv1_3),
+            cipher_suite: None,
+            alpn: alpn.as_deref().map(alpn_name),
+            server_name: sni(sever_name),
+            resumed: None,
+            certificates: connection
+                .peer_identity()
+                .and_then(|identity| identity.downcast::<Vec<quinn_rustls::Certificate>>().ok())
+                .map(|chain| chain.into_iter().map(|cert| cert.0).collect())
+                .unwrap_or_default(),
+        };
+        debug!("Negotiated {session}");
 
         let (tx_stream, rx_stream) = connection.open_bi().await?;
 
+        // Keep the connection to read path statistics
         Ok(Quic {
+            connection,
             tx_stream,
             rx_stream,
+            peer_addr,
+            session,
         })
     }
 }
 
 #[derive(Debug)]
 pub struct Tcp {
-    pub(crate) rx_stream: ReadHalf<TcpStream>,
-    pub(crate) tx_stream: WriteHalf<TcpStream>,
+    pub(crate) rx_stream: SocketHalf<ReadHalf<TcpStream>>,
+    pub(crate) tx_stream: SocketHalf<WriteHalf<TcpStream>>,
+    #[cfg(target_os = "linux")]
+    pub(crate) sampler: PathSampler,
+    pub(crate) peer_addr: SocketAddr,
 }
 
 impl Tcp {
-    pub async fn new(host: &String, port: &String, nagle: bool) -> Result<Tcp, Box<dyn Error>> {
-        // Open connection
-        let tcp_stream = TcpStream::connect(format!("{host}:{port}")).await?;
-
-        // Enable/Disable Nagle's algorithm
-        tcp_stream
-            .set_nodelay(nagle == false)
-            .expect("Failed to set nodelay");
-
-        // Split into parse_packet and write halves
-        let (rx_stream, tx_stream) = split(tcp_stream);
+    pub async fn new(host: &str, port: &str, config: &TcpConfig) -> Result<Tcp, Box<dyn Error>> {
+        // Open connection (with socket options and Nagle's algorithm set)
+        let (tcp_stream, peer_addr) = socket::connect(
+            host,
+            port,
+            config.nagle,
+            &config.socket,
+            &config.address,
+            config.proxy.as_ref(),
+        )
+        .await?;
+
+        // Split into parse_packet and write halves, TCP_INFO is read through the socket they own
+        #[cfg(target_os = "linux")]
+        let ((rx_stream, tx_stream), sampler) = {
+            let fd = tcp_stream.as_raw_fd();
+            split_socket(tcp_stream, fd)
+        };
+        #[cfg(not(target_os = "linux"))]
+        let (rx_stream, tx_stream) = split_socket(tcp_stream);
 
         Ok(Tcp {
             rx_stream,
             tx_stream,
+            #[cfg(target_os = "linux")]
+            sampler,
+            peer_addr,
         })
     }
 }
 
 #[derive(Debug)]
 pub struct Tls {
-    pub(crate) rx_stream: ReadHalf<TlsStream<TcpStream>>,
-    pub(crate) tx_stream: WriteHalf<TlsStream<TcpStream>>,
+    pub(crate) rx_stream: SocketHalf<ReadHalf<TlsStream<TcpStream>>>,
+    pub(crate) tx_stream: SocketHalf<WriteHalf<TlsStream<TcpStream>>>,
+    #[cfg(target_os = "linux")]
+    pub(crate) sampler: PathSampler,
+    pub(crate) peer_addr: SocketAddr,
+    pub(crate) session: SessionInfo,
 }
 
 impl Tls {
     pub async fn new(
-        host: &String,
-        port: &String,
-        nagle: bool,
-        insecure: bool,
-        server_name: &String,
+        host: &str,
+        port: &str,
+        config: &TlsConfig,
+        server_name: &str,
     ) -> Result<Tls, Box<dyn Error>> {
-        let tcp_stream = TcpStream::connect(format!("{host}:{port}")).await?;
-
-        // Enable/Disable Nagle's algorithm
-        tcp_stream
-            .set_nodelay(nagle == false)
-            .expect("Failed to set nodelay");
-
-        // Set server certificate verification
-        let tls_client_config = if insecure {
-            // If insecure skip server verification
-            tokio_rustls::rustls::ClientConfig::builder()
-                .dangerous()
-                .with_custom_certificate_verifier(SkipServerVerification::new())
-                .with_no_client_auth()
-        } else {
-            let mut roots = tokio_rustls::rustls::RootCertStore::empty();
-            roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
-            tokio_rustls::rustls::ClientConfig::builder()
-                .with_root_certificates(roots)
-                .with_no_client_auth()
-        };
-
+        // Open connection (with socket options and Nagle's algorithm set)
+        let (tcp_stream, peer_addr) = socket::connect(
+            host,
+            port,
+            config.nagle,
+            &config.socket,
+            &config.address,
+            config.proxy.as_ref(),
+        )
+        .await?;
+
+        let tls_client_config = tls_client_config(config)?;
         let connector = TlsConnector::from(Arc::new(tls_client_config));
         let tls_stream = connector
-            .connect(
-                ServerName::try_from(server_name.as_str())?.to_owned(),
-                tcp_stream,
-            )
+            .connect(ServerName::try_from(server_name)?.to_owned(), tcp_stream)
             .await?;
 
-        // Split into parse_packet and write halves
-        let (rx_stream, tx_stream) = split(tls_stream);
+        // Record negotiated parameters and the server certificate chain (DER)
+        let connection = tls_stream.get_ref().1;
+        let session = SessionInfo {
+            protocol_version: format!("{:?}", connection.protocol_version().unwrap()),
+            cipher_suite: connection
+                .negotiated_cipher_suite()
+                .map(|suite| format!("{:?}", suite.suite())),
+            alpn: connection.alpn_protocol().map(alpn_name),
+            server_name: sni(server_name),
+            resumed: connection
+                .handshake_kind()
+                .map(|kind| kind == HandshakeKind::Resumed),
+            certificates: connection
+                .peer_certificates()
+                .map(|chain| chain.iter().map(|cert| cert.to_vec()).collect())
+                .unwrap_or_default(),
+        };
+        debug!("Negotiated {session}");
+
+        // Split into parse_packet and write halves, TCP_INFO is read through the socket they own
+        #[cfg(target_os = "linux")]
+        let ((rx_stream, tx_stream), sampler) = {
+            let fd = tls_stream.get_ref().0.as_raw_fd();
+            split_socket(tls_stream, fd)
+        };
+        #[cfg(not(target_os = "linux"))]
+        let (rx_stream, tx_stream) = split_socket(tls_stream);
 
         Ok(Tls {
             rx_stream,
             tx_stream,
+            #[cfg(target_os = "linux")]
+            sampler,
+            peer_addr,
+            session,
         })
     }
 }
+
+/// Build the rustls client configuration of TLS connections.
+fn tls_client_config(config: &TlsConfig) -> Result<rustls::ClientConfig, Box<dyn Error>> {
+    let default_provider = rustls::crypto::ring::default_provider();
+    let provider = rustls::crypto::CryptoProvider {
+        cipher_suites: select(
+            default_provider.cipher_suites.clone(),
+            &config.cipher_suites,
+            |suite| format!("{:?}", suite.suite()),
+        )?,
+        kx_groups: select(
+            default_provider.kx_groups.clone(),
+            &config.kx_groups,
+            |group| format!("{:?}", group.name()),
+        )?,
+        ..default_provider
+    };
+
+    let versions: Vec<&'static rustls::SupportedProtocolVersion> = if config.versions.is_empty() {
+        rustls::DEFAULT_VERSIONS.to_vec()
+    } else {
+        config
+            .versions
+            .iter()
+            .map(|version| match version {
+                TlsVersion::Tls12 => &rustls::version::TLS12,
+                TlsVersion::Tls13 => &rustls::version::TLS13,
+            })
+            .collect()
+    };
+
+    let builder = rustls::ClientConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(&versions)?;
+
+    // Set server certificate verification
+    let mut tls_client_config = if !config.pins.is_empty() {
+        // If pinned accept only the pinned certificates
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(PinnedServerVerification::new(config.pins.clone()))
+            .with_no_client_auth()
+    } else if config.insecure {
+        // If insecure skip server verification
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(SkipServerVerification::new())
+            .with_no_client_auth()
+    } else {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        builder.with_root_certificates(roots).with_no_client_auth()
+    };
+
+    // Log TLS secrets (if requested)
+    if let Some(ref keylog) = config.keylog {
+        tls_client_config.key_log = KeyLogWriter::open(keylog)?;
+    }
+
+    // Set ALPN field (if requested)
+    tls_client_config.alpn_protocols = config
+        .alpn
+        .iter()
+        .map(|protocol| protocol.as_bytes().to_vec())
+        .collect();
+
+    Ok(tls_client_config)
+}
+
+/// Build the rustls client configuration of QUIC connections.
+fn quic_tls_config(config: &QuicConfig) -> Result<quinn_rustls::ClientConfig, Box<dyn Error>> {
+    // QUIC requires TLS 1.3
+    if !config.versions.is_empty() && !config.versions.contains(&TlsVersion::Tls13) {
+        Err("QUIC requires TLS 1.3")?
+    }
+
+    let cipher_suites = select(
+        quinn_rustls::DEFAULT_CIPHER_SUITES.to_vec(),
+        &config.cipher_suites,
+        |suite| format!("{:?}", suite.suite()),
+    )?;
+    let kx_groups = select(
+        quinn_rustls::ALL_KX_GROUPS.to_vec(),
+        &config.kx_groups,
+        |group| format!("{:?}", group.name),
+    )?;
+
+    let builder = quinn_rustls::ClientConfig::builder()
+        .with_cipher_suites(&cipher_suites)
+        .with_kx_groups(&kx_groups)
+        .with_protocol_versions(&[&quinn_rustls::version::TLS13])?;
+
+    // Set server certificate verification
+    let mut tls_config = if !config.pins.is_empty() {
+        // If pinned accept only the pinned certificates
+        builder
+            .with_custom_certificate_verifier(QuinnPinnedServerVerification::new(
+                config.pins.clone(),
+            ))
+            .with_no_client_auth()
+    } else if config.insecure {
+        // If insecure skip server verification
+        builder
+            .with_custom_certificate_verifier(QuinnSkipServerVerification::new())
+            .with_no_client_auth()
+    } else {
+        let mut roots = quinn_rustls::RootCertStore::empty();
+        for root in rustls_native_certs::load_native_certs().expect("Failed to load native certs") {
+            roots
+                .add(&quinn_rustls::Certificate(root.to_vec()))
+                .expect("Failed to add root certificate");
+        }
+        builder.with_root_certificates(roots).with_no_client_auth()
+    };
+
+    // Log TLS secrets (if requested)
+    if let Some(ref keylog) = config.keylog {
+        tls_config.key_log = KeyLogWriter::open(keylog)?;
+    }
+
+    // Set ALPN field (mqtt by default)
+    tls_config.alpn_protocols = if config.alpn.is_empty() {
+        vec!["mqtt".as_bytes().to_vec()]
+    } else {
+        config
+            .alpn
+            .iter()
+            .map(|protocol| protocol.as_bytes().to_vec())
+            .collect()
+    };
+
+    Ok(tls_config)
+}
+
+/// Keep the `available` items whose name is listed in `names` (all of them if `names` is empty).
+fn select<T>(
+    available: Vec<T>,
+    names: &[String],
+    name: impl Fn(&T) -> String,
+) -> Result<Vec<T>, String> {
+    if names.is_empty() {
+        return Ok(available);
+    }
+
+    let mut available: Vec<(String, T)> = available
+        .into_iter()
+        .map(|item| (name(&item), item))
+        .collect();
+    let mut selected = Vec::new();
+    for requested in names {
+        match available
+            .iter()
+            .position(|(name, _)| name.eq_ignore_ascii_case(requested))
+        {
+            Some(index) => selected.push(available.remove(index).1),
+            None => Err(format!(
+                "Unsupported or duplicated {requested}, available: {}",
+                available
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))?,
+        }
+    }
+    Ok(selected)
+}
+
+fn alpn_name(protocol: &[u8]) -> String {
+    String::from_utf8_lossy(protocol).to_string()
+}
+
+/// Server name sent in the SNI extension, rustls does not send IP addresses.
+fn sni(server_name: &str) -> Option<String> {
+    match ServerName::try_from(server_name) {
+        Ok(ServerName::DnsName(_)) => Some(server_name.to_string()),
+        _ => None,
+    }
+}
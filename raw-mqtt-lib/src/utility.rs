@@ -1,2 +1,9 @@
 pub mod argument_parser;
+pub mod experiment;
+pub mod exporter;
+pub mod output;
+pub mod pacing;
+pub mod saturation;
 pub mod stream_argument_parser;
+pub mod summary;
+pub mod traffic;
@@ -1,4 +1,20 @@
 use clap::Parser;
+use log::info;
+use serde_json::{json, Map, Value};
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::network::proxy::Proxy;
+use crate::network::server_verification::{CertificateFingerprints, Fingerprint};
+use crate::network::session::SessionInfo;
+use crate::network::socket::{
+    AddressConfig, AddressFamily, ConnectStrategy, KeepaliveConfig, SocketConfig,
+};
+use crate::network::transport::{TlsVersion, Transport};
+use crate::utility::output::{Output, OutputFormat};
 
 const DEFAULT_HOST: &str = "127.0.0.1";
 const DEFAULT_PORT: u16 = 1883;
@@ -9,11 +25,23 @@ const DEFAULT_PROTO_VERSION: &str = "3.1.1";
 const DEFAULT_SERVER_NAME: &str = "localhost";
 const DEFAULT_INSECURE: bool = false;
 const DEFAULT_DEBUG: bool = false;
+const DEFAULT_CONNECT_STRATEGY: &str = "sequential";
+const DEFAULT_FORMAT: &str = "jsonl";
+const DEFAULT_PING_INTERVAL: u64 = 1000;
+const DEFAULT_PING_COUNT: usize = 0;
+const DEFAULT_PING_TIMEOUT: u64 = 5000;
 
 #[derive(Debug, Clone)]
 pub enum Request {
     Publish,
     Subscribe,
+    /// A PINGREQ every interval, `count` times (until interrupted if 0), each answered within
+    /// the timeout
+    Ping {
+        interval: Duration,
+        count: usize,
+        timeout: Duration,
+    },
 }
 
 #[derive(Parser)]
@@ -25,6 +53,9 @@ pub enum MqttCli {
 
     #[clap(alias = "sub")]
     Subscribe(SubscribeArgs),
+
+    /// Measure the round-trip time to the broker with PINGREQ and PINGRESP
+    Ping(PingArgs),
 }
 
 #[derive(clap::Args, Debug)]
@@ -57,8 +88,274 @@ pub struct Args {
     #[arg(long, default_value = DEFAULT_SERVER_NAME)]
     pub server_name: String,
 
+    /// Accept only a server whose certificate or SPKI matches this fingerprint (sha256:<hex>)
+    #[arg(long = "pin")]
+    pub pins: Vec<Fingerprint>,
+
+    /// Print the fingerprints of the certificate presented by the broker
+    #[arg(long)]
+    pub print_fingerprint: bool,
+
+    /// Print the negotiated TLS session (version, cipher suite, ALPN, SNI, certificate chain)
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Write the certificate chain presented by the broker to this file (PEM)
+    #[arg(long)]
+    pub dump_chain: Option<PathBuf>,
+
+    /// Allowed TLS version (1.2 or 1.3), repeatable
+    #[arg(long = "tls-version")]
+    pub tls_versions: Vec<TlsVersion>,
+
+    /// Allowed cipher suite (e.g. TLS13_AES_128_GCM_SHA256), repeatable
+    #[arg(long = "cipher-suite")]
+    pub cipher_suites: Vec<String>,
+
+    /// Allowed key exchange group (e.g. X25519, secp256r1), repeatable
+    #[arg(long = "kx-group")]
+    pub kx_groups: Vec<String>,
+
+    /// ALPN protocol offered to the broker (e.g. x-amzn-mqtt-ca), repeatable
+    #[arg(long)]
+    pub alpn: Vec<String>,
+
+    /// Log TLS secrets to this file for packet capture decryption (TLS and QUIC)
+    #[arg(long, env = "SSLKEYLOGFILE")]
+    pub keylog: Option<PathBuf>,
+
     #[arg(short, long, default_value_t = DEFAULT_DEBUG)]
     pub debug: bool,
+
+    /// Local address to bind (addr:port, port 0 picks one per connection), a fixed port cannot
+    /// be used with the happy-eyeballs strategy over QUIC
+    #[arg(long)]
+    pub bind: Option<SocketAddr>,
+
+    /// Network interface to bind to
+    #[arg(long)]
+    pub interface: Option<String>,
+
+    /// Use IPv4 addresses only
+    #[arg(short = '4', conflicts_with = "ipv6")]
+    pub ipv4: bool,
+
+    /// Use IPv6 addresses only
+    #[arg(short = '6')]
+    pub ipv6: bool,
+
+    /// How resolved addresses are tried: first, sequential or happy-eyeballs
+    #[arg(long, default_value = DEFAULT_CONNECT_STRATEGY)]
+    pub connect_strategy: ConnectStrategy,
+
+    #[command(flatten)]
+    pub socket_args: SocketArgs,
+}
+
+/// TCP socket options (TCP and TLS transports)
+#[derive(clap::Args, Debug)]
+#[clap(group(clap::ArgGroup::new("marking").args(&["dscp", "tos"])))]
+pub struct SocketArgs {
+    /// SO_SNDBUF size in bytes
+    #[arg(long)]
+    pub send_buffer: Option<usize>,
+
+    /// SO_RCVBUF size in bytes
+    #[arg(long)]
+    pub recv_buffer: Option<usize>,
+
+    /// TCP_NOTSENT_LOWAT threshold in bytes
+    #[arg(long)]
+    pub notsent_lowat: Option<u32>,
+
+    /// Congestion control algorithm (e.g. cubic, bbr)
+    #[arg(long)]
+    pub congestion: Option<String>,
+
+    /// Enable TCP keepalive with the given idle time in seconds
+    #[arg(long)]
+    pub keepalive_idle: Option<u64>,
+
+    /// TCP keepalive probe interval in seconds
+    #[arg(long, requires = "keepalive_idle")]
+    pub keepalive_interval: Option<u64>,
+
+    /// TCP keepalive probe count
+    #[arg(long, requires = "keepalive_idle")]
+    pub keepalive_retries: Option<u32>,
+
+    /// TCP_USER_TIMEOUT in milliseconds
+    #[arg(long)]
+    pub user_timeout: Option<u64>,
+
+    /// DSCP code point (0-63)
+    #[arg(long, group = "marking", value_parser = clap::value_parser!(u32).range(0..64))]
+    pub dscp: Option<u32>,
+
+    /// IP TOS byte (0-255)
+    #[arg(long, group = "marking", value_parser = clap::value_parser!(u32).range(0..256))]
+    pub tos: Option<u32>,
+
+    /// Connect timeout in milliseconds
+    #[arg(long)]
+    pub connect_timeout: Option<u64>,
+
+    /// Proxy URL (socks5://[user:password@]host:port or http://[user:password@]host:port)
+    #[arg(long)]
+    pub proxy: Option<Proxy>,
+}
+
+impl SocketArgs {
+    pub fn socket_config(&self) -> SocketConfig {
+        SocketConfig {
+            send_buffer: self.send_buffer,
+            recv_buffer: self.recv_buffer,
+            notsent_lowat: self.notsent_lowat,
+            congestion: self.congestion.clone(),
+            keepalive: self.keepalive_idle.map(|idle| KeepaliveConfig {
+                idle: Duration::from_secs(idle),
+                interval: self.keepalive_interval.map(Duration::from_secs),
+                retries: self.keepalive_retries,
+            }),
+            user_timeout: self.user_timeout.map(Duration::from_millis),
+            tos: self.dscp.map(|dscp| dscp << 2).or(self.tos),
+            connect_timeout: self.connect_timeout.map(Duration::from_millis),
+        }
+    }
+}
+
+impl Args {
+    /**
+     * Build the transport configuration from the command line arguments.
+     */
+    pub fn transport(&self) -> Result<Transport, String> {
+        let address = AddressConfig {
+            bind: self.bind,
+            interface: self.interface.clone(),
+            family: if self.ipv4 {
+                AddressFamily::V4
+            } else if self.ipv6 {
+                AddressFamily::V6
+            } else {
+                AddressFamily::Any
+            },
+            strategy: self.connect_strategy,
+        };
+
+        let mut transport = Transport::from_str(self.transport.as_str())?;
+        match transport {
+            Transport::TCP(ref mut config) => {
+                config.socket = self.socket_args.socket_config();
+                config.address = address;
+                config.proxy = self.socket_args.proxy.clone();
+            }
+            Transport::TLS(ref mut config) => {
+                config.insecure = self.insecure;
+                config.pins = self.pins.clone();
+                config.keylog = self.keylog.clone();
+                config.versions = self.tls_versions.clone();
+                config.cipher_suites = self.cipher_suites.clone();
+                config.kx_groups = self.kx_groups.clone();
+                config.alpn = self.alpn.clone();
+                config.socket = self.socket_args.socket_config();
+                config.address = address;
+                config.proxy = self.socket_args.proxy.clone();
+            }
+            Transport::QUIC(ref mut config) => {
+                config.insecure = self.insecure;
+                config.pins = self.pins.clone();
+                config.keylog = self.keylog.clone();
+                config.versions = self.tls_versions.clone();
+                config.cipher_suites = self.cipher_suites.clone();
+                config.kx_groups = self.kx_groups.clone();
+                config.alpn = self.alpn.clone();
+                config.address = address;
+            }
+            Transport::Memory(_) => {}
+        }
+        Ok(transport)
+    }
+
+    /**
+     * Connection parameters recorded with the run results.
+     */
+    pub fn parameters(&self) -> Map<String, Value> {
+        Map::from_iter([
+            ("host".to_string(), json!(self.host)),
+            ("port".to_string(), json!(self.port)),
+            ("transport".to_string(), json!(self.transport)),
+            ("topic".to_string(), json!(self.topic)),
+            ("qos".to_string(), json!(self.qos)),
+            ("keep_alive".to_string(), json!(self.keep_alive)),
+            ("proto_version".to_string(), json!(self.proto_version)),
+            ("server_name".to_string(), json!(self.server_name)),
+        ])
+    }
+
+    /**
+     * Report the session negotiated with the broker as requested: TLS parameters (verbose),
+     * fingerprints of the broker certificate and its chain saved to a file.
+     */
+    pub fn report_session(&self, session: Option<&SessionInfo>) -> Result<(), Box<dyn Error>> {
+        if self.verbose {
+            match session {
+                Some(session) => info!("TLS session: {session}"),
+                None => info!("No TLS session (plain TCP)"),
+            }
+        }
+        let certificates = session.map_or(&[][..], |session| &session.certificates);
+
+        // Print the fingerprints of the broker certificate (if requested)
+        if self.print_fingerprint {
+            match certificates.first() {
+                Some(leaf) => {
+                    let fingerprints = CertificateFingerprints::of(leaf)?;
+                    info!(
+                        "Server certificate fingerprint: {} (SPKI {})",
+                        fingerprints.certificate, fingerprints.spki
+                    );
+                }
+                None => info!("No server certificate presented"),
+            }
+        }
+
+        // Save the broker certificate chain (if requested)
+        if let Some(ref path) = self.dump_chain {
+            let pem = session.map(SessionInfo::chain_pem).unwrap_or_default();
+            std::fs::write(path, pem)?;
+            info!(
+                "Saved {} certificate(s) to {}",
+                certificates.len(),
+                path.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Structured results (connection events, message records and run summary)
+#[derive(clap::Args, Debug)]
+pub struct OutputArgs {
+    /// Write the connection events, message records, transport statistics (stream CLI) and run
+    /// summary to files in this directory
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+
+    /// Format of the output files: jsonl (JSON Lines), csv or parquet
+    #[arg(long, default_value = DEFAULT_FORMAT)]
+    pub format: OutputFormat,
+}
+
+impl OutputArgs {
+    /**
+     * Output directory, created if missing, none unless requested.
+     */
+    pub fn output(&self) -> std::io::Result<Option<Output>> {
+        self.output
+            .as_ref()
+            .map(|directory| Output::new(directory, self.format))
+            .transpose()
+    }
 }
 
 #[derive(clap::Args)]
@@ -76,6 +373,25 @@ pub struct PublishArgs {
 
     #[arg(long, group = "payload")]
     pub message: Option<String>,
+
+    #[command(flatten)]
+    pub output_args: OutputArgs,
+}
+
+impl PublishArgs {
+    /**
+     * Connection and message parameters recorded with the run results.
+     */
+    pub fn parameters(&self) -> Map<String, Value> {
+        let mut parameters = self.common_args.parameters();
+        parameters.insert(
+            "size".to_string(),
+            json!(self
+                .size
+                .or(self.message.as_ref().map(|message| message.len()))),
+        );
+        parameters
+    }
 }
 
 #[derive(clap::Args)]
@@ -83,4 +399,73 @@ pub struct PublishArgs {
 pub struct SubscribeArgs {
     #[command(flatten)]
     pub common_args: Args,
+
+    #[command(flatten)]
+    pub output_args: OutputArgs,
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+// No message is published
+#[command(mut_arg("topic", |topic| topic.required(false).default_value("").hide(true)))]
+#[command(mut_arg("qos", |qos| qos.hide(true)))]
+pub struct PingArgs {
+    #[command(flatten)]
+    pub common_args: Args,
+
+    /// Time between two pings in milliseconds, at most the keep-alive
+    #[arg(long, default_value_t = DEFAULT_PING_INTERVAL)]
+    pub interval: u64,
+
+    /// Pings to send (0 pings until interrupted)
+    #[arg(short, long, default_value_t = DEFAULT_PING_COUNT)]
+    pub count: usize,
+
+    /// Time to wait for each PINGRESP in milliseconds, the connection is closed past it
+    #[arg(long, default_value_t = DEFAULT_PING_TIMEOUT)]
+    pub timeout: u64,
+
+    #[command(flatten)]
+    pub output_args: OutputArgs,
+}
+
+impl PingArgs {
+    /**
+     * Ping request, checked against the keep-alive: the broker closes the connection if no
+     * packet comes within 1.5 times the keep-alive.
+     */
+    pub fn request(&self) -> Result<Request, String> {
+        let keep_alive = self.common_args.keep_alive;
+        if keep_alive > u16::MAX as u64 {
+            Err(format!("The keep-alive must not exceed {} s", u16::MAX))?;
+        }
+        if self.interval == 0 {
+            Err("The ping interval must be positive")?;
+        }
+        if keep_alive > 0 && self.interval > keep_alive * 1000 {
+            Err(format!(
+                "The ping interval must not exceed the keep-alive ({keep_alive} s)"
+            ))?;
+        }
+        Ok(Request::Ping {
+            interval: Duration::from_millis(self.interval),
+            count: self.count,
+            timeout: Duration::from_millis(self.timeout),
+        })
+    }
+
+    /**
+     * Connection and ping parameters recorded with the run results.
+     */
+    pub fn parameters(&self) -> Map<String, Value> {
+        let mut parameters = self.common_args.parameters();
+        parameters.remove("topic");
+        parameters.remove("qos");
+        parameters.extend([
+            ("interval".to_string(), json!(self.interval)),
+            ("count".to_string(), json!(self.count)),
+            ("timeout".to_string(), json!(self.timeout)),
+        ]);
+        parameters
+    }
 }
@@ -0,0 +1,269 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use toml::{Table, Value};
+
+/// Subcommand of every run
+const COMMAND: &str = "publish";
+
+fn default_repetitions() -> usize {
+    1
+}
+
+/**
+ * Experiment file (TOML). Every key of `broker`, `options` and `axes` is the long name of a
+ * publish option, e.g. `qos = 1` for `--qos=1` (`true` for flags, arrays for repeated options).
+ * Every axis lists the values swept, a table value sets several options together, e.g.
+ * `endpoint = [{ transport = "tcp", port = 1883 }, { transport = "quic", port = 14567 }]`.
+ */
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Experiment {
+    /// Directory of the results and manifest
+    pub output: PathBuf,
+    /// Runs of every combination
+    #[serde(default = "default_repetitions")]
+    pub repetitions: usize,
+    /// Leading period of every run left out of the results, in seconds
+    #[serde(default)]
+    pub warm_up: f64,
+    /// Pause between two runs, in seconds
+    #[serde(default)]
+    pub cool_down: f64,
+    /// Broker connection options
+    #[serde(default)]
+    pub broker: Table,
+    /// Options of every run
+    #[serde(default)]
+    pub options: Table,
+    /// Swept options, the first axis changes the slowest
+    #[serde(default)]
+    pub axes: Table,
+}
+
+/// One run of an experiment.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Run {
+    /// Position in the experiment, from 1
+    pub index: usize,
+    /// Repetition of the combination, from 1
+    pub repetition: usize,
+    /// Swept option values
+    pub parameters: Table,
+    /// Command line, without the program name
+    pub args: Vec<String>,
+}
+
+impl FromStr for Experiment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let experiment: Experiment =
+            toml::from_str(s).map_err(|e| format!("Invalid experiment: {e}"))?;
+
+        if experiment.repetitions == 0 {
+            return Err("At least one repetition is required".to_string());
+        }
+        if !(experiment.warm_up >= 0.0 && experiment.cool_down >= 0.0) {
+            return Err("The warm-up and cool-down must not be negative".to_string());
+        }
+        for (axis, values) in &experiment.axes {
+            match values.as_array() {
+                Some(values) if !values.is_empty() => {}
+                _ => return Err(format!("Axis {axis} must be a non-empty array")),
+            }
+        }
+
+        Ok(experiment)
+    }
+}
+
+impl Experiment {
+    pub fn load(path: &Path) -> Result<Experiment, String> {
+        std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read {}: {e}", path.display()))?
+            .parse()
+    }
+
+    /// Every run in order: the whole cartesian product of the axes, once per repetition.
+    pub fn runs(&self) -> Result<Vec<Run>, String> {
+        let mut names = HashSet::new();
+        let mut fixed = Vec::new();
+        for (name, value) in self.broker.iter().chain(&self.options) {
+            if !names.insert(name.as_str()) {
+                return Err(format!("Option {name} set twice"));
+            }
+            push_option(&mut fixed, name, value)?;
+        }
+        if self.warm_up > 0.0 {
+            if !names.insert("warm_up") {
+                return Err("Option warm_up set twice".to_string());
+            }
+            push_option(&mut fixed, "warm_up", &Value::Float(self.warm_up))?;
+        }
+
+        let mut combinations = vec![Table::new()];
+        for (axis, values) in &self.axes {
+            let values = values.as_array().map_or(&[][..], Vec::as_slice);
+            let mut expanded = Vec::with_capacity(combinations.len() * values.len());
+            for combination in &combinations {
+                for value in values {
+                    let mut combination = combination.clone();
+                    let options = match value {
+                        Value::Table(options) => options.clone(),
+                        value => Table::from_iter([(axis.clone(), value.clone())]),
+                    };
+                    for (name, value) in options {
+                        if names.contains(name.as_str()) || combination.contains_key(&name) {
+                            return Err(format!("Option {name} set twice"));
+                        }
+                        combination.insert(name, value);
+                    }
+                    expanded.push(combination);
+                }
+            }
+            combinations = expanded;
+        }
+
+        let mut runs = Vec::with_capacity(combinations.len() * self.repetitions);
+        for repetition in 1..=self.repetitions {
+            for parameters in &combinations {
+                let mut args = vec![COMMAND.to_string()];
+                args.extend(fixed.iter().cloned());
+                for (name, value) in parameters {
+                    push_option(&mut args, name, value)?;
+                }
+                runs.push(Run {
+                    index: runs.len() + 1,
+                    repetition,
+                    parameters: parameters.clone(),
+                    args,
+                });
+            }
+        }
+        Ok(runs)
+    }
+}
+
+/// Command line form of an option: `--name=value`, `--name` for a set flag, once per element of
+/// an array.
+fn push_option(args: &mut Vec<String>, name: &str, value: &Value) -> Result<(), String> {
+    let option = format!("--{}", name.replace('_', "-"));
+    match value {
+        Value::String(value) => args.push(format!("{option}={value}")),
+        Value::Integer(value) => args.push(format!("{option}={value}")),
+        Value::Float(value) => args.push(format!("{option}={value}")),
+        Value::Boolean(true) => args.push(option),
+        Value::Boolean(false) => {}
+        Value::Array(values) => {
+            for value in values {
+                if value.is_array() || value.is_table() {
+                    return Err(format!("Option {name} takes a list of plain values"));
+                }
+                push_option(args, name, value)?;
+            }
+        }
+        Value::Table(_) | Value::Datetime(_) => {
+            return Err(format!("Unsupported value for option {name}"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPERIMENT: &str = r#"
+        output = "results/sweep"
+        repetitions = 2
+        warm_up = 1.5
+        cool_down = 5
+
+        [broker]
+        host = "10.0.0.1"
+        insecure = true
+
+        [options]
+        topic = "bench/{id}"
+        nagle_off = false
+        pin = ["sha256:aa", "sha256:bb"]
+
+        [axes]
+        endpoint = [{ transport = "tcp", port = 1883 }, { transport = "quic", port = 14567 }]
+        qos = [0, 1]
+        queue = [-1, 1024]
+    "#;
+
+    #[test]
+    fn expand_runs() {
+        let experiment: Experiment = EXPERIMENT.parse().unwrap();
+        assert_eq!(experiment.output, PathBuf::from("results/sweep"));
+        assert_eq!(experiment.cool_down, 5.0);
+
+        let runs = experiment.runs().unwrap();
+        assert_eq!(runs.len(), 2 * 2 * 2 * 2);
+        assert_eq!(
+            runs[0].args,
+            [
+                "publish",
+                "--host=10.0.0.1",
+                "--insecure",
+                "--topic=bench/{id}",
+                "--pin=sha256:aa",
+                "--pin=sha256:bb",
+                "--warm-up=1.5",
+                "--transport=tcp",
+                "--port=1883",
+                "--qos=0",
+                "--queue=-1",
+            ]
+        );
+
+        // The last axis changes the fastest, the repetitions come last
+        let parameters: Vec<String> = runs[..8]
+            .iter()
+            .map(|run| run.args[8..].join(" "))
+            .collect();
+        assert_eq!(parameters[1], "--port=1883 --qos=0 --queue=1024");
+        assert_eq!(parameters[2], "--port=1883 --qos=1 --queue=-1");
+        assert_eq!(parameters[4], "--port=14567 --qos=0 --queue=-1");
+        assert_eq!(runs[8].args, runs[0].args);
+        assert_eq!((runs[8].index, runs[8].repetition), (9, 2));
+        assert_eq!(runs[5].parameters["transport"].as_str(), Some("quic"));
+    }
+
+    #[test]
+    fn defaults() {
+        let experiment: Experiment = "output = \"out\"".parse().unwrap();
+        let runs = experiment.runs().unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].args, ["publish"]);
+        assert!(runs[0].parameters.is_empty());
+    }
+
+    #[test]
+    fn invalid_experiments() {
+        assert!("repetitions = 1".parse::<Experiment>().is_err());
+        assert!("output = \"out\"\nrate = 10".parse::<Experiment>().is_err());
+        assert!("output = \"out\"\nrepetitions = 0"
+            .parse::<Experiment>()
+            .is_err());
+        assert!("output = \"out\"\n[axes]\nqos = 1"
+            .parse::<Experiment>()
+            .is_err());
+        assert!("output = \"out\"\n[axes]\nqos = []"
+            .parse::<Experiment>()
+            .is_err());
+
+        let twice: Experiment = "output = \"out\"\n[options]\nqos = 1\n[axes]\nqos = [0, 1]"
+            .parse()
+            .unwrap();
+        assert_eq!(twice.runs().unwrap_err(), "Option qos set twice");
+        let nested: Experiment = "output = \"out\"\n[options]\nsize = { min = 1 }"
+            .parse()
+            .unwrap();
+        assert!(nested.runs().is_err());
+    }
+}
@@ -0,0 +1,302 @@
+use log::debug;
+use std::fmt::Write;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::client::metrics::{ClientMetrics, MetricsSnapshot};
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+/// Upper bounds of the ack latency buckets in seconds
+const LATENCY_BUCKETS: [f64; 16] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+    5.0, 10.0,
+];
+/// Longest request line and headers read before answering
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// Clients exposed by the exporter, registered as they are created.
+#[derive(Debug, Clone, Default)]
+pub struct MetricsRegistry {
+    clients: Arc<Mutex<Vec<(String, ClientMetrics)>>>,
+}
+
+impl MetricsRegistry {
+    pub fn new() -> MetricsRegistry {
+        MetricsRegistry::default()
+    }
+
+    /// Expose the metrics of a client under the `client` label.
+    pub fn register(&self, client: impl Into<String>, metrics: ClientMetrics) {
+        self.clients.lock().unwrap().push((client.into(), metrics));
+    }
+
+    /// Current metrics of every client, in registration order.
+    pub fn snapshots(&self) -> Vec<(String, MetricsSnapshot)> {
+        self.clients
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(client, metrics)| (client.clone(), metrics.snapshot()))
+            .collect()
+    }
+
+    /// Current metrics of every client in OpenMetrics text format.
+    pub fn render(&self) -> String {
+        let snapshots: Vec<(String, MetricsSnapshot)> = self
+            .snapshots()
+            .into_iter()
+            .map(|(client, snapshot)| (escape(&client), snapshot))
+            .collect();
+
+        let mut text = String::new();
+        let counters: [(&str, &str, Value); 6] = [
+            ("messages_sent", "Messages handed to the transport.", |s| {
+                Some(s.sent.to_string())
+            }),
+            ("messages_acked", "PUBACK and PUBREC received.", |s| {
+                Some(s.acked.to_string())
+            }),
+            ("messages_dropped", "Messages skipped by the pacer.", |s| {
+                Some(s.dropped.to_string())
+            }),
+            (
+                "messages_replaced",
+                "Messages replaced in the send queue before being sent.",
+                |s| Some(s.replaced.to_string()),
+            ),
+            (
+                "messages_lost",
+                "Acks and reflected messages given up.",
+                |s| Some(s.lost.to_string()),
+            ),
+            ("reconnects", "Connections after the first one.", |s| {
+                Some(s.reconnects.to_string())
+            }),
+        ];
+        for (name, help, value) in counters {
+            family(&mut text, name, "counter", help);
+            samples(&mut text, &format!("{name}_total"), &snapshots, value);
+        }
+
+        let gauges: [(&str, &str, Value); 3] = [
+            ("queue_depth", "Messages waiting in the send queue.", |s| {
+                Some(s.queued.to_string())
+            }),
+            (
+                "in_flight",
+                "Acks and reflected messages still expected.",
+                |s| Some(s.in_flight.to_string()),
+            ),
+            (
+                "transport_rtt_seconds",
+                "Smoothed round-trip time reported by the transport.",
+                |s| s.transport_rtt.map(|rtt| rtt.as_secs_f64().to_string()),
+            ),
+        ];
+        for (name, help, value) in gauges {
+            family(&mut text, name, "gauge", help);
+            samples(&mut text, name, &snapshots, value);
+        }
+
+        family(
+            &mut text,
+            "ack_latency_seconds",
+            "histogram",
+            "Time from publishing to the PUBACK or PUBREC.",
+        );
+        for (client, snapshot) in &snapshots {
+            let histogram = &snapshot.ack_latency;
+            for bound in LATENCY_BUCKETS {
+                let _ = writeln!(
+                    text,
+                    "raw_mqtt_ack_latency_seconds_bucket{{client=\"{client}\",le=\"{bound:?}\"}} {}",
+                    histogram.count_up_to(Duration::from_secs_f64(bound))
+                );
+            }
+            let _ = writeln!(
+                text,
+                "raw_mqtt_ack_latency_seconds_bucket{{client=\"{client}\",le=\"+Inf\"}} {}",
+                histogram.len()
+            );
+            let _ = writeln!(
+                text,
+                "raw_mqtt_ack_latency_seconds_count{{client=\"{client}\"}} {}",
+                histogram.len()
+            );
+            let _ = writeln!(
+                text,
+                "raw_mqtt_ack_latency_seconds_sum{{client=\"{client}\"}} {}",
+                histogram.sum().as_secs_f64()
+            );
+        }
+
+        text.push_str("# EOF\n");
+        text
+    }
+
+    /// Serve the metrics over HTTP at `/metrics` until the runtime stops, returning the address
+    /// actually bound (e.g. with port 0).
+    pub async fn serve(&self, address: SocketAddr) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(address).await?;
+        let address = listener.local_addr()?;
+        let registry = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let registry = registry.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = registry.answer(stream).await {
+                                debug!("Failed to answer metrics request: {e}");
+                            }
+                        });
+                    }
+                    Err(e) => debug!("Failed to accept metrics connection: {e}"),
+                }
+            }
+        });
+        Ok(address)
+    }
+
+    /// Answer a single request, then close the connection.
+    async fn answer(&self, stream: TcpStream) -> io::Result<()> {
+        // Bounded, even a request line without end
+        let mut reader = BufReader::new(stream.take(MAX_REQUEST_SIZE as u64));
+        let mut request = String::new();
+        reader.read_line(&mut request).await?;
+        // Skip the headers
+        loop {
+            let mut header = String::new();
+            let size = reader.read_line(&mut header).await?;
+            if size == 0 || header.trim_end().is_empty() {
+                break;
+            }
+        }
+
+        let mut parts = request.split_whitespace();
+        let response = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => {
+                let body = self.render();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: {CONTENT_TYPE}\r\nContent-Length: {}\r\n\
+                     Connection: close\r\n\r\n{body}",
+                    body.len()
+                )
+            }
+            _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                .to_string(),
+        };
+        let mut stream = reader.into_inner().into_inner();
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }
+}
+
+/// Value of a metric for a client, none if unknown.
+type Value = fn(&MetricsSnapshot) -> Option<String>;
+
+/// One sample per client, skipping unknown values.
+fn samples(text: &mut String, name: &str, snapshots: &[(String, MetricsSnapshot)], value: Value) {
+    for (client, snapshot) in snapshots {
+        if let Some(value) = value(snapshot) {
+            let _ = writeln!(text, "raw_mqtt_{name}{{client=\"{client}\"}} {value}");
+        }
+    }
+}
+
+/// Metadata of a metric family.
+fn family(text: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(text, "# HELP raw_mqtt_{name} {help}");
+    let _ = writeln!(text, "# TYPE raw_mqtt_{name} {kind}");
+}
+
+/// Label value with backslashes, quotes and line feeds escaped.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::metrics::Counters;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::AsyncReadExt;
+
+    fn metrics() -> ClientMetrics {
+        let counters = Counters::default();
+        counters.sent.store(10, Ordering::SeqCst);
+        counters.acked.store(8, Ordering::SeqCst);
+        counters.connects.store(2, Ordering::SeqCst);
+        {
+            let mut latency = counters.ack_latency.lock().unwrap();
+            latency.record(Duration::from_micros(200));
+            latency.record(Duration::from_millis(20));
+        }
+        ClientMetrics {
+            counters: Arc::new(counters),
+            queued: Arc::new(AtomicUsize::new(3)),
+            pending: Arc::new(AtomicUsize::new(2)),
+        }
+    }
+
+    #[test]
+    fn render_openmetrics() {
+        let registry = MetricsRegistry::new();
+        registry.register("0", metrics());
+        registry.register("a\"b", ClientMetrics::default());
+        let text = registry.render();
+        let lines: Vec<&str> = text.lines().collect();
+
+        for line in [
+            "# TYPE raw_mqtt_messages_sent counter",
+            "raw_mqtt_messages_sent_total{client=\"0\"} 10",
+            "raw_mqtt_messages_acked_total{client=\"0\"} 8",
+            "raw_mqtt_reconnects_total{client=\"0\"} 1",
+            "raw_mqtt_reconnects_total{client=\"a\\\"b\"} 0",
+            "raw_mqtt_queue_depth{client=\"0\"} 3",
+            "raw_mqtt_in_flight{client=\"0\"} 2",
+            "# TYPE raw_mqtt_ack_latency_seconds histogram",
+            "raw_mqtt_ack_latency_seconds_bucket{client=\"0\",le=\"0.0001\"} 0",
+            "raw_mqtt_ack_latency_seconds_bucket{client=\"0\",le=\"0.00025\"} 1",
+            "raw_mqtt_ack_latency_seconds_bucket{client=\"0\",le=\"0.025\"} 2",
+            "raw_mqtt_ack_latency_seconds_bucket{client=\"0\",le=\"+Inf\"} 2",
+            "raw_mqtt_ack_latency_seconds_count{client=\"0\"} 2",
+        ] {
+            assert!(lines.contains(&line), "{line} missing from\n{text}");
+        }
+        assert!(!text.contains("raw_mqtt_transport_rtt_seconds{"));
+        assert_eq!(lines.last(), Some(&"# EOF"));
+    }
+
+    #[tokio::test]
+    async fn serve_metrics() {
+        let registry = MetricsRegistry::new();
+        registry.register("0", metrics());
+        let address = registry
+            .serve("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+
+        let get = |path: &'static str| async move {
+            let mut stream = TcpStream::connect(address).await.unwrap();
+            let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let response = get("/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains(CONTENT_TYPE));
+        assert!(response.ends_with("# EOF\n"));
+        assert!(get("/").await.starts_with("HTTP/1.1 404"));
+    }
+}
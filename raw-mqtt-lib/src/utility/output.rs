@@ -0,0 +1,814 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use parquet::basic::{ConvertedType, Repetition, Type as PhysicalType};
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::types::Type;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::client::rtt;
+use crate::network::server_verification::CertificateFingerprints;
+use crate::network::session::SessionInfo;
+use crate::network::stats::{StatsSample, TransportStats};
+use crate::utility::pacing::PacingRecord;
+
+/// File format of the structured results.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum OutputFormat {
+    /// One JSON object per line, nested like the records
+    #[default]
+    JsonLines,
+    /// Comma-separated values, nested fields flattened to `parent.field` columns
+    Csv,
+    /// Apache Parquet, with the columns of the CSV format
+    Parquet,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "jsonl" => Ok(OutputFormat::JsonLines),
+            "csv" => Ok(OutputFormat::Csv),
+            "parquet" => Ok(OutputFormat::Parquet),
+            _ => Err(format!(
+                "Unknown output format {s}, expected jsonl, csv or parquet"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::JsonLines => "jsonl",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Parquet => "parquet",
+        }
+    }
+}
+
+/// Structured results of a run: one file per table (e.g. `events.csv`) in a directory.
+#[derive(Debug, Clone)]
+pub struct Output {
+    directory: PathBuf,
+    format: OutputFormat,
+}
+
+impl Output {
+    pub fn new(directory: &Path, format: OutputFormat) -> std::io::Result<Output> {
+        std::fs::create_dir_all(directory)?;
+        Ok(Output {
+            directory: directory.to_path_buf(),
+            format,
+        })
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /**
+     * Write `records` to the `name` table, replacing it. Tables without records are not written
+     * (their columns are unknown), the path of the file otherwise.
+     */
+    pub fn write<T: Serialize>(
+        &self,
+        name: &str,
+        records: &[T],
+    ) -> Result<Option<PathBuf>, Box<dyn Error>> {
+        if records.is_empty() {
+            return Ok(None);
+        }
+
+        let path = self
+            .directory
+            .join(format!("{name}.{}", self.format.extension()));
+        let mut file = BufWriter::new(File::create(&path)?);
+        match self.format {
+            OutputFormat::JsonLines => {
+                for record in records {
+                    serde_json::to_writer(&mut file, record)?;
+                    file.write_all(b"\n")?;
+                }
+            }
+            OutputFormat::Csv => write_csv(&mut file, &Table::of(records)?)?,
+            OutputFormat::Parquet => write_parquet(&mut file, &Table::of(records)?)?,
+        }
+        file.flush()?;
+        Ok(Some(path))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionEventKind {
+    Connected,
+    Disconnected,
+}
+
+/// Connection or disconnection of a client.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionEvent {
+    /// Nanoseconds since the Unix epoch, once done
+    pub timestamp_ns: u64,
+    pub client: usize,
+    pub client_id: String,
+    pub event: ConnectionEventKind,
+    /// Time taken in nanoseconds (transport handshakes and CONNACK when connecting)
+    pub duration_ns: u64,
+    /// Transport and TLS session, on connection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<TransportSession>,
+}
+
+impl ConnectionEvent {
+    /// Client connected just now, after `duration`.
+    pub fn connected(
+        client: usize,
+        client_id: &str,
+        duration: Duration,
+        session: TransportSession,
+    ) -> ConnectionEvent {
+        ConnectionEvent {
+            timestamp_ns: rtt::timestamp() as u64,
+            client,
+            client_id: client_id.to_string(),
+            event: ConnectionEventKind::Connected,
+            duration_ns: duration.as_nanos() as u64,
+            session: Some(session),
+        }
+    }
+
+    /// Client disconnected just now, after `duration`.
+    pub fn disconnected(client: usize, client_id: &str, duration: Duration) -> ConnectionEvent {
+        ConnectionEvent {
+            timestamp_ns: rtt::timestamp() as u64,
+            client,
+            client_id: client_id.to_string(),
+            event: ConnectionEventKind::Disconnected,
+            duration_ns: duration.as_nanos() as u64,
+            session: None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageEvent {
+    Publish,
+    /// PUBACK or PUBREC received
+    Ack,
+    /// Message back from a reflector (RTT mode)
+    Reflect,
+    /// Message received by a subscriber
+    Deliver,
+    /// PINGRESP received (ping)
+    Ping,
+}
+
+/// A published message, or the ack, reflection or delivery of one, or a ping.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub struct MessageRecord {
+    pub client: usize,
+    pub event: MessageEvent,
+    /// Nanoseconds since the Unix epoch
+    pub timestamp_ns: u64,
+    /// Send time planned by the traffic model, in nanoseconds since the start of the client
+    pub scheduled_ns: Option<u64>,
+    /// Actual minus planned send time, in nanoseconds
+    pub lateness_ns: Option<i64>,
+    /// From publishing to the ack, reflection or delivery, in nanoseconds
+    pub latency_ns: Option<u64>,
+}
+
+impl MessageRecord {
+    /// Publish of a single message at `timestamp` (Unix epoch, ns).
+    pub fn published(client: usize, timestamp: u128) -> MessageRecord {
+        MessageRecord {
+            client,
+            event: MessageEvent::Publish,
+            timestamp_ns: timestamp as u64,
+            scheduled_ns: None,
+            lateness_ns: None,
+            latency_ns: None,
+        }
+    }
+
+    /// Message delivered on a subscription at `timestamp` (Unix epoch, ns), its latency known if
+    /// the payload starts with the send timestamp.
+    pub fn delivered(client: usize, payload: &[u8], timestamp: u128) -> MessageRecord {
+        MessageRecord {
+            client,
+            event: MessageEvent::Deliver,
+            timestamp_ns: timestamp as u64,
+            scheduled_ns: None,
+            lateness_ns: None,
+            latency_ns: rtt::sent_timestamp(payload)
+                .map(|sent| timestamp.saturating_sub(sent) as u64),
+        }
+    }
+
+    /// Publish of a paced message, its send times relative to `started` (Unix epoch, ns).
+    pub fn paced(client: usize, started: u128, record: &PacingRecord) -> MessageRecord {
+        MessageRecord {
+            client,
+            event: MessageEvent::Publish,
+            timestamp_ns: (started + record.actual.as_nanos()) as u64,
+            scheduled_ns: Some(record.scheduled.as_nanos() as u64),
+            lateness_ns: Some(record.lateness() as i64),
+            latency_ns: None,
+        }
+    }
+
+    /// Ack, reflection or delivery received at `timestamp` (Unix epoch, ns).
+    pub fn received(
+        client: usize,
+        event: MessageEvent,
+        timestamp: u128,
+        latency: Duration,
+    ) -> MessageRecord {
+        MessageRecord {
+            client,
+            event,
+            timestamp_ns: timestamp as u64,
+            scheduled_ns: None,
+            lateness_ns: None,
+            latency_ns: Some(latency.as_nanos() as u64),
+        }
+    }
+}
+
+/// Transport statistics sample of a client, TCP (`TCP_INFO`) or QUIC fields left empty for the
+/// other transport.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub struct StatsRecord {
+    pub client: usize,
+    /// Nanoseconds since the Unix epoch
+    pub timestamp_ns: u64,
+    /// Smoothed round-trip time, in nanoseconds
+    pub rtt_ns: u64,
+    pub rttvar_ns: Option<u64>,
+    /// Congestion window, in segments (TCP) or bytes (QUIC)
+    pub cwnd: u64,
+    pub retransmits: Option<u32>,
+    pub lost_packets: Option<u64>,
+    pub sent_bytes: u64,
+    pub recv_bytes: u64,
+}
+
+impl StatsRecord {
+    pub fn new(client: usize, sample: &StatsSample) -> StatsRecord {
+        let mut record = StatsRecord {
+            client,
+            timestamp_ns: sample.timestamp as u64,
+            rtt_ns: 0,
+            rttvar_ns: None,
+            cwnd: 0,
+            retransmits: None,
+            lost_packets: None,
+            sent_bytes: 0,
+            recv_bytes: 0,
+        };
+        match sample.stats {
+            TransportStats::Tcp(tcp) => {
+                record.rtt_ns = tcp.rtt.as_nanos() as u64;
+                record.rttvar_ns = Some(tcp.rttvar.as_nanos() as u64);
+                record.cwnd = tcp.cwnd as u64;
+                record.retransmits = Some(tcp.retransmits);
+                record.sent_bytes = tcp.sent_bytes;
+                record.recv_bytes = tcp.recv_bytes;
+            }
+            TransportStats::Quic(quic) => {
+                record.rtt_ns = quic.rtt.as_nanos() as u64;
+                record.cwnd = quic.cwnd;
+                record.lost_packets = Some(quic.lost_packets);
+                record.sent_bytes = quic.sent_bytes;
+                record.recv_bytes = quic.recv_bytes;
+            }
+        }
+        record
+    }
+}
+
+/// Transport of a connection and the TLS session negotiated over it (TLS and QUIC).
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TransportSession {
+    pub transport: String,
+    pub peer: Option<String>,
+    pub tls_version: Option<String>,
+    pub cipher_suite: Option<String>,
+    pub alpn: Option<String>,
+    pub server_name: Option<String>,
+    /// Whether the TLS session was resumed, none if not reported (QUIC)
+    pub resumed: Option<bool>,
+    pub certificates: usize,
+    /// Fingerprints of the leaf certificate and its public key
+    pub certificate: Option<String>,
+    pub spki: Option<String>,
+}
+
+impl TransportSession {
+    pub fn new(
+        transport: &str,
+        peer: Option<SocketAddr>,
+        session: Option<&SessionInfo>,
+    ) -> TransportSession {
+        let mut transport_session = TransportSession {
+            transport: transport.to_string(),
+            peer: peer.map(|peer| peer.to_string()),
+            ..TransportSession::default()
+        };
+        let Some(session) = session else {
+            return transport_session;
+        };
+
+        transport_session.tls_version = Some(session.protocol_version.clone());
+        transport_session.cipher_suite = session.cipher_suite.clone();
+        transport_session.alpn = session.alpn.clone();
+        transport_session.server_name = session.server_name.clone();
+        transport_session.resumed = session.resumed;
+        transport_session.certificates = session.certificates.len();
+        if let Some(Ok(fingerprints)) = session
+            .certificates
+            .first()
+            .map(|leaf| CertificateFingerprints::of(leaf))
+        {
+            transport_session.certificate = Some(fingerprints.certificate.to_string());
+            transport_session.spki = Some(fingerprints.spki.to_string());
+        }
+        transport_session
+    }
+}
+
+/// Machine the run was made from.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HostInfo {
+    pub hostname: Option<String>,
+    pub os: &'static str,
+    pub kernel: Option<String>,
+    pub arch: &'static str,
+    pub cpus: usize,
+}
+
+impl HostInfo {
+    pub fn current() -> HostInfo {
+        let (hostname, kernel) = uname();
+        HostInfo {
+            hostname,
+            os: std::env::consts::OS,
+            kernel,
+            arch: std::env::consts::ARCH,
+            cpus: std::thread::available_parallelism().map_or(1, usize::from),
+        }
+    }
+}
+
+/// Node name and kernel release.
+#[cfg(unix)]
+fn uname() -> (Option<String>, Option<String>) {
+    let mut name: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut name) } != 0 {
+        return (None, None);
+    }
+    let field = |field: &[libc::c_char]| {
+        let bytes: Vec<u8> = field
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as u8)
+            .collect();
+        Some(String::from_utf8_lossy(&bytes).into_owned())
+    };
+    (field(&name.nodename), field(&name.release))
+}
+
+#[cfg(not(unix))]
+fn uname() -> (Option<String>, Option<String>) {
+    (None, None)
+}
+
+/// Summary of a run: its command line, parameters, host and session, followed by the results.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunRecord<T> {
+    pub tool: &'static str,
+    pub version: &'static str,
+    pub command_line: String,
+    /// Start and end of the run (RFC 3339)
+    pub started: String,
+    pub finished: String,
+    pub host: HostInfo,
+    pub parameters: Map<String, Value>,
+    /// Session of the first connection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<TransportSession>,
+    #[serde(flatten)]
+    pub results: T,
+}
+
+impl<T> RunRecord<T> {
+    /// Run of the current process started at `started`, finished now.
+    pub fn new(
+        tool: &'static str,
+        version: &'static str,
+        parameters: Map<String, Value>,
+        started: DateTime<Utc>,
+        session: Option<TransportSession>,
+        results: T,
+    ) -> RunRecord<T> {
+        RunRecord {
+            tool,
+            version,
+            command_line: std::env::args().collect::<Vec<_>>().join(" "),
+            started: started.to_rfc3339_opts(SecondsFormat::Nanos, true),
+            finished: Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true),
+            host: HostInfo::current(),
+            parameters,
+            session,
+            results,
+        }
+    }
+}
+
+/// Records flattened to named columns, nested fields as `parent.field` and arrays as JSON.
+struct Table {
+    columns: Vec<String>,
+    rows: Vec<Map<String, Value>>,
+}
+
+impl Table {
+    fn of<T: Serialize>(records: &[T]) -> Result<Table, serde_json::Error> {
+        let mut columns = Vec::new();
+        let mut known = HashSet::new();
+        let mut rows = Vec::with_capacity(records.len());
+        for record in records {
+            let mut row = Map::new();
+            flatten("", serde_json::to_value(record)?, &mut row);
+            // Columns in order of appearance, some records may lack fields
+            for column in row.keys() {
+                if known.insert(column.clone()) {
+                    columns.push(column.clone());
+                }
+            }
+            rows.push(row);
+        }
+        Ok(Table { columns, rows })
+    }
+
+    /// Values of a column, null where missing.
+    fn column<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Value> + 'a {
+        self.rows
+            .iter()
+            .map(move |row| row.get(name).unwrap_or(&Value::Null))
+    }
+}
+
+fn flatten(prefix: &str, value: Value, row: &mut Map<String, Value>) {
+    match value {
+        Value::Object(fields) => {
+            for (name, value) in fields {
+                let name = match prefix {
+                    "" => name,
+                    prefix => format!("{prefix}.{name}"),
+                };
+                flatten(&name, value, row);
+            }
+        }
+        value => {
+            let name = match prefix {
+                "" => "value",
+                prefix => prefix,
+            };
+            let value = match value {
+                Value::Array(_) => Value::String(value.to_string()),
+                value => value,
+            };
+            row.insert(name.to_string(), value);
+        }
+    }
+}
+
+/// Text form of a value, empty for null.
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn write_csv<W: Write>(writer: W, table: &Table) -> Result<(), Box<dyn Error>> {
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record(&table.columns)?;
+    for row in &table.rows {
+        csv.write_record(table.columns.iter().map(|column| match row.get(column) {
+            Some(value) => text(value),
+            None => String::new(),
+        }))?;
+    }
+    csv.flush()?;
+    Ok(())
+}
+
+/// Parquet type of a column.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ColumnType {
+    Boolean,
+    Int64,
+    Double,
+    Text,
+}
+
+/// Narrowest type holding every value of a column, text if they disagree or are all null.
+fn column_type<'a>(values: impl Iterator<Item = &'a Value>) -> ColumnType {
+    let mut column_type = None;
+    for value in values {
+        let value_type = match value {
+            Value::Null => continue,
+            Value::Bool(_) => ColumnType::Boolean,
+            Value::Number(number) if number.is_i64() => ColumnType::Int64,
+            Value::Number(_) => ColumnType::Double,
+            _ => ColumnType::Text,
+        };
+        column_type = Some(match (column_type, value_type) {
+            (None, value_type) => value_type,
+            (Some(column_type), value_type) if column_type == value_type => column_type,
+            (
+                Some(ColumnType::Int64 | ColumnType::Double),
+                ColumnType::Int64 | ColumnType::Double,
+            ) => ColumnType::Double,
+            _ => ColumnType::Text,
+        });
+    }
+    column_type.unwrap_or(ColumnType::Text)
+}
+
+/// Single row group of optional columns.
+fn write_parquet<W: Write + Send>(writer: W, table: &Table) -> Result<(), Box<dyn Error>> {
+    let types: Vec<ColumnType> = table
+        .columns
+        .iter()
+        .map(|column| column_type(table.column(column)))
+        .collect();
+    let mut fields = Vec::with_capacity(types.len());
+    for (column, column_type) in table.columns.iter().zip(&types) {
+        let field = match column_type {
+            ColumnType::Boolean => Type::primitive_type_builder(column, PhysicalType::BOOLEAN),
+            ColumnType::Int64 => Type::primitive_type_builder(column, PhysicalType::INT64),
+            ColumnType::Double => Type::primitive_type_builder(column, PhysicalType::DOUBLE),
+            ColumnType::Text => Type::primitive_type_builder(column, PhysicalType::BYTE_ARRAY)
+                .with_converted_type(ConvertedType::UTF8),
+        };
+        fields.push(Arc::new(
+            field.with_repetition(Repetition::OPTIONAL).build()?,
+        ));
+    }
+    let schema = Type::group_type_builder("record")
+        .with_fields(fields)
+        .build()?;
+
+    let properties = WriterProperties::builder().build();
+    let mut writer = SerializedFileWriter::new(writer, Arc::new(schema), Arc::new(properties))?;
+    let mut row_group = writer.next_row_group()?;
+    for (name, column_type) in table.columns.iter().zip(types) {
+        let mut column = row_group.next_column()?.ok_or("Missing Parquet column")?;
+        // Definition levels: 1 for a value, 0 for null
+        let levels: Vec<i16> = table
+            .column(name)
+            .map(|value| i16::from(!value.is_null()))
+            .collect();
+        let values = table.column(name).filter(|value| !value.is_null());
+        match column_type {
+            ColumnType::Boolean => {
+                let values: Vec<bool> = values.filter_map(Value::as_bool).collect();
+                column
+                    .typed::<BoolType>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+            ColumnType::Int64 => {
+                let values: Vec<i64> = values.filter_map(Value::as_i64).collect();
+                column
+                    .typed::<Int64Type>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+            ColumnType::Double => {
+                let values: Vec<f64> = values.filter_map(Value::as_f64).collect();
+                column
+                    .typed::<DoubleType>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+            ColumnType::Text => {
+                let values: Vec<ByteArray> = values
+                    .map(|value| ByteArray::from(text(value).into_bytes()))
+                    .collect();
+                column
+                    .typed::<ByteArrayType>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+        }
+        column.close()?;
+    }
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::stats::TcpStats;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    #[derive(Serialize)]
+    struct Nested {
+        latency: Option<u64>,
+        rate: f64,
+    }
+
+    #[derive(Serialize)]
+    struct Record {
+        client: usize,
+        name: String,
+        ok: bool,
+        nested: Nested,
+        tags: Vec<u8>,
+    }
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record {
+                client: 0,
+                name: "a,b".to_string(),
+                ok: true,
+                nested: Nested {
+                    latency: Some(1500),
+                    rate: 10.0,
+                },
+                tags: vec![1, 2],
+            },
+            Record {
+                client: 1,
+                name: "c".to_string(),
+                ok: false,
+                nested: Nested {
+                    latency: None,
+                    rate: 2.5,
+                },
+                tags: Vec::new(),
+            },
+        ]
+    }
+
+    fn output(format: OutputFormat) -> Output {
+        let directory = std::env::temp_dir().join(format!(
+            "output-{}-{}",
+            format.extension(),
+            std::process::id()
+        ));
+        Output::new(&directory, format).unwrap()
+    }
+
+    #[test]
+    fn flatten_records() {
+        let table = Table::of(&records()).unwrap();
+        assert_eq!(
+            table.columns,
+            [
+                "client",
+                "name",
+                "ok",
+                "nested.latency",
+                "nested.rate",
+                "tags"
+            ]
+        );
+        assert_eq!(table.rows[0]["tags"], Value::String("[1,2]".to_string()));
+
+        let types: Vec<ColumnType> = table
+            .columns
+            .iter()
+            .map(|column| column_type(table.column(column)))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                ColumnType::Int64,
+                ColumnType::Text,
+                ColumnType::Boolean,
+                ColumnType::Int64,
+                ColumnType::Double,
+                ColumnType::Text
+            ]
+        );
+        assert_eq!(
+            column_type([Value::from(1), Value::from(0.5)].iter()),
+            ColumnType::Double
+        );
+        assert_eq!(column_type([Value::Null].iter()), ColumnType::Text);
+    }
+
+    #[test]
+    fn write_formats() {
+        let jsonl = output(OutputFormat::JsonLines);
+        let path = jsonl.write("records", &records()).unwrap().unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].contains(r#""nested":{"latency":null,"rate":2.5}"#));
+
+        let csv = output(OutputFormat::Csv);
+        let path = csv.write("records", &records()).unwrap().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "client,name,ok,nested.latency,nested.rate,tags\n\
+             0,\"a,b\",true,1500,10.0,\"[1,2]\"\n\
+             1,c,false,,2.5,[]\n"
+        );
+        assert!(csv.write::<Record>("empty", &[]).unwrap().is_none());
+
+        let parquet = output(OutputFormat::Parquet);
+        let path = parquet.write("records", &records()).unwrap().unwrap();
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let metadata = reader.metadata().file_metadata();
+        assert_eq!(metadata.num_rows(), 2);
+        assert_eq!(metadata.schema_descr().num_columns(), 6);
+        assert_eq!(
+            metadata.schema_descr().column(3).physical_type(),
+            PhysicalType::INT64
+        );
+        let rows: Vec<String> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().to_string())
+            .collect();
+        assert!(rows[1].contains("nested.latency: null"), "{}", rows[1]);
+
+        for output in [jsonl, csv, parquet] {
+            std::fs::remove_dir_all(output.directory()).unwrap();
+        }
+    }
+
+    #[test]
+    fn transport_session() {
+        let session = SessionInfo {
+            protocol_version: "TLSv1_3".to_string(),
+            alpn: Some("mqtt".to_string()),
+            resumed: Some(false),
+            ..SessionInfo::default()
+        };
+        let peer = "127.0.0.1:8883".parse().ok();
+        let transport_session = TransportSession::new("tls", peer, Some(&session));
+        assert_eq!(transport_session.peer.as_deref(), Some("127.0.0.1:8883"));
+        assert_eq!(transport_session.tls_version.as_deref(), Some("TLSv1_3"));
+        assert_eq!(transport_session.resumed, Some(false));
+        assert!(transport_session.certificate.is_none());
+
+        let plain = TransportSession::new("tcp", peer, None);
+        assert!(plain.tls_version.is_none() && plain.resumed.is_none());
+    }
+
+    #[test]
+    fn delivered_record() {
+        let record =
+            MessageRecord::delivered(1, b"1700000000000000000\x7f", 1_700_000_000_000_002_000);
+        assert_eq!(record.event, MessageEvent::Deliver);
+        assert_eq!(record.latency_ns, Some(2000));
+        assert_eq!(MessageRecord::delivered(1, b"hello", 42).latency_ns, None);
+    }
+
+    #[test]
+    fn stats_record() {
+        let sample = StatsSample {
+            timestamp: 42,
+            stats: TransportStats::Tcp(TcpStats {
+                rtt: Duration::from_micros(250),
+                cwnd: 10,
+                retransmits: 1,
+                sent_bytes: 100,
+                ..TcpStats::default()
+            }),
+        };
+        let record = StatsRecord::new(3, &sample);
+        assert_eq!((record.client, record.timestamp_ns), (3, 42));
+        assert_eq!((record.rtt_ns, record.cwnd), (250_000, 10));
+        assert_eq!((record.retransmits, record.lost_packets), (Some(1), None));
+        assert_eq!(record.sent_bytes, 100);
+    }
+}
@@ -0,0 +1,247 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+
+use crate::utility::traffic::Arrival;
+
+/// Granularity of the tokio timer: wake-ups overshooting by less are not missed ticks
+pub const TIMER_RESOLUTION: Duration = Duration::from_millis(1);
+
+/// What the pacer does with messages whose send time has passed by more than the timer
+/// resolution (e.g. after a stall).
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum MissedTicks {
+    /// Send the late messages back to back until the schedule is caught up
+    #[default]
+    Burst,
+    /// Send the late message now and shift the rest of the schedule by its lateness
+    Delay,
+    /// Drop every late message but the latest one, counting the dropped ones
+    Skip,
+}
+
+impl FromStr for MissedTicks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "burst" => Ok(MissedTicks::Burst),
+            "delay" => Ok(MissedTicks::Delay),
+            "skip" => Ok(MissedTicks::Skip),
+            _ => Err(format!("Unknown missed tick behavior: {s}")),
+        }
+    }
+}
+
+impl fmt::Display for MissedTicks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MissedTicks::Burst => write!(f, "burst"),
+            MissedTicks::Delay => write!(f, "delay"),
+            MissedTicks::Skip => write!(f, "skip"),
+        }
+    }
+}
+
+/// Message due for sending.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Paced {
+    pub arrival: Arrival,
+    /// Send time planned by the traffic model, relative to the start
+    pub scheduled: Duration,
+}
+
+/// Send time of a message against its planned one, relative to the start.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PacingRecord {
+    pub scheduled: Duration,
+    pub actual: Duration,
+}
+
+impl PacingRecord {
+    /// Lateness in nanoseconds (negative for batched messages sent ahead of time).
+    pub fn lateness(&self) -> i128 {
+        self.actual.as_nanos() as i128 - self.scheduled.as_nanos() as i128
+    }
+}
+
+/**
+ * Release messages at their scheduled time, measured from an absolute start so that the error
+ * does not accumulate. Messages are released in batches of `batch` at the time of the first one,
+ * to reach rates above the timer resolution.
+ */
+#[derive(Debug)]
+pub struct Pacer<I: Iterator<Item = Arrival>> {
+    arrivals: Peekable<I>,
+    missed_ticks: MissedTicks,
+    batch: usize,
+    start: Instant,
+    /// Accumulated lateness, shifting the schedule (delay mode)
+    shift: Duration,
+    skipped: usize,
+}
+
+impl<I: Iterator<Item = Arrival>> Pacer<I> {
+    pub fn new(arrivals: I, missed_ticks: MissedTicks, batch: usize) -> Pacer<I> {
+        Pacer {
+            arrivals: arrivals.peekable(),
+            missed_ticks,
+            batch: batch.max(1),
+            start: Instant::now(),
+            shift: Duration::ZERO,
+            skipped: 0,
+        }
+    }
+
+    /// Restart the schedule now.
+    pub fn start(&mut self) {
+        self.start = Instant::now();
+    }
+
+    /// Time elapsed since the start.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Messages dropped for being late (skip mode).
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Wait for the next batch of messages, `None` once every message is released.
+    pub async fn next_batch(&mut self) -> Option<Vec<Paced>> {
+        let first = self.next_due()?;
+        sleep_until(self.start + self.shift + first.arrival.offset).await;
+
+        let mut batch = vec![first];
+        while batch.len() < self.batch {
+            match self.arrivals.next() {
+                Some(arrival) => batch.push(self.paced(arrival)),
+                None => break,
+            }
+        }
+        Some(batch)
+    }
+
+    /// Next message to send, after applying the missed tick behavior.
+    fn next_due(&mut self) -> Option<Paced> {
+        let mut arrival = self.arrivals.next()?;
+        let now = Instant::now();
+        match self.missed_ticks {
+            MissedTicks::Burst => {}
+            MissedTicks::Delay => {
+                let due = self.start + self.shift + arrival.offset;
+                if now > due + TIMER_RESOLUTION {
+                    self.shift += now - due;
+                }
+            }
+            MissedTicks::Skip => {
+                // Keep the latest late message
+                while self.next_missed(now) {
+                    arrival = self.arrivals.next().unwrap();
+                    self.skipped += 1;
+                }
+            }
+        }
+        Some(self.paced(arrival))
+    }
+
+    /// Whether the following message is late as well.
+    fn next_missed(&mut self, now: Instant) -> bool {
+        let start = self.start + self.shift;
+        self.arrivals
+            .peek()
+            .is_some_and(|next| start + next.offset + TIMER_RESOLUTION < now)
+    }
+
+    fn paced(&self, arrival: Arrival) -> Paced {
+        Paced {
+            scheduled: arrival.offset,
+            arrival,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One message every 10 ms.
+    fn arrivals(count: u64) -> impl Iterator<Item = Arrival> {
+        (0..count).map(|i| Arrival {
+            offset: Duration::from_millis(10 * i),
+            topic: None,
+            size: 1,
+        })
+    }
+
+    /// Send times of every message, stalling 35 ms after the second one.
+    async fn run(missed_ticks: MissedTicks, batch: usize) -> (Vec<PacingRecord>, usize) {
+        let mut pacer = Pacer::new(arrivals(10), missed_ticks, batch);
+        pacer.start();
+
+        let mut records = Vec::new();
+        while let Some(batch) = pacer.next_batch().await {
+            for paced in batch {
+                records.push(PacingRecord {
+                    scheduled: paced.scheduled,
+                    actual: pacer.elapsed(),
+                });
+            }
+            if records.len() == 2 {
+                tokio::time::sleep(Duration::from_millis(35)).await;
+            }
+        }
+        (records, pacer.skipped())
+    }
+
+    fn actual_millis(records: &[PacingRecord]) -> Vec<u128> {
+        records
+            .iter()
+            .map(|record| record.actual.as_millis())
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn burst_catches_up() {
+        let (records, skipped) = run(MissedTicks::Burst, 1).await;
+        assert_eq!(
+            actual_millis(&records),
+            vec![0, 10, 45, 45, 45, 50, 60, 70, 80, 90]
+        );
+        assert_eq!(skipped, 0);
+        assert_eq!(records[2].lateness(), 25_000_000);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn delay_shifts_schedule() {
+        let (records, skipped) = run(MissedTicks::Delay, 1).await;
+        assert_eq!(
+            actual_millis(&records),
+            vec![0, 10, 45, 55, 65, 75, 85, 95, 105, 115]
+        );
+        assert_eq!(skipped, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn skip_drops_late_messages() {
+        let (records, skipped) = run(MissedTicks::Skip, 1).await;
+        assert_eq!(actual_millis(&records), vec![0, 10, 45, 50, 60, 70, 80, 90]);
+        assert_eq!(records[2].scheduled, Duration::from_millis(40));
+        assert_eq!(skipped, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn batches() {
+        let mut pacer = Pacer::new(arrivals(10), MissedTicks::Burst, 4);
+        pacer.start();
+
+        let mut batches = Vec::new();
+        while let Some(batch) = pacer.next_batch().await {
+            batches.push((pacer.elapsed().as_millis(), batch.len()));
+        }
+        assert_eq!(batches, vec![(0, 4), (40, 4), (80, 2)]);
+    }
+}
@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+/// Share of the target rate a sustainable step must achieve
+const MIN_ACHIEVED_RATE: f64 = 0.95;
+/// Rate below which the search gives up (messages per second)
+const MIN_RATE: f64 = 1.0;
+
+/// Limits of a sustainable rate.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Thresholds {
+    /// Highest 99th percentile ack latency (QoS 1 and 2)
+    pub max_latency: Duration,
+    /// Largest send queue left at the end of a step
+    pub max_queue: usize,
+}
+
+/// Measurements of one step at a constant rate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    /// Target rate in messages per second
+    pub rate: f64,
+    pub sent: usize,
+    /// Time taken to send every message of the step
+    pub elapsed: Duration,
+    /// 99th percentile ack latency, none without acks (QoS 0)
+    pub latency: Option<Duration>,
+    /// Messages still waiting for an ack after draining
+    pub unacked: usize,
+    /// Send queue length once every message is sent
+    pub queue: usize,
+    /// Packet ids ran out before the end of the step (too many messages in flight)
+    pub exhausted: bool,
+}
+
+impl Step {
+    /// Rate actually sent in messages per second.
+    pub fn achieved_rate(&self) -> f64 {
+        self.sent as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl Thresholds {
+    /// Whether the broker kept up with the step: the rate was achieved, every message was
+    /// acked in time, the send queue did not build up and packet ids did not run out.
+    pub fn sustainable(&self, step: &Step) -> bool {
+        step.achieved_rate() >= MIN_ACHIEVED_RATE * step.rate
+            && !step.exhausted
+            && step.unacked == 0
+            && step
+                .latency
+                .is_none_or(|latency| latency <= self.max_latency)
+            && step.queue <= self.max_queue
+    }
+}
+
+/**
+ * Search of the maximum sustainable rate: the rate doubles from the start rate until a step is
+ * not sustainable, then a binary search narrows the interval between the highest sustainable
+ * rate and the lowest unsustainable one down to `precision` (relative).
+ */
+#[derive(Debug, Clone)]
+pub struct SaturationSearch {
+    max_rate: f64,
+    precision: f64,
+    /// Highest sustainable rate so far
+    low: f64,
+    /// Lowest unsustainable rate so far, probing until there is one
+    high: Option<f64>,
+    next: Option<f64>,
+}
+
+impl SaturationSearch {
+    pub fn new(start_rate: f64, max_rate: f64, precision: f64) -> SaturationSearch {
+        SaturationSearch {
+            max_rate,
+            precision,
+            low: 0.0,
+            high: None,
+            next: Some(start_rate.min(max_rate)),
+        }
+    }
+
+    /// Next rate to try, `None` once the search is over.
+    pub fn next_rate(&self) -> Option<f64> {
+        self.next
+    }
+
+    /// Record the outcome of a step at `rate`.
+    pub fn record(&mut self, rate: f64, sustainable: bool) {
+        if sustainable {
+            self.low = self.low.max(rate);
+        } else {
+            self.high = Some(self.high.map_or(rate, |high| high.min(rate)));
+        }
+
+        self.next = match self.high {
+            None if rate >= self.max_rate => None,
+            None => Some((rate * 2.0).min(self.max_rate)),
+            Some(high) if high - self.low <= self.precision * high || high < MIN_RATE => None,
+            Some(high) => Some((self.low + high) / 2.0),
+        };
+    }
+
+    /// Highest sustainable rate found (0 if none).
+    pub fn result(&self) -> f64 {
+        self.low
+    }
+
+    /// Whether an unsustainable rate was found below the maximum rate.
+    pub fn saturated(&self) -> bool {
+        self.high.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Search against a broker sustaining up to `capacity`, returning the rates tried.
+    fn run(mut search: SaturationSearch, capacity: f64) -> (SaturationSearch, Vec<f64>) {
+        let mut rates = Vec::new();
+        while let Some(rate) = search.next_rate() {
+            rates.push(rate);
+            search.record(rate, rate <= capacity);
+        }
+        (search, rates)
+    }
+
+    #[test]
+    fn probe_then_bisect() {
+        let (search, rates) = run(SaturationSearch::new(100.0, 1e6, 0.05), 1000.0);
+
+        assert_eq!(rates[..5], [100.0, 200.0, 400.0, 800.0, 1600.0]);
+        assert_eq!(rates[5], 1200.0);
+        assert!(search.saturated());
+        assert!(search.result() <= 1000.0 && search.result() >= 950.0);
+        assert!(rates.len() < 12, "{rates:?}");
+    }
+
+    #[test]
+    fn max_rate_sustained() {
+        let (search, rates) = run(SaturationSearch::new(100.0, 500.0, 0.05), 1000.0);
+
+        assert_eq!(rates, vec![100.0, 200.0, 400.0, 500.0]);
+        assert!(!search.saturated());
+        assert_eq!(search.result(), 500.0);
+    }
+
+    #[test]
+    fn start_rate_unsustainable() {
+        let (search, _) = run(SaturationSearch::new(1000.0, 1e6, 0.05), 100.0);
+        assert!(search.result() <= 100.0 && search.result() >= 95.0);
+
+        // Nothing sustainable at all
+        let (search, _) = run(SaturationSearch::new(1000.0, 1e6, 0.05), 0.0);
+        assert_eq!(search.result(), 0.0);
+    }
+
+    #[test]
+    fn thresholds() {
+        let thresholds = Thresholds {
+            max_latency: Duration::from_millis(100),
+            max_queue: 10,
+        };
+        let step = Step {
+            rate: 100.0,
+            sent: 100,
+            elapsed: Duration::from_millis(990),
+            latency: Some(Duration::from_millis(5)),
+            unacked: 0,
+            queue: 0,
+            exhausted: false,
+        };
+        assert!(thresholds.sustainable(&step));
+        assert!(thresholds.sustainable(&Step {
+            latency: None,
+            ..step.clone()
+        }));
+
+        assert!(!thresholds.sustainable(&Step {
+            elapsed: Duration::from_secs(2),
+            ..step.clone()
+        }));
+        assert!(!thresholds.sustainable(&Step {
+            latency: Some(Duration::from_millis(150)),
+            ..step.clone()
+        }));
+        assert!(!thresholds.sustainable(&Step {
+            unacked: 1,
+            ..step.clone()
+        }));
+        assert!(!thresholds.sustainable(&Step {
+            queue: 11,
+            ..step.clone()
+        }));
+        assert!(!thresholds.sustainable(&Step {
+            exhausted: true,
+            ..step
+        }));
+    }
+}
@@ -1,21 +1,67 @@
-use crate::utility::argument_parser::{PublishArgs, SubscribeArgs};
+use crate::utility::argument_parser::{Args, OutputArgs, PublishArgs, SubscribeArgs};
+use crate::utility::pacing::MissedTicks;
+use crate::utility::traffic::{SizeDistribution, TrafficModel};
 use clap::Parser;
+use serde_json::{json, Map, Value};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 
 const DEFAULT_RATE: f64 = 0.0;
 const DEFAULT_DURATION: usize = 10;
 const DEFAULT_QUEUE: i64 = 1024;
 const DEFAULT_NAGLE_OFF: bool = false;
+const DEFAULT_STATS_INTERVAL: u64 = 0;
+const DEFAULT_RTT: bool = false;
+const DEFAULT_MODEL: &str = "constant";
+const DEFAULT_BATCH: usize = 1;
+const DEFAULT_CLIENTS: usize = 1;
+const DEFAULT_RAMP_UP: u64 = 0;
+const DEFAULT_THREADS: usize = 1;
+const DEFAULT_DRAIN: u64 = 1000;
+const DEFAULT_SIZES: &str = "64";
+const DEFAULT_START_RATE: f64 = 100.0;
+const DEFAULT_MAX_RATE: f64 = 1_000_000.0;
+const DEFAULT_STEP_DURATION: u64 = 5000;
+const DEFAULT_MAX_LATENCY: f64 = 100.0;
+const DEFAULT_MAX_QUEUE: usize = 100;
+const DEFAULT_PRECISION: f64 = 0.05;
+const DEFAULT_WARM_UP: f64 = 0.0;
+const DEFAULT_COOL_DOWN: f64 = 0.0;
+const DEFAULT_TUI: bool = false;
+const DEFAULT_DEBUG: bool = false;
 
 #[cfg(feature = "pub_stream")]
 #[derive(Parser)]
 #[command(name = "mqtt-client")]
 #[command(bin_name = "mqtt-client")]
+#[allow(clippy::large_enum_variant)]
 pub enum MqttStreamCli {
     #[clap(alias = "pub")]
     Publish(PublishStreamArgs),
 
     #[clap(alias = "sub")]
     Subscribe(SubscribeArgs),
+
+    /// One publisher, every client subscribes to its topic
+    FanOut(ScenarioArgs),
+
+    /// Every client publishes, a single subscriber receives on a wildcard
+    FanIn(ScenarioArgs),
+
+    /// Broker benchmarks
+    #[command(subcommand)]
+    Bench(BenchCommand),
+}
+
+#[cfg(feature = "pub_stream")]
+#[derive(clap::Subcommand)]
+#[allow(clippy::large_enum_variant)]
+pub enum BenchCommand {
+    /// Search the maximum sustainable publish rate per transport, QoS and payload size
+    Saturate(SaturateArgs),
+
+    /// Run every combination of the parameters of an experiment file (TOML)
+    Sweep(SweepArgs),
 }
 
 #[cfg(feature = "pub_stream")]
@@ -37,4 +83,223 @@ pub struct PublishStreamArgs {
 
     #[arg(long, default_value_t=DEFAULT_NAGLE_OFF)]
     pub nagle_off: bool,
+
+    /// Transport statistics sampling interval in milliseconds (0 samples only on disconnect)
+    #[arg(long, default_value_t=DEFAULT_STATS_INTERVAL)]
+    pub stats_interval: u64,
+
+    /// Measure round-trip times against a reflector (raw-mqtt-broker reflect), messages must be
+    /// at least 19 bytes long to carry the send timestamp
+    #[arg(long, default_value_t=DEFAULT_RTT)]
+    pub rtt: bool,
+
+    /// Arrival process at the given rate: "constant", "poisson", "on-off:ON_MS,OFF_MS" or
+    /// "jitter:JITTER_MS"
+    #[arg(long, default_value = DEFAULT_MODEL)]
+    pub model: TrafficModel,
+
+    /// Payload size distribution: "fixed:SIZE", "uniform:MIN,MAX", "normal:MEAN,STD_DEV" or
+    /// "exp:MEAN" (the message size if not set)
+    #[arg(long)]
+    pub size_dist: Option<SizeDistribution>,
+
+    /// Replay a CSV trace of timestamp (seconds),topic,size records instead of the model
+    #[arg(long)]
+    pub trace: Option<PathBuf>,
+
+    /// Seed of the random traffic models, for reproducible runs
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Late messages (e.g. after a stall): "burst" sends them back to back, "delay" shifts the
+    /// schedule, "skip" drops them
+    #[arg(long, default_value_t = MissedTicks::default())]
+    pub missed_ticks: MissedTicks,
+
+    /// Messages sent per timer wake-up, for rates above the timer resolution (about 1 kHz, late
+    /// wake-ups are taken for missed ticks otherwise)
+    #[arg(long, default_value_t = DEFAULT_BATCH)]
+    pub batch: usize,
+
+    /// Write the scheduled and actual send time of every message to this file (CSV)
+    #[arg(long)]
+    pub pacing_log: Option<PathBuf>,
+
+    /// Independent clients, each with its own connection, publishing at the full rate. "{id}"
+    /// in the topic, trace topics and client id is replaced by the client index (from 0)
+    #[arg(long, default_value_t = DEFAULT_CLIENTS)]
+    pub clients: usize,
+
+    /// Client id template, e.g. "bench-{id}" (a random id per client if not set)
+    #[arg(long)]
+    pub client_id: Option<String>,
+
+    /// Time over which the client connections are spread, in milliseconds
+    #[arg(long, default_value_t = DEFAULT_RAMP_UP)]
+    pub ramp_up: u64,
+
+    /// Runtime worker threads (1 runs every client on the main thread, 0 uses one per core)
+    #[arg(long, default_value_t = DEFAULT_THREADS)]
+    pub threads: usize,
+
+    /// Leading period left out of the results, in seconds (sent on top of the duration)
+    #[arg(long, default_value_t = DEFAULT_WARM_UP)]
+    pub warm_up: f64,
+
+    /// Trailing period left out of the results, in seconds (sent on top of the duration)
+    #[arg(long, default_value_t = DEFAULT_COOL_DOWN)]
+    pub cool_down: f64,
+
+    /// Time to wait for missing acks, reflected messages and deliveries after the last
+    /// message, in milliseconds (then counted as lost)
+    #[arg(long, default_value_t = DEFAULT_DRAIN)]
+    pub drain: u64,
+
+    /// Write the run summary (throughput, losses, latency statistics) to this file (JSON)
+    #[arg(long)]
+    pub summary: Option<PathBuf>,
+
+    /// Serve live metrics of every client in OpenMetrics text format at /metrics on this
+    /// address, e.g. 127.0.0.1:9464
+    #[arg(long)]
+    pub metrics: Option<SocketAddr>,
+
+    /// Show a live dashboard of the run in the terminal (send rate, queue, in-flight messages,
+    /// latencies, losses and transport RTT), "q" closes it and the run goes on
+    #[arg(long, default_value_t = DEFAULT_TUI)]
+    pub tui: bool,
+}
+
+#[cfg(feature = "pub_stream")]
+impl PublishStreamArgs {
+    /**
+     * Connection, message and stream parameters recorded with the run results.
+     */
+    pub fn parameters(&self) -> Map<String, Value> {
+        let mut parameters = self.publish_args.parameters();
+        parameters.extend([
+            ("rate".to_string(), json!(self.rate)),
+            ("duration".to_string(), json!(self.duration)),
+            ("model".to_string(), json!(self.model.to_string())),
+            (
+                "size_dist".to_string(),
+                json!(self.size_dist.map(|sizes| sizes.to_string())),
+            ),
+            ("trace".to_string(), json!(self.trace)),
+            ("seed".to_string(), json!(self.seed)),
+            ("queue".to_string(), json!(self.queue)),
+            ("nagle_off".to_string(), json!(self.nagle_off)),
+            ("rtt".to_string(), json!(self.rtt)),
+            (
+                "missed_ticks".to_string(),
+                json!(self.missed_ticks.to_string()),
+            ),
+            ("batch".to_string(), json!(self.batch)),
+            ("clients".to_string(), json!(self.clients)),
+            ("ramp_up".to_string(), json!(self.ramp_up)),
+            ("threads".to_string(), json!(self.threads)),
+            ("warm_up".to_string(), json!(self.warm_up)),
+            ("cool_down".to_string(), json!(self.cool_down)),
+            ("drain".to_string(), json!(self.drain)),
+        ]);
+        parameters
+    }
+}
+
+#[cfg(feature = "pub_stream")]
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct ScenarioArgs {
+    #[command(flatten)]
+    pub stream_args: PublishStreamArgs,
+
+    /// Subscription QoS (the publish QoS if not set)
+    #[arg(long)]
+    pub sub_qos: Option<u8>,
+
+    /// Topic filter of the subscribers (the topic, with "{id}" replaced by "+" in fan-in)
+    #[arg(long)]
+    pub filter: Option<String>,
+}
+
+#[cfg(feature = "pub_stream")]
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct SaturateArgs {
+    #[command(flatten)]
+    pub common_args: Args,
+
+    /// Transports to test, each with an optional port (e.g. "tcp:1883,quic:14567"), the
+    /// transport and port options if not set
+    #[arg(long, value_delimiter = ',', value_parser = parse_transport_port)]
+    pub transports: Vec<(String, Option<u16>)>,
+
+    /// QoS levels to test (the QoS option if not set)
+    #[arg(long, value_delimiter = ',')]
+    pub qos_levels: Vec<u8>,
+
+    /// Payload sizes to test, in bytes
+    #[arg(long, value_delimiter = ',', default_value = DEFAULT_SIZES)]
+    pub sizes: Vec<usize>,
+
+    /// First rate tried, in messages per second (doubled until saturation)
+    #[arg(long, default_value_t = DEFAULT_START_RATE)]
+    pub start_rate: f64,
+
+    /// Highest rate tried, in messages per second
+    #[arg(long, default_value_t = DEFAULT_MAX_RATE)]
+    pub max_rate: f64,
+
+    /// Duration of each step at a constant rate, in milliseconds
+    #[arg(long, default_value_t = DEFAULT_STEP_DURATION)]
+    pub step_duration: u64,
+
+    /// Highest sustainable 99th percentile ack latency, in milliseconds (QoS 1 and 2)
+    #[arg(long, default_value_t = DEFAULT_MAX_LATENCY)]
+    pub max_latency: f64,
+
+    /// Largest sustainable send queue once a step is sent, in messages
+    #[arg(long, default_value_t = DEFAULT_MAX_QUEUE)]
+    pub max_queue: usize,
+
+    /// Relative width of the final search interval
+    #[arg(long, default_value_t = DEFAULT_PRECISION)]
+    pub precision: f64,
+
+    #[clap(allow_hyphen_values = true)]
+    #[arg(long, default_value_t = DEFAULT_QUEUE)]
+    pub queue: i64,
+
+    #[command(flatten)]
+    pub output_args: OutputArgs,
+}
+
+#[cfg(feature = "pub_stream")]
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct SweepArgs {
+    /// Experiment file: output directory, repetitions, warm-up, cool-down, broker, fixed
+    /// options and swept axes
+    pub experiment: PathBuf,
+
+    /// List the runs without running them
+    #[arg(long)]
+    pub dry_run: bool,
+
+    #[arg(short, long, default_value_t = DEFAULT_DEBUG)]
+    pub debug: bool,
+}
+
+/// Parse a "TRANSPORT[:PORT]" pair.
+#[cfg(feature = "pub_stream")]
+fn parse_transport_port(s: &str) -> Result<(String, Option<u16>), String> {
+    match s.split_once(':') {
+        Some((transport, port)) => {
+            let port = port
+                .parse()
+                .map_err(|e| format!("Invalid port in {s}: {e}"))?;
+            Ok((transport.to_string(), Some(port)))
+        }
+        None => Ok((s.to_string(), None)),
+    }
 }
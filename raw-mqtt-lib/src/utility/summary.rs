@@ -0,0 +1,347 @@
+use hdrhistogram::Histogram;
+use serde::Serialize;
+use std::time::Duration;
+
+/// Significant digits kept by the latency histograms
+const SIGNIFICANT_DIGITS: u8 = 3;
+
+/// Latency distribution in nanoseconds, with HDR precision over any range.
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    histogram: Histogram<u64>,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> LatencyHistogram {
+        LatencyHistogram {
+            histogram: Histogram::new(SIGNIFICANT_DIGITS).expect("Invalid histogram precision"),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn new() -> LatencyHistogram {
+        LatencyHistogram::default()
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let nanos = latency.as_nanos().min(u64::MAX as u128) as u64;
+        // The histogram grows as needed, clamp only if it cannot
+        if self.histogram.record(nanos).is_err() {
+            self.histogram.saturating_record(nanos);
+        }
+    }
+
+    /// Add every value recorded by `other`.
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        self.histogram
+            .add(&other.histogram)
+            .expect("Failed to merge latency histograms");
+    }
+
+    /// Values recorded since `earlier`, a previous copy of this histogram.
+    pub fn since(&self, earlier: &LatencyHistogram) -> LatencyHistogram {
+        let mut histogram = self.clone();
+        histogram
+            .histogram
+            .subtract(&earlier.histogram)
+            .expect("Not a previous copy of the latency histogram");
+        histogram
+    }
+
+    pub fn len(&self) -> u64 {
+        self.histogram.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.histogram.is_empty()
+    }
+
+    /// Number of latencies up to `latency` included, within the histogram precision.
+    pub fn count_up_to(&self, latency: Duration) -> u64 {
+        let nanos = latency.as_nanos().min(u64::MAX as u128) as u64;
+        self.histogram.count_between(0, nanos)
+    }
+
+    /// Sum of every latency, within the histogram precision.
+    pub fn sum(&self) -> Duration {
+        Duration::from_secs_f64(self.histogram.mean() * self.histogram.len() as f64 / 1e9)
+    }
+
+    /// Latency at `quantile` (0 to 1), within the histogram precision.
+    pub fn quantile(&self, quantile: f64) -> Duration {
+        Duration::from_nanos(self.histogram.value_at_quantile(quantile))
+    }
+
+    /// Summary statistics, none without values.
+    pub fn summary(&self) -> Option<LatencySummary> {
+        if self.is_empty() {
+            return None;
+        }
+        let histogram = &self.histogram;
+        Some(LatencySummary {
+            count: histogram.len(),
+            min_ns: histogram.min(),
+            mean_ns: histogram.mean(),
+            p50_ns: histogram.value_at_quantile(0.5),
+            p90_ns: histogram.value_at_quantile(0.9),
+            p99_ns: histogram.value_at_quantile(0.99),
+            p999_ns: histogram.value_at_quantile(0.999),
+            max_ns: histogram.max(),
+            stddev_ns: histogram.stdev(),
+        })
+    }
+}
+
+impl FromIterator<Duration> for LatencyHistogram {
+    fn from_iter<I: IntoIterator<Item = Duration>>(latencies: I) -> LatencyHistogram {
+        let mut histogram = LatencyHistogram::new();
+        for latency in latencies {
+            histogram.record(latency);
+        }
+        histogram
+    }
+}
+
+/// Latency statistics in nanoseconds.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub min_ns: u64,
+    pub mean_ns: f64,
+    pub p50_ns: u64,
+    pub p90_ns: u64,
+    pub p99_ns: u64,
+    pub p999_ns: u64,
+    pub max_ns: u64,
+    pub stddev_ns: f64,
+}
+
+/// Results of a stream run, over the measurement window (warm-up and cool-down excluded).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunSummary {
+    /// Measurement window in seconds
+    pub window: f64,
+    pub sent: usize,
+    /// Messages dropped by the pacer (missed ticks)
+    pub skipped: usize,
+    /// Messages replaced in the send queue before being sent (LIFO queue)
+    pub replaced: usize,
+    /// Acks and reflected messages still missing after the drain time (whole run)
+    pub lost: usize,
+    /// Rate of the traffic model over every client in messages per second, none for traces
+    pub requested_rate: Option<f64>,
+    pub achieved_rate: f64,
+    /// From publishing to the PUBACK or PUBREC (QoS 1 and 2)
+    pub ack_latency: Option<LatencySummary>,
+    /// From publishing to the reflected message (RTT mode) or the delivery (scenarios)
+    pub end_to_end_latency: Option<LatencySummary>,
+}
+
+impl RunSummary {
+    /// Text table of the summary, one line each.
+    pub fn table(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!(
+                "Throughput: {:.1} messages/s over {:.3} s{}",
+                self.achieved_rate,
+                self.window,
+                match self.requested_rate {
+                    Some(requested) if requested > 0.0 => format!(
+                        " (requested {:.1}, {:.1}%)",
+                        requested,
+                        100.0 * self.achieved_rate / requested
+                    ),
+                    _ => String::new(),
+                }
+            ),
+            format!(
+                "Messages: {} sent, {} skipped, {} replaced, {} lost",
+                self.sent, self.skipped, self.replaced, self.lost
+            ),
+        ];
+
+        let latencies = [
+            ("ack", self.ack_latency),
+            ("end-to-end", self.end_to_end_latency),
+        ];
+        if latencies.iter().all(|(_, latency)| latency.is_none()) {
+            return lines;
+        }
+        lines.push(latency_header());
+        for (name, latency) in latencies {
+            if let Some(latency) = latency {
+                lines.push(latency_row(name, &latency));
+            }
+        }
+        lines
+    }
+}
+
+/// Results of a ping run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PingSummary {
+    pub sent: usize,
+    pub received: usize,
+    /// Pings left without PINGRESP within the timeout
+    pub lost: usize,
+    /// Round-trip times of the answered pings
+    pub rtt: Option<LatencySummary>,
+}
+
+impl PingSummary {
+    /// Summary of `sent` pings, answered in the round-trip times of `rtts`.
+    pub fn new(sent: usize, rtts: &LatencyHistogram) -> PingSummary {
+        let received = rtts.len() as usize;
+        PingSummary {
+            sent,
+            received,
+            lost: sent.saturating_sub(received),
+            rtt: rtts.summary(),
+        }
+    }
+
+    /// Text table of the summary, one line each.
+    pub fn table(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "Pings: {} sent, {} received, {:.1}% lost",
+            self.sent,
+            self.received,
+            match self.sent {
+                0 => 0.0,
+                sent => 100.0 * self.lost as f64 / sent as f64,
+            }
+        )];
+        if let Some(ref rtt) = self.rtt {
+            lines.push(latency_header());
+            lines.push(latency_row("rtt", rtt));
+        }
+        lines
+    }
+}
+
+/// Header of the latency table.
+pub fn latency_header() -> String {
+    format!(
+        "{:<10} {:>8} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "latency", "count", "min", "mean", "p50", "p90", "p99", "p99.9", "max", "stddev"
+    )
+}
+
+/// Table row of a latency summary.
+pub fn latency_row(name: &str, latency: &LatencySummary) -> String {
+    format!(
+        "{:<10} {:>8} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        name,
+        latency.count,
+        short_duration(latency.min_ns as f64),
+        short_duration(latency.mean_ns),
+        short_duration(latency.p50_ns as f64),
+        short_duration(latency.p90_ns as f64),
+        short_duration(latency.p99_ns as f64),
+        short_duration(latency.p999_ns as f64),
+        short_duration(latency.max_ns as f64),
+        short_duration(latency.stddev_ns)
+    )
+}
+
+/// Nanoseconds with three significant digits and a fitting unit, e.g. `1.23ms`.
+pub fn short_duration(nanos: f64) -> String {
+    let (value, unit) = match nanos {
+        nanos if nanos < 1e3 => (nanos, "ns"),
+        nanos if nanos < 1e6 => (nanos / 1e3, "µs"),
+        nanos if nanos < 1e9 => (nanos / 1e6, "ms"),
+        nanos => (nanos / 1e9, "s"),
+    };
+    match value {
+        value if value < 10.0 => format!("{value:.2}{unit}"),
+        value if value < 100.0 => format!("{value:.1}{unit}"),
+        value => format!("{value:.0}{unit}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_summary() {
+        let histogram: LatencyHistogram = (1..=1000).map(Duration::from_micros).collect();
+        let summary = histogram.summary().unwrap();
+
+        // Within the precision of 3 significant digits
+        let close = |value: u64, expected: u64| value.abs_diff(expected) <= expected / 1000 + 1;
+        assert_eq!(summary.count, 1000);
+        assert!(close(summary.min_ns, 1_000));
+        assert!(close(summary.p50_ns, 500_000), "{}", summary.p50_ns);
+        assert!(close(summary.p99_ns, 990_000), "{}", summary.p99_ns);
+        assert!(close(summary.p999_ns, 999_000), "{}", summary.p999_ns);
+        assert!(close(summary.max_ns, 1_000_000), "{}", summary.max_ns);
+        assert!((summary.mean_ns - 500_500.0).abs() < 1_000.0);
+        assert!((summary.stddev_ns - 288_675.0).abs() < 1_000.0);
+        assert!(close(histogram.quantile(0.9).as_nanos() as u64, 900_000));
+        assert_eq!(histogram.count_up_to(Duration::from_micros(100)), 100);
+        assert_eq!(histogram.count_up_to(Duration::from_secs(1)), 1000);
+        assert!((histogram.sum().as_secs_f64() - 0.5005).abs() < 0.001);
+
+        assert!(LatencyHistogram::new().summary().is_none());
+    }
+
+    #[test]
+    fn merge_histograms() {
+        let mut histogram: LatencyHistogram = [Duration::from_nanos(10)].into_iter().collect();
+        let other: LatencyHistogram = [Duration::from_secs(3600)].into_iter().collect();
+        histogram.merge(&other);
+
+        let summary = histogram.summary().unwrap();
+        assert_eq!(summary.count, 2);
+        assert_eq!(summary.min_ns, 10);
+        assert!(summary.max_ns >= 3_600_000_000_000);
+
+        let earlier: LatencyHistogram = [Duration::from_nanos(10)].into_iter().collect();
+        let since = histogram.since(&earlier).summary().unwrap();
+        assert_eq!(since.count, 1);
+        assert!(since.min_ns >= 3_599_000_000_000);
+    }
+
+    #[test]
+    fn summary_table() {
+        let summary = RunSummary {
+            window: 10.0,
+            sent: 990,
+            skipped: 0,
+            replaced: 3,
+            lost: 1,
+            requested_rate: Some(100.0),
+            achieved_rate: 99.0,
+            ack_latency: LatencyHistogram::from_iter([Duration::from_micros(1500)]).summary(),
+            end_to_end_latency: None,
+        };
+        let table = summary.table();
+
+        assert_eq!(
+            table[0],
+            "Throughput: 99.0 messages/s over 10.000 s (requested 100.0, 99.0%)"
+        );
+        assert_eq!(
+            table[1],
+            "Messages: 990 sent, 0 skipped, 3 replaced, 1 lost"
+        );
+        assert!(table[2].starts_with("latency"));
+        assert!(table[3].starts_with("ack") && table[3].contains("1.50ms"));
+        assert_eq!(table.len(), 4);
+
+        let ping = PingSummary::new(4, &LatencyHistogram::from_iter([Duration::from_micros(80)]));
+        let table = ping.table();
+        assert_eq!(table[0], "Pings: 4 sent, 1 received, 75.0% lost");
+        assert!(table[2].starts_with("rtt") && table[2].contains("80.0µs"));
+        assert_eq!(
+            PingSummary::new(0, &LatencyHistogram::new()).table().len(),
+            1
+        );
+
+        assert_eq!(short_duration(999.0), "999ns");
+        assert_eq!(short_duration(12_345.0), "12.3µs");
+        assert_eq!(short_duration(2.5e9), "2.50s");
+    }
+}
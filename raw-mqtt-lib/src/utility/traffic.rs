@@ -0,0 +1,513 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Exp, Normal};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Arrival process of the published messages.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrafficModel {
+    /// One message every `1 / rate` seconds
+    Constant,
+    /// Exponential inter-arrival times with mean `1 / rate`
+    Poisson,
+    /// Constant rate during `on`, silent during `off`, repeated
+    OnOff { on: Duration, off: Duration },
+    /// Constant rate, every message displaced uniformly within `± jitter` (order is kept)
+    Jitter(Duration),
+    /// Replay of a recorded trace, the rate and size distribution are ignored
+    Trace(Vec<TraceRecord>),
+}
+
+/**
+ * Parse a traffic model: `constant`, `poisson`, `on-off:ON_MS,OFF_MS` or `jitter:JITTER_MS`.
+ * Traces are loaded with `TrafficModel::trace`.
+ */
+impl FromStr for TrafficModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (model, params) = match s.split_once(':') {
+            Some((model, params)) => (model, parse_params(params)?),
+            None => (s, Vec::new()),
+        };
+        match (model.to_lowercase().as_str(), &params[..]) {
+            ("constant", []) => Ok(TrafficModel::Constant),
+            ("poisson", []) => Ok(TrafficModel::Poisson),
+            ("on-off", [on, off]) if *on > 0.0 && *off >= 0.0 => Ok(TrafficModel::OnOff {
+                on: millis(*on),
+                off: millis(*off),
+            }),
+            ("jitter", [jitter]) if *jitter >= 0.0 => Ok(TrafficModel::Jitter(millis(*jitter))),
+            _ => Err(format!(
+                "Invalid traffic model {s}, expected constant, poisson, on-off:ON_MS,OFF_MS or \
+                 jitter:JITTER_MS"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TrafficModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrafficModel::Constant => write!(f, "constant"),
+            TrafficModel::Poisson => write!(f, "poisson"),
+            TrafficModel::OnOff { on, off } => {
+                write!(f, "on-off:{},{}", as_millis(*on), as_millis(*off))
+            }
+            TrafficModel::Jitter(jitter) => write!(f, "jitter:{}", as_millis(*jitter)),
+            TrafficModel::Trace(_) => write!(f, "trace"),
+        }
+    }
+}
+
+impl TrafficModel {
+    /**
+     * Load a CSV trace of `timestamp,topic,size` records, timestamps in seconds (relative to the
+     * first record). A header line and lines starting with `#` are skipped.
+     */
+    pub fn trace(path: &Path) -> Result<TrafficModel, Box<dyn Error>> {
+        let content = std::fs::read_to_string(path)?;
+        let mut records = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [timestamp, topic, size] = fields[..] else {
+                Err(format!(
+                    "{}:{}: expected timestamp,topic,size",
+                    path.display(),
+                    number + 1
+                ))?
+            };
+            let timestamp = match timestamp.parse::<f64>() {
+                Ok(timestamp) if !timestamp.is_finite() => Err(format!(
+                    "{}:{}: invalid timestamp {timestamp}",
+                    path.display(),
+                    number + 1
+                ))?,
+                Ok(timestamp) => timestamp,
+                Err(_) if records.is_empty() => continue, // Header
+                Err(e) => Err(format!("{}:{}: {e}", path.display(), number + 1))?,
+            };
+            records.push(TraceRecord {
+                offset: timestamp,
+                topic: topic.to_string(),
+                size: size
+                    .parse()
+                    .map_err(|e| format!("{}:{}: {e}", path.display(), number + 1))?,
+            });
+        }
+
+        // Replay relative to the first record, in time order
+        records.sort_by(|a, b| a.offset.total_cmp(&b.offset));
+        let start = records.first().map_or(0.0, |record| record.offset);
+        for record in records.iter_mut() {
+            record.offset -= start;
+        }
+
+        Ok(TrafficModel::Trace(records))
+    }
+}
+
+/// Message of a recorded trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceRecord {
+    /// Seconds since the first message
+    pub offset: f64,
+    pub topic: String,
+    pub size: usize,
+}
+
+/// Distribution of the payload sizes (at least 1 byte).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SizeDistribution {
+    Fixed(usize),
+    /// Uniform within `[min, max]`
+    Uniform {
+        min: usize,
+        max: usize,
+    },
+    Normal {
+        mean: f64,
+        std_dev: f64,
+    },
+    Exponential {
+        mean: f64,
+    },
+}
+
+/// Parse a size distribution: `fixed:SIZE`, `uniform:MIN,MAX`, `normal:MEAN,STD_DEV` or `exp:MEAN`.
+impl FromStr for SizeDistribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid size distribution {s}, expected fixed:SIZE, uniform:MIN,MAX, \
+                 normal:MEAN,STD_DEV or exp:MEAN"
+            )
+        };
+        let (distribution, params) = s.split_once(':').ok_or_else(invalid)?;
+        let params = parse_params(params)?;
+        match (distribution.to_lowercase().as_str(), &params[..]) {
+            ("fixed", [size]) if *size >= 1.0 => Ok(SizeDistribution::Fixed(*size as usize)),
+            ("uniform", [min, max]) if *min >= 1.0 && min <= max => Ok(SizeDistribution::Uniform {
+                min: *min as usize,
+                max: *max as usize,
+            }),
+            ("normal", [mean, std_dev]) if *std_dev >= 0.0 => Ok(SizeDistribution::Normal {
+                mean: *mean,
+                std_dev: *std_dev,
+            }),
+            ("exp", [mean]) if *mean > 0.0 => Ok(SizeDistribution::Exponential { mean: *mean }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for SizeDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SizeDistribution::Fixed(size) => write!(f, "fixed:{size}"),
+            SizeDistribution::Uniform { min, max } => write!(f, "uniform:{min},{max}"),
+            SizeDistribution::Normal { mean, std_dev } => write!(f, "normal:{mean},{std_dev}"),
+            SizeDistribution::Exponential { mean } => write!(f, "exp:{mean}"),
+        }
+    }
+}
+
+impl SizeDistribution {
+    fn sample(&self, rng: &mut StdRng) -> usize {
+        let size = match *self {
+            SizeDistribution::Fixed(size) => return size.max(1),
+            SizeDistribution::Uniform { min, max } => return rng.gen_range(min..=max),
+            SizeDistribution::Normal { mean, std_dev } => {
+                Normal::new(mean, std_dev).unwrap().sample(rng)
+            }
+            SizeDistribution::Exponential { mean } => Exp::new(1.0 / mean).unwrap().sample(rng),
+        };
+        (size.round() as usize).max(1)
+    }
+}
+
+/// Message to publish.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arrival {
+    /// Scheduled send time, relative to the start of the generation
+    pub offset: Duration,
+    /// Topic of the message, `None` for the default one (only traces set it)
+    pub topic: Option<String>,
+    /// Payload size in bytes
+    pub size: usize,
+}
+
+/// Endless (except for traces) sequence of messages drawn from a traffic model.
+#[derive(Debug)]
+pub struct TrafficGenerator {
+    model: TrafficModel,
+    /// Mean period of the rate driven models, in seconds
+    period: f64,
+    sizes: SizeDistribution,
+    rng: StdRng,
+    /// Index of the next message
+    index: usize,
+    /// Offset of the next Poisson arrival in seconds
+    next: f64,
+    /// Offset of the last message in seconds (jittered messages do not overtake each other)
+    last: f64,
+}
+
+impl TrafficGenerator {
+    /// `rate` in messages per second (ignored by traces), `seed` for reproducible sequences.
+    pub fn new(
+        model: TrafficModel,
+        rate: f64,
+        sizes: SizeDistribution,
+        seed: Option<u64>,
+    ) -> Result<TrafficGenerator, String> {
+        let trace = matches!(model, TrafficModel::Trace(_));
+        if !trace && (rate <= 0.0 || !rate.is_finite()) {
+            return Err(format!("Invalid rate: {rate}"));
+        }
+
+        Ok(TrafficGenerator {
+            model,
+            period: 1.0 / rate,
+            sizes,
+            rng: match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            },
+            index: 0,
+            next: 0.0,
+            last: 0.0,
+        })
+    }
+}
+
+impl Iterator for TrafficGenerator {
+    type Item = Arrival;
+
+    fn next(&mut self) -> Option<Arrival> {
+        let index = self.index;
+        self.index += 1;
+
+        let offset = match self.model {
+            TrafficModel::Trace(ref records) => {
+                let record = records.get(index)?;
+                return Some(Arrival {
+                    offset: Duration::from_secs_f64(record.offset),
+                    topic: Some(record.topic.clone()),
+                    size: record.size,
+                });
+            }
+            TrafficModel::Constant => index as f64 * self.period,
+            TrafficModel::Poisson => {
+                let offset = self.next;
+                self.next += Exp::new(1.0 / self.period).unwrap().sample(&mut self.rng);
+                offset
+            }
+            TrafficModel::OnOff { on, off } => {
+                // Messages fitting in an on period (at least one), the off period follows
+                let burst = ((on.as_secs_f64() / self.period - 1e-9).ceil() as usize).max(1);
+                (index / burst) as f64 * (on + off).as_secs_f64()
+                    + (index % burst) as f64 * self.period
+            }
+            TrafficModel::Jitter(jitter) => {
+                let jitter = jitter.as_secs_f64();
+                let displacement = if jitter > 0.0 {
+                    self.rng.gen_range(-jitter..=jitter)
+                } else {
+                    0.0
+                };
+                let offset = (index as f64 * self.period + displacement).max(self.last);
+                self.last = offset;
+                offset
+            }
+        };
+
+        Some(Arrival {
+            offset: Duration::from_secs_f64(offset),
+            topic: None,
+            size: self.sizes.sample(&mut self.rng),
+        })
+    }
+}
+
+fn parse_params(params: &str) -> Result<Vec<f64>, String> {
+    params
+        .split(',')
+        .map(|param| {
+            // Infinity and NaN parse, but fit no model
+            param
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .ok_or_else(|| format!("Invalid parameter: {param}"))
+        })
+        .collect()
+}
+
+fn millis(millis: f64) -> Duration {
+    Duration::from_secs_f64(millis / 1000.0)
+}
+
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: usize = 100_000;
+
+    fn generator(model: TrafficModel, rate: f64) -> TrafficGenerator {
+        TrafficGenerator::new(model, rate, SizeDistribution::Fixed(10), Some(42)).unwrap()
+    }
+
+    fn offsets(generator: TrafficGenerator, count: usize) -> Vec<f64> {
+        generator
+            .take(count)
+            .map(|arrival| arrival.offset.as_secs_f64())
+            .collect()
+    }
+
+    #[test]
+    fn parse_models() {
+        assert_eq!(
+            TrafficModel::from_str("poisson").unwrap(),
+            TrafficModel::Poisson
+        );
+        assert_eq!(
+            TrafficModel::from_str("on-off:100,400").unwrap(),
+            TrafficModel::OnOff {
+                on: Duration::from_millis(100),
+                off: Duration::from_millis(400)
+            }
+        );
+        assert_eq!(
+            TrafficModel::from_str("jitter:2.5").unwrap(),
+            TrafficModel::Jitter(Duration::from_micros(2500))
+        );
+        for model in ["constant", "on-off:100,400", "jitter:2.5"] {
+            assert_eq!(TrafficModel::from_str(model).unwrap().to_string(), model);
+        }
+        assert!(TrafficModel::from_str("on-off:100").is_err());
+        assert!(TrafficModel::from_str("on-off:inf,0").is_err());
+        assert!(TrafficModel::from_str("jitter:inf").is_err());
+        assert!(TrafficModel::from_str("jitter:nan").is_err());
+        assert!(TrafficModel::from_str("bursty").is_err());
+        assert!(TrafficGenerator::new(
+            TrafficModel::Constant,
+            0.0,
+            SizeDistribution::Fixed(1),
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!(
+            SizeDistribution::from_str("fixed:64").unwrap(),
+            SizeDistribution::Fixed(64)
+        );
+        assert_eq!(
+            SizeDistribution::from_str("uniform:10,20").unwrap(),
+            SizeDistribution::Uniform { min: 10, max: 20 }
+        );
+        for sizes in ["uniform:10,20", "normal:100,12.5", "exp:64"] {
+            assert_eq!(
+                SizeDistribution::from_str(sizes).unwrap().to_string(),
+                sizes
+            );
+        }
+        assert!(SizeDistribution::from_str("uniform:20,10").is_err());
+        assert!(SizeDistribution::from_str("exp:0").is_err());
+        assert!(SizeDistribution::from_str("normal:nan,1").is_err());
+        assert!(SizeDistribution::from_str("64").is_err());
+    }
+
+    #[test]
+    fn constant_fractional_rate() {
+        let offsets = offsets(generator(TrafficModel::Constant, 0.5), 3);
+        assert_eq!(offsets, vec![0.0, 2.0, 4.0]);
+    }
+
+    #[test]
+    fn poisson_inter_arrivals() {
+        let offsets = offsets(generator(TrafficModel::Poisson, 100.0), SAMPLES);
+        let gaps: Vec<f64> = offsets.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        let mean = gaps.iter().sum::<f64>() / gaps.len() as f64;
+        let variance = gaps.iter().map(|gap| (gap - mean).powi(2)).sum::<f64>() / gaps.len() as f64;
+
+        // Exponential: standard deviation equal to the mean
+        assert!((mean - 0.01).abs() < 0.0002);
+        assert!((variance.sqrt() - 0.01).abs() < 0.0002);
+    }
+
+    #[test]
+    fn on_off_bursts() {
+        let model = TrafficModel::OnOff {
+            on: Duration::from_millis(100),
+            off: Duration::from_millis(400),
+        };
+        let offsets = offsets(generator(model, 100.0), 30);
+
+        // 10 messages per burst, one burst every 500 ms
+        assert_eq!(offsets[9], 0.09);
+        assert_eq!(offsets[10], 0.5);
+        assert!((offsets[25] - 1.05).abs() < 1e-9);
+    }
+
+    #[test]
+    fn jitter_keeps_order() {
+        let model = TrafficModel::Jitter(Duration::from_millis(20));
+        let offsets = offsets(generator(model, 100.0), SAMPLES);
+        assert!(offsets.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(offsets
+            .iter()
+            .enumerate()
+            .all(|(i, offset)| *offset <= i as f64 * 0.01 + 0.02));
+    }
+
+    #[test]
+    fn size_distributions() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let uniform = SizeDistribution::Uniform { min: 10, max: 20 };
+        assert!((0..SAMPLES)
+            .map(|_| uniform.sample(&mut rng))
+            .all(|size| (10..=20).contains(&size)));
+
+        let exponential = SizeDistribution::Exponential { mean: 100.0 };
+        let mean = (0..SAMPLES)
+            .map(|_| exponential.sample(&mut rng))
+            .sum::<usize>() as f64
+            / SAMPLES as f64;
+        assert!((mean - 100.0).abs() < 2.0);
+
+        let normal = SizeDistribution::Normal {
+            mean: 0.0,
+            std_dev: 10.0,
+        };
+        assert!((0..SAMPLES).all(|_| normal.sample(&mut rng) >= 1));
+    }
+
+    #[test]
+    fn trace_replay() {
+        let path = std::env::temp_dir().join(format!("trace-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "timestamp,topic,size\n# comment\n100.5,b,20\n100.0,a,10\n\n101.0,c,30\n",
+        )
+        .unwrap();
+        let model = TrafficModel::trace(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let arrivals: Vec<Arrival> = generator(model, 0.0).collect();
+        assert_eq!(
+            arrivals,
+            vec![
+                Arrival {
+                    offset: Duration::ZERO,
+                    topic: Some("a".to_string()),
+                    size: 10
+                },
+                Arrival {
+                    offset: Duration::from_millis(500),
+                    topic: Some("b".to_string()),
+                    size: 20
+                },
+                Arrival {
+                    offset: Duration::from_secs(1),
+                    topic: Some("c".to_string()),
+                    size: 30
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn trace_rejects_non_finite_timestamps() {
+        let path = std::env::temp_dir().join(format!("trace-nan-{}.csv", std::process::id()));
+        for line in ["inf,t,10", "nan,t,10"] {
+            std::fs::write(&path, format!("100.0,t,10\n{line}\n")).unwrap();
+            let err = TrafficModel::trace(&path).unwrap_err();
+            assert!(
+                err.to_string()
+                    .starts_with(&format!("{}:2: ", path.display())),
+                "{err}"
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,471 @@
+use log::warn;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, Paragraph, Row, Sparkline, Table};
+use ratatui::Frame;
+use raw_mqtt::client::metrics::MetricsSnapshot;
+use raw_mqtt::utility::exporter::MetricsRegistry;
+use raw_mqtt::utility::summary::{latency_header, latency_row, short_duration, LatencyHistogram};
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Refresh interval of the dashboard
+const REFRESH: Duration = Duration::from_millis(250);
+/// Window of the current send rate
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+/// Window of the rolling latency percentiles
+const LATENCY_WINDOW: Duration = Duration::from_secs(10);
+/// Send rates kept for the chart, one per refresh
+const RATE_HISTORY: usize = 1024;
+/// Log lines kept while the dashboard is shown, printed once it is closed
+const LOG_LINES: usize = 1000;
+
+/// Log lines written while the dashboard is shown, none otherwise.
+static LOGS: Mutex<Option<CapturedLogs>> = Mutex::new(None);
+
+#[derive(Default)]
+struct CapturedLogs {
+    lines: VecDeque<String>,
+    /// Oldest lines left out once `LOG_LINES` are kept
+    dropped: usize,
+}
+
+/// Log target, the standard error or the dashboard while it is shown.
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match LOGS.lock().unwrap().as_mut() {
+            Some(logs) => {
+                for line in String::from_utf8_lossy(buf).lines() {
+                    if logs.lines.len() == LOG_LINES {
+                        logs.lines.pop_front();
+                        logs.dropped += 1;
+                    }
+                    logs.lines.push_back(line.to_string());
+                }
+                Ok(buf.len())
+            }
+            None => io::stderr().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// Live view of the client metrics on the alternate screen, closed when dropped.
+pub struct Dashboard {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Dashboard {
+    /**
+     * Show the metrics of the registered clients, refreshed on a thread of its own. The log is
+     * shown below and printed out once the dashboard is closed, by dropping it or with "q".
+     * Ctrl-C stops the run.
+     */
+    pub fn start(mut view: View, registry: MetricsRegistry) -> io::Result<Dashboard> {
+        let mut terminal = ratatui::try_init()?;
+        *LOGS.lock().unwrap() = Some(CapturedLogs::default());
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = thread::spawn(move || {
+            let mut draw = || -> io::Result<()> {
+                while !stopped.load(Ordering::SeqCst) {
+                    view.update(Instant::now(), registry.snapshots());
+                    terminal.draw(|frame| view.draw(frame))?;
+                    if !event::poll(REFRESH)? {
+                        continue;
+                    }
+                    if let Event::Key(key) = event::read()? {
+                        match key.code {
+                            _ if key.kind != KeyEventKind::Press => {}
+                            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                // Raw mode catches the interrupt
+                                close();
+                                std::process::exit(130);
+                            }
+                            KeyCode::Char('q') | KeyCode::Esc => break,
+                            _ => {}
+                        }
+                    }
+                }
+                Ok(())
+            };
+            let result = draw();
+            close();
+            if let Err(e) = result {
+                warn!("Dashboard closed: {e}");
+            }
+        });
+
+        Ok(Dashboard {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Restore the terminal and print the log lines written meanwhile.
+fn close() {
+    let _ = ratatui::try_restore();
+    if let Some(logs) = LOGS.lock().unwrap().take() {
+        let mut stderr = io::stderr();
+        if logs.dropped > 0 {
+            let _ = writeln!(stderr, "({} earlier log lines left out)", logs.dropped);
+        }
+        for line in logs.lines {
+            let _ = writeln!(stderr, "{line}");
+        }
+    }
+}
+
+/// Latest log lines, at most `count`.
+fn recent_logs(count: usize) -> Vec<String> {
+    match LOGS.lock().unwrap().as_ref() {
+        Some(logs) => logs
+            .lines
+            .iter()
+            .skip(logs.lines.len().saturating_sub(count))
+            .cloned()
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Dashboard state, updated from the client metrics at every refresh.
+pub struct View {
+    title: String,
+    /// Send rate of the traffic model over every client, none for traces
+    target_rate: Option<f64>,
+    /// Send queue size of a client, none if unbounded
+    queue: Option<usize>,
+    started: Instant,
+    /// Messages sent and ack latencies over every client at each refresh, back to the latency
+    /// window
+    history: VecDeque<(Instant, u64, LatencyHistogram)>,
+    /// Send rate at each refresh
+    rates: VecDeque<u64>,
+    rate: f64,
+    /// Ack latencies over the latency window
+    latency: LatencyHistogram,
+    clients: Vec<(String, MetricsSnapshot)>,
+}
+
+impl View {
+    pub fn new(title: String, target_rate: Option<f64>, queue: Option<usize>) -> View {
+        View {
+            title,
+            target_rate,
+            queue,
+            started: Instant::now(),
+            history: VecDeque::new(),
+            rates: VecDeque::new(),
+            rate: 0.0,
+            latency: LatencyHistogram::new(),
+            clients: Vec::new(),
+        }
+    }
+
+    fn update(&mut self, now: Instant, clients: Vec<(String, MetricsSnapshot)>) {
+        // Clients are only ever added, the totals only grow
+        let sent = clients.iter().map(|(_, snapshot)| snapshot.sent).sum();
+        let mut latency = LatencyHistogram::new();
+        for (_, snapshot) in &clients {
+            latency.merge(&snapshot.ack_latency);
+        }
+        self.history.push_back((now, sent, latency));
+        while self.history.len() > 1
+            && now.saturating_duration_since(self.history[1].0) >= LATENCY_WINDOW
+        {
+            self.history.pop_front();
+        }
+
+        let (_, _, latest) = self.history.back().unwrap();
+        self.latency = latest.since(&self.history.front().unwrap().2);
+        let (since, sent_since, _) = self
+            .history
+            .iter()
+            .rev()
+            .find(|(time, _, _)| now.saturating_duration_since(*time) >= RATE_WINDOW)
+            .unwrap_or(self.history.front().unwrap());
+        let elapsed = now.saturating_duration_since(*since).as_secs_f64();
+        self.rate = if elapsed > 0.0 {
+            (sent - sent_since) as f64 / elapsed
+        } else {
+            0.0
+        };
+
+        if self.rates.len() == RATE_HISTORY {
+            self.rates.pop_front();
+        }
+        self.rates.push_back(self.rate.round() as u64);
+        self.clients = clients;
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [header, rate, queue, chart, totals, latency, clients, logs] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(6),
+            Constraint::Length(4),
+            Constraint::Length(4),
+            Constraint::Min(4),
+            Constraint::Length(8),
+        ])
+        .areas(frame.area());
+
+        let elapsed = self.started.elapsed().as_secs();
+        frame.render_widget(
+            Paragraph::new(Line::from(vec![
+                self.title.clone().bold(),
+                format!(
+                    "  {:02}:{:02}:{:02}  (q closes the dashboard, Ctrl-C stops the run)",
+                    elapsed / 3600,
+                    elapsed / 60 % 60,
+                    elapsed % 60
+                )
+                .into(),
+            ])),
+            header,
+        );
+
+        // Send rate against the traffic model
+        let (label, ratio) = match self.target_rate {
+            Some(target) if target > 0.0 => (
+                format!(
+                    "{:.1} / {:.1} messages/s ({:.1}%)",
+                    self.rate,
+                    target,
+                    100.0 * self.rate / target
+                ),
+                self.rate / target,
+            ),
+            _ => (format!("{:.1} messages/s", self.rate), 0.0),
+        };
+        frame.render_widget(
+            Gauge::default()
+                .block(Block::bordered().title("Send rate"))
+                .gauge_style(Style::default().fg(Color::Green))
+                .label(label)
+                .ratio(ratio.clamp(0.0, 1.0)),
+            rate,
+        );
+
+        // Send queues of every client
+        let total = |value: fn(&MetricsSnapshot) -> u64| -> u64 {
+            self.clients
+                .iter()
+                .map(|(_, snapshot)| value(snapshot))
+                .sum()
+        };
+        let queued = total(|snapshot| snapshot.queued as u64);
+        let (label, ratio) = match self.queue {
+            Some(size) => {
+                let capacity = (size * self.clients.len().max(1)) as u64;
+                (
+                    format!("{queued} / {capacity} queued"),
+                    queued as f64 / capacity as f64,
+                )
+            }
+            None => (format!("{queued} queued (unbounded)"), 0.0),
+        };
+        frame.render_widget(
+            Gauge::default()
+                .block(Block::bordered().title("Send queue"))
+                .gauge_style(Style::default().fg(Color::Yellow))
+                .label(label)
+                .ratio(ratio.clamp(0.0, 1.0)),
+            queue,
+        );
+
+        // Latest send rates, as many as fit
+        let width = chart.width.saturating_sub(2) as usize;
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title("Send rate history"))
+                .style(Style::default().fg(Color::Cyan))
+                .data(
+                    self.rates
+                        .iter()
+                        .skip(self.rates.len().saturating_sub(width))
+                        .copied(),
+                ),
+            chart,
+        );
+
+        let rtts: Vec<Duration> = self
+            .clients
+            .iter()
+            .filter_map(|(_, snapshot)| snapshot.transport_rtt)
+            .collect();
+        let rtt = match (rtts.iter().min(), rtts.iter().max()) {
+            (Some(min), Some(max)) if min == max => duration(*min),
+            (Some(min), Some(max)) => format!("{} to {}", duration(*min), duration(*max)),
+            _ => "-".to_string(),
+        };
+        frame.render_widget(
+            Paragraph::new(vec![
+                Line::from(format!(
+                    "Sent {}   Acked {}   In flight {}   Transport RTT {rtt}",
+                    total(|snapshot| snapshot.sent),
+                    total(|snapshot| snapshot.acked),
+                    total(|snapshot| snapshot.in_flight as u64),
+                )),
+                Line::from(format!(
+                    "Dropped {}   Replaced {}   Lost {}   Reconnects {}",
+                    total(|snapshot| snapshot.dropped),
+                    total(|snapshot| snapshot.replaced),
+                    total(|snapshot| snapshot.lost),
+                    total(|snapshot| snapshot.reconnects),
+                )),
+            ])
+            .block(Block::bordered().title(format!("{} client(s)", self.clients.len()))),
+            totals,
+        );
+
+        let rows = match self.latency.summary() {
+            Some(summary) => vec![
+                Line::from(latency_header()),
+                Line::from(latency_row("ack", &summary)),
+            ],
+            None => vec![Line::from("No acks")],
+        };
+        frame.render_widget(
+            Paragraph::new(rows).block(
+                Block::bordered().title(format!("Latency, last {} s", LATENCY_WINDOW.as_secs())),
+            ),
+            latency,
+        );
+
+        let header = Row::new([
+            "client",
+            "sent",
+            "acked",
+            "in flight",
+            "queued",
+            "dropped",
+            "replaced",
+            "lost",
+            "reconnects",
+            "RTT",
+        ])
+        .bold();
+        let rows = self.clients.iter().map(|(client, snapshot)| {
+            Row::new([
+                client.clone(),
+                snapshot.sent.to_string(),
+                snapshot.acked.to_string(),
+                snapshot.in_flight.to_string(),
+                snapshot.queued.to_string(),
+                snapshot.dropped.to_string(),
+                snapshot.replaced.to_string(),
+                snapshot.lost.to_string(),
+                snapshot.reconnects.to_string(),
+                snapshot.transport_rtt.map_or("-".to_string(), duration),
+            ])
+        });
+        frame.render_widget(
+            Table::new(rows, [Constraint::Fill(1); 10])
+                .header(header)
+                .block(Block::bordered().title("Clients")),
+            clients,
+        );
+
+        let lines = recent_logs(logs.height.saturating_sub(2) as usize);
+        frame.render_widget(
+            Paragraph::new(lines.into_iter().map(Line::from).collect::<Vec<_>>())
+                .block(Block::bordered().title("Log")),
+            logs,
+        );
+    }
+}
+
+fn duration(duration: Duration) -> String {
+    short_duration(duration.as_nanos() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn snapshot(sent: u64, latencies: &[u64]) -> MetricsSnapshot {
+        MetricsSnapshot {
+            sent,
+            acked: latencies.len() as u64,
+            queued: 5,
+            ack_latency: latencies
+                .iter()
+                .copied()
+                .map(Duration::from_millis)
+                .collect(),
+            transport_rtt: Some(Duration::from_micros(250)),
+            ..MetricsSnapshot::default()
+        }
+    }
+
+    #[test]
+    fn rolling_view() {
+        let mut view = View::new("test".to_string(), Some(200.0), Some(10));
+        let now = Instant::now();
+        view.update(now, vec![("0".to_string(), snapshot(0, &[]))]);
+        view.update(
+            now + Duration::from_secs(1),
+            vec![("0".to_string(), snapshot(100, &[1, 2]))],
+        );
+        view.update(
+            now + Duration::from_secs(11),
+            vec![
+                ("0".to_string(), snapshot(300, &[1, 2, 40])),
+                ("1".to_string(), snapshot(0, &[])),
+            ],
+        );
+
+        // Over the last second only, the first latencies are past the window
+        assert_eq!(view.rate, 20.0);
+        assert_eq!(view.latency.len(), 1);
+        assert!(view.latency.quantile(0.5) >= Duration::from_millis(39));
+        assert_eq!(view.history.len(), 2);
+        assert_eq!(view.rates, [0, 100, 20]);
+
+        let mut terminal = Terminal::new(TestBackend::new(120, 40)).unwrap();
+        terminal.draw(|frame| view.draw(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        for text in [
+            "20.0 / 200.0 messages/s (10.0%)",
+            "10 / 20 queued",
+            "Sent 300   Acked 3   In flight 0   Transport RTT 250µs",
+            "2 client(s)",
+        ] {
+            assert!(screen.contains(text), "{text} missing");
+        }
+    }
+}
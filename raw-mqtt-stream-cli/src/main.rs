@@ -1,154 +1,946 @@
+use chrono::{DateTime, Utc};
 use clap::Parser;
-use log::{debug, info, LevelFilter};
+use log::{debug, info, warn, LevelFilter};
 use mqttbytes::QoS;
+use raw_mqtt::client::rtt::{self, RttSample, SEQUENCE_LEN, TIMESTAMP_LEN};
 use raw_mqtt::client::stream_client::StreamMqttClient;
 use raw_mqtt::network::transport::Transport;
-use raw_mqtt::utility::argument_parser::Request;
-use raw_mqtt::utility::stream_argument_parser::MqttStreamCli;
+use raw_mqtt::utility::argument_parser::{Args, SubscribeArgs};
+use raw_mqtt::utility::exporter::MetricsRegistry;
+use raw_mqtt::utility::output::{
+    ConnectionEvent, MessageEvent, MessageRecord, Output, RunRecord, StatsRecord, TransportSession,
+};
+use raw_mqtt::utility::pacing::{Pacer, PacingRecord};
+use raw_mqtt::utility::stream_argument_parser::{BenchCommand, MqttStreamCli, PublishStreamArgs};
+use raw_mqtt::utility::summary::{LatencyHistogram, RunSummary};
+use raw_mqtt::utility::traffic::{Arrival, SizeDistribution, TrafficGenerator, TrafficModel};
 use raw_mqtt::Version;
+use serde::Serialize;
+use serde_json::{Map, Value};
 use std::error;
+use std::path::Path;
 use std::str::FromStr;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::{Builder, Runtime};
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), Box<dyn error::Error>> {
+use crate::dashboard::{Dashboard, LogWriter, View};
+use crate::scenario::Scenario;
+
+mod dashboard;
+mod saturate;
+mod scenario;
+mod sweep;
+
+/// Polling interval of the send queue and pending acks while draining
+const DRAIN_POLL: Duration = Duration::from_millis(10);
+
+/// Stream settings shared by every client.
+struct Stream {
+    args: PublishStreamArgs,
+    /// Publishing clients, from `--clients` unless a scenario decides
+    publishers: usize,
+    transport: Transport,
+    qos: QoS,
+    /// Payloads must carry the send timestamp (RTT mode and scenarios)
+    timestamped: bool,
+    /// Payloads carry the publisher index and a sequence number after the send timestamp
+    /// (scenarios)
+    sequenced: bool,
+    payload: String,
+    /// Traffic model or trace, every client publishes a single message if not set
+    model: Option<TrafficModel>,
+    sizes: SizeDistribution,
+    /// Structured results (if requested)
+    output: Option<Output>,
+    /// Live metrics of every client, served if requested
+    metrics: MetricsRegistry,
+}
+
+/// Results of one client, over the measurement window.
+struct ClientReport {
+    id: usize,
+    records: Vec<PacingRecord>,
+    skipped: usize,
+    replaced: usize,
+    lost: usize,
+    rtt_samples: Vec<RttSample>,
+    ack_latency: LatencyHistogram,
+    /// Send window, from the end of the warm-up to the last message measured
+    started: Instant,
+    finished: Instant,
+    events: Vec<ConnectionEvent>,
+    /// Publishes, acks and reflected messages over the window, kept for the structured results
+    messages: Vec<MessageRecord>,
+    /// Transport statistics samples, kept for the structured results
+    stats: Vec<StatsRecord>,
+}
+
+fn main() -> Result<(), Box<dyn error::Error>> {
     // Parse command line arguments
-    let (request, args, message_payload, rate, duration, queue, nagle) =
-        match MqttStreamCli::parse() {
-            MqttStreamCli::Publish(stream_args) => {
-                let payload = match stream_args.publish_args.size {
-                    Some(size) => String::from_utf8(vec![127_u8; size]).unwrap(),
-                    None => stream_args.publish_args.message.unwrap(),
-                };
-                (
-                    Request::Publish,
-                    stream_args.publish_args.common_args,
-                    Some(payload),
-                    if stream_args.rate > 0.0 {
-                        Some(stream_args.rate)
-                    } else {
-                        None
-                    },
-                    if stream_args.duration > 0 {
-                        Some(stream_args.duration)
-                    } else {
-                        None
-                    },
-                    Some(stream_args.queue),
-                    Some(!stream_args.nagle_off),
-                )
-            }
-            MqttStreamCli::Subscribe(subscribe_args) => (
-                Request::Subscribe,
-                subscribe_args.common_args,
-                None,
-                None,
-                None,
-                None,
-                None,
-            ),
+    match MqttStreamCli::parse() {
+        MqttStreamCli::Publish(stream_args) => {
+            init_logger(stream_args.publish_args.common_args.debug, stream_args.tui);
+            runtime(stream_args.threads)?.block_on(publish(stream_args))
+        }
+        MqttStreamCli::Subscribe(subscribe_args) => {
+            init_logger(subscribe_args.common_args.debug, false);
+            runtime(1)?.block_on(subscribe(subscribe_args))
+        }
+        MqttStreamCli::FanOut(scenario_args) => {
+            init_logger(
+                scenario_args.stream_args.publish_args.common_args.debug,
+                scenario_args.stream_args.tui,
+            );
+            runtime(scenario_args.stream_args.threads)?
+                .block_on(scenario::run(Scenario::FanOut, scenario_args))
+        }
+        MqttStreamCli::FanIn(scenario_args) => {
+            init_logger(
+                scenario_args.stream_args.publish_args.common_args.debug,
+                scenario_args.stream_args.tui,
+            );
+            runtime(scenario_args.stream_args.threads)?
+                .block_on(scenario::run(Scenario::FanIn, scenario_args))
+        }
+        MqttStreamCli::Bench(BenchCommand::Saturate(saturate_args)) => {
+            init_logger(saturate_args.common_args.debug, false);
+            runtime(1)?.block_on(saturate::run(saturate_args))
+        }
+        MqttStreamCli::Bench(BenchCommand::Sweep(sweep_args)) => {
+            init_logger(sweep_args.debug, false);
+            sweep::run(sweep_args)
+        }
+    }
+}
+
+/// Log to the standard error, through the dashboard with `tui` (shown there while it is open).
+fn init_logger(debug: bool, tui: bool) {
+    let mut builder = env_logger::builder();
+    builder.filter_level(if debug {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    if tui {
+        builder.target(env_logger::Target::Pipe(Box::new(LogWriter)));
+    }
+    builder.init();
+}
+
+/// Runtime of the clients: the main thread only, or `threads` workers (0 for one per core).
+fn runtime(threads: usize) -> std::io::Result<Runtime> {
+    match threads {
+        1 => Builder::new_current_thread().enable_all().build(),
+        0 => Builder::new_multi_thread().enable_all().build(),
+        threads => Builder::new_multi_thread()
+            .worker_threads(threads)
+            .enable_all()
+            .build(),
+    }
+}
+
+async fn publish(stream_args: PublishStreamArgs) -> Result<(), Box<dyn error::Error>> {
+    let started = Utc::now();
+    let stream = Arc::new(Stream::new(stream_args)?);
+    let reports = start_publishers(&stream).await?;
+
+    let summary = if stream.model.is_some() {
+        let summary = log_reports(&stream, &reports, None);
+        if let Some(ref path) = stream.args.pacing_log {
+            write_pacing_log(path, &reports)?;
+            info!("Saved send times to {}", path.display());
+        }
+        if let Some(ref path) = stream.args.summary {
+            write_json(path, &summary)?;
+            info!("Saved the run summary to {}", path.display());
+        }
+        Some(summary)
+    } else {
+        None
+    };
+    write_output(
+        &stream,
+        stream.args.parameters(),
+        started,
+        &reports,
+        Vec::new(),
+        Vec::new(),
+        summary,
+    )?;
+
+    Ok(())
+}
+
+impl Stream {
+    fn new(stream_args: PublishStreamArgs) -> Result<Stream, Box<dyn error::Error>> {
+        let args = &stream_args.publish_args.common_args;
+        debug!("{:?}", args);
+
+        let payload = match stream_args.publish_args.size {
+            Some(size) => String::from_utf8(vec![127_u8; size]).unwrap(),
+            None => stream_args.publish_args.message.clone().unwrap(),
         };
 
-    // Set log level
-    env_logger::builder()
-        .filter_level(if args.debug {
-            LevelFilter::Debug
-        } else {
-            LevelFilter::Info
-        })
-        .init();
+        // Stream messages following a traffic model or a trace (if requested)
+        let model = match stream_args.trace {
+            Some(ref path) => Some(TrafficModel::trace(path)?),
+            None if stream_args.rate > 0.0 => Some(stream_args.model.clone()),
+            None => None,
+        };
+        let sizes = stream_args
+            .size_dist
+            .unwrap_or(SizeDistribution::Fixed(payload.len()));
 
-    debug!("{:?}", args);
+        // Measure round-trip times against a reflector (if requested)
+        if stream_args.rtt && model.is_none() {
+            Err("RTT mode requires a rate or a trace")?;
+        }
 
-    let proto_version = Version::from_str(args.proto_version.as_str()).unwrap();
-    let mut transport = Transport::from_str(args.transport.as_str()).unwrap();
-    match transport {
-        Transport::TCP(ref mut config) => {
-            config.nagle = nagle.unwrap();
+        // Clients with the same id would take each other over on the broker
+        let clients = stream_args.clients;
+        if clients == 0 {
+            Err("At least one client is required")?;
+        }
+        if clients > 1
+            && stream_args
+                .client_id
+                .as_ref()
+                .is_some_and(|client_id| !client_id.contains("{id}"))
+        {
+            Err("The client id must contain {id} with several clients")?;
+        }
+
+        // Measurement window
+        for period in [stream_args.warm_up, stream_args.cool_down] {
+            if !(period >= 0.0 && period.is_finite()) {
+                Err("Invalid warm-up or cool-down")?;
+            }
+        }
+        if stream_args.cool_down > 0.0 && stream_args.duration == 0 {
+            Err("The cool-down requires a duration")?;
         }
-        Transport::TLS(ref mut config) => {
-            config.insecure = args.insecure;
-            config.nagle = nagle.unwrap();
+
+        let mut transport = args.transport()?;
+        match transport {
+            Transport::TCP(ref mut config) => {
+                config.nagle = !stream_args.nagle_off;
+            }
+            Transport::TLS(ref mut config) => {
+                config.nagle = !stream_args.nagle_off;
+            }
+            Transport::QUIC(_) | Transport::Memory(_) => {}
         }
-        Transport::QUIC(ref mut config) => {
-            config.insecure = args.insecure;
+
+        let output = stream_args.publish_args.output_args.output()?;
+
+        Ok(Stream {
+            publishers: clients,
+            qos: qos(args.qos),
+            timestamped: stream_args.rtt,
+            sequenced: false,
+            args: stream_args,
+            transport,
+            payload,
+            model,
+            sizes,
+            output,
+            metrics: MetricsRegistry::new(),
+        })
+    }
+
+    /// Measurement window from the start of the schedule, open-ended without a duration.
+    fn window(&self) -> (Duration, Option<Duration>) {
+        let warm_up = Duration::from_secs_f64(self.args.warm_up);
+        let until = match self.args.duration {
+            0 => None,
+            duration => Some(warm_up + Duration::from_secs(duration as u64)),
+        };
+        (warm_up, until)
+    }
+
+    /// Rate of the traffic model over `clients`, none for traces.
+    fn requested_rate(&self, clients: usize) -> Option<f64> {
+        match self.args.trace {
+            Some(_) => None,
+            None => Some(self.args.rate * clients as f64),
         }
     }
+}
 
-    let qos = match { args.qos } {
-        0 => QoS::AtMostOnce,
-        1 => QoS::AtLeastOnce,
-        2 => QoS::ExactlyOnce,
-        _ => panic!("Invalid QoS value"),
+/// Run every publisher, started over the ramp-up, until their streams end.
+async fn start_publishers(
+    stream: &Arc<Stream>,
+) -> Result<Vec<ClientReport>, Box<dyn error::Error>> {
+    let publishers = stream.publishers;
+    if let Some(address) = stream.args.metrics {
+        let address = stream.metrics.serve(address).await?;
+        info!("Serving metrics on http://{address}/metrics");
+    }
+    // Closed once every publisher is done, or on error
+    let dashboard = if stream.args.tui {
+        let args = &stream.args.publish_args.common_args;
+        let view = View::new(
+            format!(
+                "{} publisher(s) to {}:{} over {}, QoS {}",
+                publishers, args.host, args.port, args.transport, args.qos
+            ),
+            stream
+                .model
+                .as_ref()
+                .and_then(|_| stream.requested_rate(publishers)),
+            match stream.args.queue {
+                -1 => None,
+                // A single message for the LIFO queue
+                queue => Some(queue.max(1) as usize),
+            },
+        );
+        Some(Dashboard::start(view, stream.metrics.clone())?)
+    } else {
+        None
     };
+    if stream.model.is_some() {
+        info!(
+            "Sending messages for {}{} from {} client(s)",
+            match stream.args.duration {
+                0 => "the whole trace".to_string(),
+                duration => format!("{duration} s"),
+            },
+            match (stream.args.warm_up, stream.args.cool_down) {
+                (warm_up, cool_down) if warm_up > 0.0 || cool_down > 0.0 => {
+                    format!(" (plus {warm_up} s warm-up, {cool_down} s cool-down)")
+                }
+                _ => String::new(),
+            },
+            publishers
+        );
+    }
 
-    let mut client = StreamMqttClient::new(
-        args.host,
-        args.server_name,
-        args.port.to_string(),
-        transport,
-        proto_version,
-    );
+    let ramp_up = Duration::from_millis(stream.args.ramp_up);
+    let tasks: Vec<_> = (0..publishers)
+        .map(|id| {
+            let stream = stream.clone();
+            let delay = ramp_up.mul_f64(id as f64 / publishers as f64);
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                publisher(&stream, id).await.map_err(|e| e.to_string())
+            })
+        })
+        .collect();
+
+    let mut reports = Vec::with_capacity(publishers);
+    for task in tasks {
+        reports.push(task.await??);
+    }
+    drop(dashboard);
+    Ok(reports)
+}
+
+/// Connect client `id` and publish its message stream.
+async fn publisher(stream: &Stream, id: usize) -> Result<ClientReport, Box<dyn error::Error>> {
+    let args = &stream.args.publish_args.common_args;
+    let label = label(stream.publishers, id);
+    let (measured_from, measured_until) = stream.window();
+
+    // Per-client schedule, random models are seeded differently for every client
+    let mut pacer = match stream.model {
+        Some(ref model) => {
+            let seed = stream.args.seed.map(|seed| seed.wrapping_add(id as u64));
+            let traffic =
+                TrafficGenerator::new(model.clone(), stream.args.rate, stream.sizes, seed)?;
+            let arrivals: Box<dyn Iterator<Item = Arrival> + Send> = match measured_until {
+                None => Box::new(traffic),
+                Some(until) => {
+                    let end = until + Duration::from_secs_f64(stream.args.cool_down);
+                    Box::new(traffic.take_while(move |arrival| arrival.offset < end))
+                }
+            };
+            Some(Pacer::new(
+                arrivals,
+                stream.args.missed_ticks,
+                stream.args.batch,
+            ))
+        }
+        None => None,
+    };
+
+    let mut client = new_client(args, &stream.transport);
+    if let Some(ref client_id) = stream.args.client_id {
+        client.set_client_id(template(client_id, id));
+    }
 
     // Set queue size
-    if queue.is_some() {
-        client.set_queue(queue.unwrap());
+    client.set_queue(stream.args.queue);
+
+    // Set transport statistics sampling interval
+    if stream.args.stats_interval > 0 {
+        client.set_stats_interval(Duration::from_millis(stream.args.stats_interval));
     }
 
-    client.connect().await?;
-    match request {
-        Request::Publish => {
-            let payload = message_payload.unwrap();
-            match rate {
-                Some(rate) => {
-                    let num_messages = duration.unwrap() * rate as usize;
-                    let rate = 1.0 / rate;
-
-                    info!("Sending {} messages", num_messages);
-
-                    let mut interval_timer = tokio::time::interval(
-                        chrono::Duration::microseconds(
-                            Duration::from_secs_f64(rate).as_micros() as i64
+    client.set_rtt(stream.args.rtt);
+    client.set_ack_latency(stream.qos != QoS::AtMostOnce);
+    let metrics = client.metrics();
+    stream.metrics.register(id.to_string(), metrics.clone());
+    let mut events = vec![connect(&mut client, args, id).await?];
+
+    // Report transport statistics next to the message results
+    let stats = client.stats();
+    let stats_label = label.clone();
+    let stats_collector = tokio::spawn(async move {
+        let mut records = Vec::new();
+        while let Ok(sample) = stats.recv().await {
+            info!("{stats_label}Transport stats: {:?}", sample);
+            records.push(StatsRecord::new(id, &sample));
+        }
+        records
+    });
+
+    // Collect round-trip times until every reflected message is back
+    let rtt_samples = client.rtt_samples();
+    let rtt_collector = tokio::spawn(async move {
+        let mut samples = Vec::new();
+        while let Ok(sample) = rtt_samples.recv().await {
+            debug!(
+                "RTT: {:?} (upstream {} ns, downstream {} ns)",
+                sample.rtt(),
+                sample.upstream(),
+                sample.downstream()
+            );
+            samples.push(sample);
+        }
+        samples
+    });
+
+    // Collect ack latencies with the publish time of their message
+    let ack_latencies = client.ack_latencies();
+    let ack_collector = tokio::spawn(async move {
+        let mut latencies = Vec::new();
+        while let Ok(latency) = ack_latencies.recv().await {
+            latencies.push((Instant::now() - latency, latency));
+        }
+        latencies
+    });
+
+    let topic = template(&args.topic, id);
+    let mut records = Vec::new();
+    let mut replaced = 0;
+    // Results are taken over the measurement window only
+    let scheduled_at = Instant::now();
+    let timestamp = rtt::timestamp();
+    let started = scheduled_at + measured_from;
+    let mut finished = started;
+    match pacer {
+        Some(ref mut pacer) => {
+            pacer.start();
+            let mut skipped = 0;
+            let mut sequence = 0;
+            while let Some(batch) = pacer.next_batch().await {
+                metrics.add_dropped((pacer.skipped() - skipped) as u64);
+                skipped = pacer.skipped();
+                for paced in batch {
+                    // Generate new data, the send timestamp (and sequence) must fit in RTT
+                    // mode and scenarios
+                    let mut header = rtt::timestamp().to_string();
+                    if stream.sequenced {
+                        header.push_str(&rtt::sequence_header(id, sequence));
+                    }
+                    sequence += 1;
+                    let size = if stream.sequenced {
+                        paced.arrival.size.max(TIMESTAMP_LEN + SEQUENCE_LEN)
+                    } else if stream.timestamped {
+                        paced.arrival.size.max(TIMESTAMP_LEN)
+                    } else {
+                        paced.arrival.size
+                    };
+                    let topic = match paced.arrival.topic {
+                        Some(ref topic) => template(topic, id),
+                        None => topic.clone(),
+                    };
+                    let measured = paced.scheduled >= measured_from
+                        && measured_until.is_none_or(|until| paced.scheduled < until);
+
+                    // Publish new message (stream publish)
+                    if measured {
+                        records.push(PacingRecord {
+                            scheduled: paced.scheduled,
+                            actual: pacer.elapsed(),
+                        });
+                    }
+                    let replaced_before = client.replaced();
+                    client
+                        .stream_publish(
+                            topic,
+                            timestamped_payload(header, &stream.payload, size),
+                            stream.qos,
                         )
-                        .to_std()
-                        .unwrap(),
-                    );
-
-                    for _ in 0..num_messages {
-                        // Wait for the next tick
-                        interval_timer.tick().await;
-
-                        // Generate new data
-                        let generation_timestamp = SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .expect("Time went backwards")
-                            .as_nanos();
-
-                        let topic = args.topic.to_string();
-
-                        // Publish new message (stream publish)
-                        client
-                            .stream_publish(
-                                topic.to_string(),
-                                format!("{:}{:}", generation_timestamp, payload)[0..payload.len()]
-                                    .to_string(),
-                                qos,
-                            )
-                            .await?;
+                        .await?;
+                    if measured {
+                        replaced += client.replaced() - replaced_before;
+                        finished = Instant::now();
                     }
                 }
-                None => {
-                    info!("Publishing message of size: {}", payload.len());
-                    client.publish(args.topic, payload, qos).await?;
-                }
             }
         }
-        Request::Subscribe => {
-            todo!("Subscribe not implemented yet")
+        None => {
+            info!(
+                "{label}Publishing message of size: {}",
+                stream.payload.len()
+            );
+            client
+                .publish(topic, stream.payload.clone(), stream.qos)
+                .await?;
+            finished = Instant::now();
         }
     }
 
+    // Wait for the queue to drain, then for the missing acks and reflected messages
+    while client.queued() > 0 {
+        tokio::time::sleep(DRAIN_POLL).await;
+    }
+    let drain = Duration::from_millis(stream.args.drain);
+    let deadline = Instant::now() + drain;
+    while client.pending() > 0 && Instant::now() < deadline {
+        tokio::time::sleep(DRAIN_POLL).await;
+    }
+    let lost = client.abandon_pending();
+    if lost > 0 {
+        warn!("{label}{lost} acks or reflected messages missing after {drain:?}");
+    }
+
+    events.push(disconnect(&mut client, id).await?);
+    let stats = stats_collector.await?;
+
+    // Leave out the messages sent during the warm-up and cool-down
+    let in_window = |offset: Duration| {
+        offset >= measured_from && measured_until.is_none_or(|until| offset < until)
+    };
+    let rtt_samples: Vec<RttSample> = rtt_collector
+        .await?
+        .into_iter()
+        .filter(|sample| {
+            in_window(Duration::from_nanos(
+                sample.sent.saturating_sub(timestamp) as u64
+            ))
+        })
+        .collect();
+    let acks: Vec<(Instant, Duration)> = ack_collector
+        .await?
+        .into_iter()
+        .filter(|(sent, _)| in_window(sent.saturating_duration_since(scheduled_at)))
+        .collect();
+    let ack_latency = acks.iter().map(|(_, latency)| *latency).collect();
+
+    // Every message in time order, the acks timed from the start of the schedule
+    let mut messages = Vec::new();
+    if stream.output.is_some() {
+        messages.extend(
+            records
+                .iter()
+                .map(|record| MessageRecord::paced(id, timestamp, record)),
+        );
+        messages.extend(acks.iter().map(|(sent, latency)| {
+            let received = (*sent + *latency).saturating_duration_since(scheduled_at);
+            MessageRecord::received(
+                id,
+                MessageEvent::Ack,
+                timestamp + received.as_nanos(),
+                *latency,
+            )
+        }));
+        messages.extend(rtt_samples.iter().map(|sample| {
+            MessageRecord::received(id, MessageEvent::Reflect, sample.received, sample.rtt())
+        }));
+        messages.sort_by_key(|message| message.timestamp_ns);
+    }
+
+    Ok(ClientReport {
+        id,
+        records,
+        skipped: pacer.map_or(0, |pacer| pacer.skipped()),
+        replaced,
+        lost,
+        rtt_samples,
+        ack_latency,
+        started,
+        finished,
+        events,
+        messages,
+        stats,
+    })
+}
+
+async fn subscribe(subscribe_args: SubscribeArgs) -> Result<(), Box<dyn error::Error>> {
+    let args = subscribe_args.common_args;
+    debug!("{:?}", args);
+    let output = subscribe_args.output_args.output()?;
+    let parameters = args.parameters();
+    let started = Utc::now();
+
+    let mut client = new_client(&args, &args.transport()?);
+    client.subscribe(args.topic.clone(), qos(args.qos));
+    let connected = connect(&mut client, &args, 0).await?;
+    let session = connected.session.clone();
+    let mut events = vec![connected];
+    info!("Subscribed to {}, waiting for messages", args.topic);
+
+    // Until interrupted or disconnected by the broker
+    let messages = client.messages();
+    let mut received = Vec::new();
+    let mut closed = false;
+    let interrupted = tokio::signal::ctrl_c();
+    tokio::pin!(interrupted);
+    loop {
+        let message = tokio::select! {
+            _ = &mut interrupted => break,
+            message = messages.recv() => message,
+        };
+        let Ok(message) = message else {
+            warn!("Connection closed");
+            closed = true;
+            break;
+        };
+        info!(
+            "Received message of size {} on {}",
+            message.publish.payload.len(),
+            message.publish.topic
+        );
+        received.push(MessageRecord::delivered(
+            0,
+            &message.publish.payload,
+            message.received,
+        ));
+    }
+
+    if closed {
+        events.push(ConnectionEvent::disconnected(
+            0,
+            client.client_id(),
+            Duration::ZERO,
+        ));
+    } else {
+        events.push(disconnect(&mut client, 0).await?);
+    }
+
+    if let Some(output) = output {
+        let run = RunRecord::new(
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            parameters,
+            started,
+            session,
+            Map::new(),
+        );
+        output.write("events", &events)?;
+        output.write("messages", &received)?;
+        output.write("run", &[run])?;
+        info!("Saved the results to {}", output.directory().display());
+    }
+    Ok(())
+}
+
+fn new_client(args: &Args, transport: &Transport) -> StreamMqttClient {
+    let proto_version = Version::from_str(args.proto_version.as_str()).unwrap();
+    let mut client = StreamMqttClient::new(
+        args.host.clone(),
+        args.server_name.clone(),
+        args.port.to_string(),
+        transport.clone(),
+        proto_version,
+    );
+    // Checked for pings, at most u16::MAX
+    client.set_keep_alive(args.keep_alive.min(u16::MAX as u64) as u16);
+    client
+}
+
+/// Connect client `id`, the first one reports the TLS session and broker certificate.
+async fn connect(
+    client: &mut StreamMqttClient,
+    args: &Args,
+    id: usize,
+) -> Result<ConnectionEvent, Box<dyn error::Error>> {
+    let connecting = Instant::now();
+    client.connect().await?;
+    let session = client.session_info();
+    let event = ConnectionEvent::connected(
+        id,
+        client.client_id(),
+        connecting.elapsed(),
+        TransportSession::new(&args.transport, client.peer_addr(), session.as_ref()),
+    );
+    if id > 0 {
+        return Ok(event);
+    }
+
+    // Report the negotiated TLS session and broker certificate (if requested)
+    args.report_session(session.as_ref())?;
+
+    Ok(event)
+}
+
+/// Disconnect client `id`, once its queue and pending acks are through.
+async fn disconnect(
+    client: &mut StreamMqttClient,
+    id: usize,
+) -> Result<ConnectionEvent, Box<dyn error::Error>> {
+    let disconnecting = Instant::now();
     client.disconnect().await?;
+    Ok(ConnectionEvent::disconnected(
+        id,
+        client.client_id(),
+        disconnecting.elapsed(),
+    ))
+}
+
+fn qos(qos: u8) -> QoS {
+    match qos {
+        0 => QoS::AtMostOnce,
+        1 => QoS::AtLeastOnce,
+        2 => QoS::ExactlyOnce,
+        _ => panic!("Invalid QoS value"),
+    }
+}
+
+/// Topic or client id of client `id`.
+fn template(pattern: &str, id: usize) -> String {
+    pattern.replace("{id}", &id.to_string())
+}
+
+/// Log prefix telling the clients apart (none for a single client).
+fn label(clients: usize, id: usize) -> String {
+    if clients > 1 {
+        format!("[client {id}] ")
+    } else {
+        String::new()
+    }
+}
+
+/// Per-client send results, followed by the summary of the run.
+fn log_reports(
+    stream: &Stream,
+    reports: &[ClientReport],
+    end_to_end: Option<&LatencyHistogram>,
+) -> RunSummary {
+    for report in reports {
+        let label = label(reports.len(), report.id);
+        log_pacing_summary(&label, &report.records, report.skipped);
+        log_one_way_delays(&label, &report.rtt_samples);
+    }
+
+    let summary = run_summary(stream, reports, end_to_end);
+    for line in summary.table() {
+        info!("{line}");
+    }
+    summary
+}
+
+/**
+ * Summary of the reports of one or several clients. The end-to-end latency is the RTT (RTT
+ * mode) unless given, e.g. the delivery latency of scenarios.
+ */
+fn run_summary(
+    stream: &Stream,
+    reports: &[ClientReport],
+    end_to_end: Option<&LatencyHistogram>,
+) -> RunSummary {
+    let mut ack_latency = LatencyHistogram::new();
+    for report in reports {
+        ack_latency.merge(&report.ack_latency);
+    }
+    let rtt: LatencyHistogram = reports
+        .iter()
+        .flat_map(|report| report.rtt_samples.iter().map(RttSample::rtt))
+        .collect();
+
+    let sent = reports.iter().map(|report| report.records.len()).sum();
+    let window = match (
+        reports.iter().map(|report| report.started).min(),
+        reports.iter().map(|report| report.finished).max(),
+    ) {
+        (Some(started), Some(finished)) => finished.saturating_duration_since(started),
+        _ => Duration::ZERO,
+    };
+
+    RunSummary {
+        window: window.as_secs_f64(),
+        sent,
+        skipped: reports.iter().map(|report| report.skipped).sum(),
+        replaced: reports.iter().map(|report| report.replaced).sum(),
+        lost: reports.iter().map(|report| report.lost).sum(),
+        requested_rate: stream.requested_rate(reports.len()),
+        achieved_rate: sent as f64 / window.as_secs_f64().max(f64::EPSILON),
+        ack_latency: ack_latency.summary(),
+        end_to_end_latency: end_to_end.unwrap_or(&rtt).summary(),
+    }
+}
+
+fn log_pacing_summary(label: &str, records: &[PacingRecord], skipped: usize) {
+    info!(
+        "{label}Sent {} messages ({} skipped)",
+        records.len(),
+        skipped
+    );
+    if records.is_empty() {
+        return;
+    }
+
+    let lateness: Vec<i128> = records.iter().map(PacingRecord::lateness).collect();
+    info!(
+        "{label}Send time error: mean {}, max {}",
+        signed_duration(lateness.iter().sum::<i128>() / lateness.len() as i128),
+        signed_duration(*lateness.iter().max().unwrap())
+    );
+}
+
+/// CSV of the scheduled and actual send times (nanoseconds since the start of each client).
+fn write_pacing_log(path: &Path, reports: &[ClientReport]) -> std::io::Result<()> {
+    let mut csv = String::from("client,index,scheduled_ns,actual_ns,lateness_ns\n");
+    for report in reports {
+        for (index, record) in report.records.iter().enumerate() {
+            csv.push_str(&format!(
+                "{},{},{},{},{}\n",
+                report.id,
+                index,
+                record.scheduled.as_nanos(),
+                record.actual.as_nanos(),
+                record.lateness()
+            ));
+        }
+    }
+    std::fs::write(path, csv)
+}
+
+/// Payload of `size` bytes starting with `header` (the generation timestamp, and the sequence in
+/// scenarios), followed by the repeated filler.
+fn timestamped_payload(header: String, filler: &str, size: usize) -> String {
+    let mut payload = header;
+    let filler = if filler.is_empty() { "\x7f" } else { filler };
+    while payload.len() < size {
+        payload.push_str(filler);
+    }
+    // Stay on a character boundary (multi-byte messages)
+    let mut end = size;
+    while !payload.is_char_boundary(end) {
+        end -= 1;
+    }
+    payload.truncate(end);
+    payload
+}
 
+/// Mean one-way delays of the reflected messages (RTT mode).
+fn log_one_way_delays(label: &str, samples: &[RttSample]) {
+    if samples.is_empty() {
+        return;
+    }
+
+    let count = samples.len() as i128;
+    let mean_upstream = samples.iter().map(RttSample::upstream).sum::<i128>() / count;
+    let mean_downstream = samples.iter().map(RttSample::downstream).sum::<i128>() / count;
+    // One-way delays rely on synchronized clocks, their sum is always the RTT
+    info!(
+        "{label}One-way estimates: upstream {}, downstream {} (mean, clock offset included)",
+        signed_duration(mean_upstream),
+        signed_duration(mean_downstream)
+    );
+}
+
+/**
+ * Structured results of the clients and `events` and `messages` of other clients (e.g.
+ * subscribers), if requested: connection events in time order, message records, transport
+ * statistics samples and the run summary, with the session of the first client.
+ */
+fn write_output(
+    stream: &Stream,
+    parameters: Map<String, Value>,
+    started: DateTime<Utc>,
+    reports: &[ClientReport],
+    events: Vec<ConnectionEvent>,
+    messages: Vec<MessageRecord>,
+    summary: Option<RunSummary>,
+) -> Result<(), Box<dyn error::Error>> {
+    let Some(ref output) = stream.output else {
+        return Ok(());
+    };
+
+    let session = reports
+        .first()
+        .and_then(|report| report.events.first())
+        .and_then(|event| event.session.clone());
+    let mut events: Vec<ConnectionEvent> = reports
+        .iter()
+        .flat_map(|report| report.events.iter().cloned())
+        .chain(events)
+        .collect();
+    events.sort_by_key(|event| event.timestamp_ns);
+    let messages: Vec<MessageRecord> = reports
+        .iter()
+        .flat_map(|report| report.messages.iter().copied())
+        .chain(messages)
+        .collect();
+    let stats: Vec<StatsRecord> = reports
+        .iter()
+        .flat_map(|report| report.stats.iter().copied())
+        .collect();
+
+    let run = RunRecord::new(
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        parameters,
+        started,
+        session,
+        summary,
+    );
+    output.write("events", &events)?;
+    output.write("messages", &messages)?;
+    output.write("stats", &stats)?;
+    output.write("run", &[run])?;
+    info!("Saved the results to {}", output.directory().display());
+    Ok(())
+}
+
+/// Pretty-printed JSON file.
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), Box<dyn error::Error>> {
+    std::fs::write(path, serde_json::to_string_pretty(value)?)?;
     Ok(())
 }
+
+/// Debug formatting of a signed nanosecond delay, e.g. `-1.2ms`.
+fn signed_duration(nanos: i128) -> String {
+    let duration = Duration::from_nanos(nanos.unsigned_abs() as u64);
+    if nanos < 0 {
+        format!("-{duration:?}")
+    } else {
+        format!("{duration:?}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(args: &[&str]) -> Result<Stream, Box<dyn error::Error>> {
+        let command_line = ["mqtt-client", "pub", "--topic", "test", "--size", "64"];
+        match MqttStreamCli::try_parse_from(command_line.iter().chain(args))? {
+            MqttStreamCli::Publish(stream_args) => Stream::new(stream_args),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn measurement_window() {
+        let windowed = stream(&["--rate", "10", "--warm-up", "1.5", "--cool-down", "2"]).unwrap();
+        assert_eq!(
+            windowed.window(),
+            (
+                Duration::from_millis(1500),
+                Some(Duration::from_millis(11_500))
+            )
+        );
+        assert_eq!(windowed.requested_rate(3), Some(30.0));
+
+        let whole_trace = stream(&["--rate", "10", "--duration", "0"]).unwrap();
+        assert_eq!(whole_trace.window(), (Duration::ZERO, None));
+
+        assert!(stream(&["--rate", "10", "--duration", "0", "--cool-down", "1"]).is_err());
+        assert!(stream(&["--rate", "10", "--warm-up=-1"]).is_err());
+    }
+}
@@ -0,0 +1,295 @@
+use chrono::Utc;
+use log::{info, warn};
+use mqttbytes::QoS;
+use raw_mqtt::client::stream_client::StreamMqttClient;
+use raw_mqtt::utility::output::RunRecord;
+use raw_mqtt::utility::pacing::{MissedTicks, Pacer, TIMER_RESOLUTION};
+use raw_mqtt::utility::saturation::{SaturationSearch, Step, Thresholds};
+use raw_mqtt::utility::stream_argument_parser::SaturateArgs;
+use raw_mqtt::utility::summary::LatencyHistogram;
+use raw_mqtt::utility::traffic::{SizeDistribution, TrafficGenerator, TrafficModel};
+use serde::Serialize;
+use serde_json::json;
+use std::error;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::{connect, disconnect, new_client, qos, DRAIN_POLL};
+
+/// Longest wait for the send queue and the acks of a step to drain
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+/// Messages awaiting an ack at most, one per non-zero packet id
+const MAX_IN_FLIGHT: usize = u16::MAX as usize;
+
+/// Maximum sustainable rate of one configuration.
+#[derive(Debug, Clone, Serialize)]
+struct Saturation {
+    transport: String,
+    qos: u8,
+    size: usize,
+    rate: f64,
+    saturated: bool,
+}
+
+/// Step of a search, in the structured results.
+#[derive(Debug, Clone, Serialize)]
+struct StepRecord {
+    transport: String,
+    qos: u8,
+    size: usize,
+    rate: f64,
+    sent: usize,
+    achieved_rate: f64,
+    /// 99th percentile ack latency in nanoseconds
+    p99_latency_ns: Option<u64>,
+    unacked: usize,
+    queue: usize,
+    exhausted: bool,
+    sustainable: bool,
+}
+
+/**
+ * Search the maximum sustainable publish rate of every transport, QoS and payload size
+ * combination, over one connection each.
+ */
+pub async fn run(mut saturate_args: SaturateArgs) -> Result<(), Box<dyn error::Error>> {
+    let output = saturate_args.output_args.output()?;
+    let mut parameters = saturate_args.common_args.parameters();
+    parameters.extend([
+        ("start_rate".to_string(), json!(saturate_args.start_rate)),
+        ("max_rate".to_string(), json!(saturate_args.max_rate)),
+        (
+            "step_duration".to_string(),
+            json!(saturate_args.step_duration),
+        ),
+        ("max_latency".to_string(), json!(saturate_args.max_latency)),
+        ("max_queue".to_string(), json!(saturate_args.max_queue)),
+        ("precision".to_string(), json!(saturate_args.precision)),
+        ("queue".to_string(), json!(saturate_args.queue)),
+    ]);
+
+    let args = &mut saturate_args.common_args;
+    let transports = if saturate_args.transports.is_empty() {
+        vec![(args.transport.clone(), None)]
+    } else {
+        saturate_args.transports.clone()
+    };
+    let qos_levels = if saturate_args.qos_levels.is_empty() {
+        vec![args.qos]
+    } else {
+        saturate_args.qos_levels.clone()
+    };
+    let thresholds = Thresholds {
+        max_latency: Duration::from_secs_f64(saturate_args.max_latency / 1000.0),
+        max_queue: saturate_args.max_queue,
+    };
+
+    let mut results = Vec::new();
+    let mut events = Vec::new();
+    let mut steps = Vec::new();
+    let mut runs = Vec::new();
+    let default_port = args.port;
+    for (transport_name, port) in transports {
+        let name = match port {
+            Some(port) => format!("{transport_name}:{port}"),
+            None => transport_name.clone(),
+        };
+        args.transport = transport_name;
+        args.port = port.unwrap_or(default_port);
+        let transport = args.transport()?;
+
+        for &qos_level in &qos_levels {
+            for &size in &saturate_args.sizes {
+                let label = format!("[{name} QoS {qos_level} {size} B] ");
+                let started = Utc::now();
+                let mut client = new_client(args, &transport);
+                client.set_queue(saturate_args.queue);
+                client.set_ack_latency(true);
+                let mut connected = connect(&mut client, args, results.len()).await?;
+                let session = connected.session.clone();
+
+                let mut search = SaturationSearch::new(
+                    saturate_args.start_rate,
+                    saturate_args.max_rate,
+                    saturate_args.precision,
+                );
+                while let Some(rate) = search.next_rate() {
+                    let step_duration = Duration::from_millis(saturate_args.step_duration);
+                    let step = step(
+                        &client,
+                        &args.topic,
+                        qos(qos_level),
+                        size,
+                        rate,
+                        step_duration,
+                    )
+                    .await?;
+                    let sustainable = thresholds.sustainable(&step);
+                    info!(
+                        "{label}{:.0} messages/s: sent {:.0} messages/s, p99 ack latency {}, \
+                         {} unacked, {} queued{} ({})",
+                        rate,
+                        step.achieved_rate(),
+                        step.latency
+                            .map_or("n/a".to_string(), |latency| format!("{latency:?}")),
+                        step.unacked,
+                        step.queue,
+                        if step.exhausted {
+                            ", packet ids exhausted"
+                        } else {
+                            ""
+                        },
+                        if sustainable {
+                            "sustained"
+                        } else {
+                            "saturated"
+                        }
+                    );
+                    search.record(rate, sustainable);
+                    steps.push(StepRecord {
+                        transport: name.clone(),
+                        qos: qos_level,
+                        size,
+                        rate,
+                        sent: step.sent,
+                        achieved_rate: step.achieved_rate(),
+                        p99_latency_ns: step.latency.map(|latency| latency.as_nanos() as u64),
+                        unacked: step.unacked,
+                        queue: step.queue,
+                        exhausted: step.exhausted,
+                        sustainable,
+                    });
+
+                    // Late acks would be counted in the next step, and their packet ids reused
+                    if step.unacked > 0 || step.exhausted {
+                        client.abandon_pending();
+                        events.push(connected);
+                        events.push(disconnect(&mut client, results.len()).await?);
+                        client = new_client(args, &transport);
+                        client.set_queue(saturate_args.queue);
+                        client.set_ack_latency(true);
+                        connected = connect(&mut client, args, results.len()).await?;
+                    }
+                }
+                events.push(connected);
+                events.push(disconnect(&mut client, results.len()).await?);
+
+                let saturation = Saturation {
+                    transport: name.clone(),
+                    qos: qos_level,
+                    size,
+                    rate: search.result(),
+                    saturated: search.saturated(),
+                };
+                // One run per configuration
+                runs.push(RunRecord::new(
+                    env!("CARGO_PKG_NAME"),
+                    env!("CARGO_PKG_VERSION"),
+                    parameters.clone(),
+                    started,
+                    session,
+                    saturation.clone(),
+                ));
+                results.push(saturation);
+            }
+        }
+    }
+
+    info!("Maximum sustainable rates:");
+    for result in results {
+        info!(
+            "  {} QoS {} {} B: {:.0} messages/s{}",
+            result.transport,
+            result.qos,
+            result.size,
+            result.rate,
+            if result.saturated {
+                ""
+            } else {
+                " (maximum rate reached)"
+            }
+        );
+    }
+
+    if let Some(output) = output {
+        output.write("events", &events)?;
+        output.write("steps", &steps)?;
+        output.write("run", &runs)?;
+        info!("Saved the results to {}", output.directory().display());
+    }
+
+    Ok(())
+}
+
+/// Publish at a constant `rate` for `duration`, then wait for the queue and acks to drain. The
+/// step stops early once every packet id is in flight.
+async fn step(
+    client: &StreamMqttClient,
+    topic: &str,
+    qos: QoS,
+    size: usize,
+    rate: f64,
+    duration: Duration,
+) -> Result<Step, Box<dyn error::Error>> {
+    // Leave out the latencies of the previous steps, their acks all came in
+    let latencies = client.ack_latencies();
+    while latencies.try_recv().is_ok() {}
+
+    // One batch per timer tick above the timer resolution
+    let batch = (rate * TIMER_RESOLUTION.as_secs_f64()).ceil() as usize;
+    let traffic = TrafficGenerator::new(
+        TrafficModel::Constant,
+        rate,
+        SizeDistribution::Fixed(size),
+        None,
+    )?;
+    let mut pacer = Pacer::new(
+        traffic.take_while(|arrival| arrival.offset < duration),
+        MissedTicks::Burst,
+        batch,
+    );
+
+    let payload = String::from_utf8(vec![127_u8; size]).unwrap();
+    let mut sent = 0;
+    let mut exhausted = false;
+    pacer.start();
+    'publish: while let Some(batch) = pacer.next_batch().await {
+        for _ in batch {
+            if qos != QoS::AtMostOnce && client.pending() >= MAX_IN_FLIGHT {
+                warn!("Every packet id is in flight, stopping the step");
+                exhausted = true;
+                break 'publish;
+            }
+            client
+                .stream_publish(topic.to_string(), payload.clone(), qos)
+                .await?;
+            sent += 1;
+        }
+    }
+    let elapsed = pacer.elapsed();
+    let queue = client.queued();
+
+    let deadline = Instant::now() + DRAIN_TIMEOUT;
+    while (client.queued() > 0 || client.pending() > 0) && Instant::now() < deadline {
+        tokio::time::sleep(DRAIN_POLL).await;
+    }
+    let unacked = client.pending();
+    if unacked > 0 {
+        warn!("{unacked} messages still unacked after {DRAIN_TIMEOUT:?}");
+    }
+
+    let mut samples = LatencyHistogram::new();
+    while let Ok(latency) = latencies.try_recv() {
+        samples.record(latency);
+    }
+
+    Ok(Step {
+        rate,
+        sent,
+        elapsed,
+        latency: (!samples.is_empty()).then(|| samples.quantile(0.99)),
+        unacked,
+        queue,
+        exhausted,
+    })
+}
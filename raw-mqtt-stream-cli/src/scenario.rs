@@ -0,0 +1,231 @@
+use chrono::Utc;
+use log::{debug, info, warn};
+use raw_mqtt::client::rtt;
+use raw_mqtt::client::stream_client::StreamMqttClient;
+use raw_mqtt::utility::output::{MessageEvent, MessageRecord};
+use raw_mqtt::utility::stream_argument_parser::ScenarioArgs;
+use raw_mqtt::utility::summary::{latency_header, latency_row, LatencyHistogram};
+use serde_json::json;
+use std::collections::HashSet;
+use std::error;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::{
+    connect, disconnect, log_reports, new_client, qos, start_publishers, template, write_json,
+    write_output, Stream, DRAIN_POLL,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Scenario {
+    /// One publisher, N subscribers on its topic
+    FanOut,
+    /// N publishers, one subscriber on a wildcard
+    FanIn,
+}
+
+impl fmt::Display for Scenario {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scenario::FanOut => write!(f, "fan-out"),
+            Scenario::FanIn => write!(f, "fan-in"),
+        }
+    }
+}
+
+/// Subscribing client, collecting the latency of every delivery.
+struct Subscriber {
+    /// Client index, after the publishers
+    id: usize,
+    client: StreamMqttClient,
+    /// Distinct messages delivered so far
+    delivered: Arc<AtomicUsize>,
+    /// Delivery latencies, and delivery records if kept
+    collector: JoinHandle<(LatencyHistogram, Vec<MessageRecord>)>,
+}
+
+/**
+ * Run a scenario: subscribe first, publish the streams, then wait for every expected delivery
+ * (up to the drain time) and report the delivery ratio and latencies of each subscriber. The
+ * end-to-end latency of the run summary is the delivery latency.
+ */
+pub async fn run(
+    scenario: Scenario,
+    scenario_args: ScenarioArgs,
+) -> Result<(), Box<dyn error::Error>> {
+    if scenario_args.stream_args.rtt {
+        Err("RTT mode is not available in scenarios")?;
+    }
+    if scenario_args.stream_args.warm_up > 0.0 || scenario_args.stream_args.cool_down > 0.0 {
+        Err("Warm-up and cool-down are not available in scenarios")?;
+    }
+    let started = Utc::now();
+    let mut stream = Stream::new(scenario_args.stream_args)?;
+    if stream.model.is_none() {
+        Err("Scenarios require a rate or a trace")?;
+    }
+
+    let clients = stream.publishers;
+    let subscribers = match scenario {
+        Scenario::FanOut => {
+            stream.publishers = 1;
+            clients
+        }
+        Scenario::FanIn => 1,
+    };
+    // Latencies are measured from the send timestamp of every message, deliveries counted by
+    // sequence number
+    stream.timestamped = true;
+    stream.sequenced = true;
+    let stream = Arc::new(stream);
+
+    let args = &stream.args.publish_args.common_args;
+    let filter = match scenario_args.filter {
+        Some(filter) => filter,
+        None => match scenario {
+            Scenario::FanOut => template(&args.topic, 0),
+            Scenario::FanIn => args.topic.replace("{id}", "+"),
+        },
+    };
+    let sub_qos = qos(scenario_args.sub_qos.unwrap_or(args.qos));
+    info!("{subscribers} subscriber(s) on {filter}");
+
+    let mut events = Vec::new();
+    let mut subscribed = Vec::with_capacity(subscribers);
+    for id in 0..subscribers {
+        let mut client = new_client(args, &stream.transport);
+        if let Some(ref client_id) = stream.args.client_id {
+            client.set_client_id(format!("{}-sub", template(client_id, id)));
+        }
+        client.subscribe(filter.clone(), sub_qos);
+        // The first publisher reports the TLS session
+        let id = stream.publishers + id;
+        stream.metrics.register(id.to_string(), client.metrics());
+        events.push(connect(&mut client, args, id).await?);
+        subscribed.push(subscriber(client, id, stream.output.is_some()));
+    }
+
+    let reports = start_publishers(&stream).await?;
+
+    // Every subscriber expects every message sent, not the ones replaced in the send queue
+    let expected: usize = reports
+        .iter()
+        .map(|report| report.records.len().saturating_sub(report.replaced))
+        .sum();
+    let deadline = Instant::now() + Duration::from_millis(stream.args.drain);
+    while Instant::now() < deadline
+        && subscribed
+            .iter()
+            .any(|subscriber| subscriber.delivered.load(Ordering::Relaxed) < expected)
+    {
+        tokio::time::sleep(DRAIN_POLL).await;
+    }
+
+    let mut latencies = LatencyHistogram::new();
+    let mut deliveries = Vec::new();
+    for (id, mut subscriber) in subscribed.into_iter().enumerate() {
+        events.push(disconnect(&mut subscriber.client, subscriber.id).await?);
+        let (subscriber_latencies, records) = subscriber.collector.await?;
+        let label = format!("[subscriber {id}] ");
+        log_deliveries(&label, &subscriber_latencies, expected);
+        latencies.merge(&subscriber_latencies);
+        deliveries.extend(records);
+    }
+    if subscribers > 1 {
+        log_deliveries("[all subscribers] ", &latencies, expected * subscribers);
+    }
+    let delivered = latencies.len() as usize;
+    if delivered < expected * subscribers {
+        warn!("{} deliveries missing", expected * subscribers - delivered);
+    }
+
+    let summary = log_reports(&stream, &reports, Some(&latencies));
+    if let Some(ref path) = stream.args.summary {
+        write_json(path, &summary)?;
+        info!("Saved the run summary to {}", path.display());
+    }
+
+    let mut parameters = stream.args.parameters();
+    parameters.extend([
+        ("scenario".to_string(), json!(scenario.to_string())),
+        ("subscribers".to_string(), json!(subscribers)),
+        ("filter".to_string(), json!(filter)),
+        ("sub_qos".to_string(), json!(scenario_args.sub_qos)),
+    ]);
+    write_output(
+        &stream,
+        parameters,
+        started,
+        &reports,
+        events,
+        deliveries,
+        Some(summary),
+    )?;
+
+    Ok(())
+}
+
+/// Collect the latency of the messages delivered to connected client `id`, and their records.
+/// A message delivered again (QoS 1) counts once.
+fn subscriber(client: StreamMqttClient, id: usize, records: bool) -> Subscriber {
+    let messages = client.messages();
+    let delivered = Arc::new(AtomicUsize::new(0));
+    let counter = delivered.clone();
+    let collector = tokio::spawn(async move {
+        let mut latencies = LatencyHistogram::new();
+        let mut deliveries = Vec::new();
+        let mut seen = HashSet::new();
+        while let Ok(message) = messages.recv().await {
+            let payload = &message.publish.payload;
+            match rtt::sent_timestamp(payload).zip(rtt::sequence(payload)) {
+                Some((_, sequence)) if !seen.insert(sequence) => {
+                    debug!("Message {sequence:?} delivered again");
+                }
+                Some((sent, _)) => {
+                    let latency =
+                        Duration::from_nanos(message.received.saturating_sub(sent) as u64);
+                    latencies.record(latency);
+                    if records {
+                        deliveries.push(MessageRecord::received(
+                            id,
+                            MessageEvent::Deliver,
+                            message.received,
+                            latency,
+                        ));
+                    }
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }
+                None => warn!(
+                    "Message without send timestamp or sequence on {}",
+                    message.publish.topic
+                ),
+            }
+        }
+        (latencies, deliveries)
+    });
+
+    Subscriber {
+        id,
+        client,
+        delivered,
+        collector,
+    }
+}
+
+/// Delivery ratio and latency distribution of a subscriber.
+fn log_deliveries(label: &str, latencies: &LatencyHistogram, expected: usize) {
+    info!(
+        "{label}Received {}/{} messages ({:.2}%)",
+        latencies.len(),
+        expected,
+        100.0 * latencies.len() as f64 / expected.max(1) as f64
+    );
+    if let Some(summary) = latencies.summary() {
+        info!("{label}{}", latency_header());
+        info!("{label}{}", latency_row("delivery", &summary));
+    }
+}
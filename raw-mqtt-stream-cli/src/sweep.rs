@@ -0,0 +1,218 @@
+use chrono::Utc;
+use clap::Parser;
+use log::{error, info};
+use raw_mqtt::utility::experiment::{Experiment, Run};
+use raw_mqtt::utility::stream_argument_parser::{MqttStreamCli, PublishStreamArgs, SweepArgs};
+use raw_mqtt::utility::summary::RunSummary;
+use serde::Serialize;
+use std::error;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use toml::{Table, Value};
+
+use crate::{
+    log_reports, run_summary, runtime, start_publishers, write_json, write_output, ClientReport,
+    Stream,
+};
+
+const MANIFEST: &str = "manifest.json";
+
+/// Exact parameters of every run of an experiment, rewritten after each run.
+#[derive(Serialize)]
+struct Manifest<'a> {
+    tool: &'static str,
+    version: &'static str,
+    experiment: &'a Path,
+    started: String,
+    repetitions: usize,
+    warm_up: f64,
+    cool_down: f64,
+    runs: Vec<RunEntry<'a>>,
+}
+
+#[derive(Serialize)]
+struct RunEntry<'a> {
+    #[serde(flatten)]
+    run: &'a Run,
+    /// Results file, in the output directory
+    results: Option<String>,
+    error: Option<String>,
+}
+
+/// Results file of one run.
+#[derive(Serialize)]
+struct RunResults<'a> {
+    run: usize,
+    repetition: usize,
+    parameters: &'a Table,
+    clients: Vec<ClientResults>,
+    /// Summary over every client
+    all: RunSummary,
+}
+
+#[derive(Serialize)]
+struct ClientResults {
+    client: usize,
+    #[serde(flatten)]
+    summary: RunSummary,
+}
+
+/**
+ * Run an experiment: every combination of its axes in order, once per repetition, with the
+ * cool-down between two runs. Each run writes its results file, the manifest lists the command
+ * line and outcome of every run.
+ */
+pub fn run(sweep_args: SweepArgs) -> Result<(), Box<dyn error::Error>> {
+    let experiment = Experiment::load(&sweep_args.experiment)?;
+    let runs = experiment.runs()?;
+
+    // Reject invalid options before the first run
+    let mut stream_args = Vec::with_capacity(runs.len());
+    for run in &runs {
+        stream_args.push(parse(run)?);
+    }
+    info!(
+        "{} run(s): {} combination(s), {} repetition(s)",
+        runs.len(),
+        runs.len() / experiment.repetitions,
+        experiment.repetitions
+    );
+    if sweep_args.dry_run {
+        for run in &runs {
+            info!("Run {}: {}", run.index, run.args.join(" "));
+        }
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(&experiment.output)?;
+    let mut manifest = Manifest {
+        tool: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        experiment: &sweep_args.experiment,
+        started: Utc::now().to_rfc3339(),
+        repetitions: experiment.repetitions,
+        warm_up: experiment.warm_up,
+        cool_down: experiment.cool_down,
+        runs: Vec::with_capacity(runs.len()),
+    };
+    let cool_down = Duration::from_secs_f64(experiment.cool_down);
+
+    for (run, stream_args) in runs.iter().zip(stream_args) {
+        if run.index > 1 && !cool_down.is_zero() {
+            info!("Cooling down for {cool_down:?}");
+            std::thread::sleep(cool_down);
+        }
+        info!(
+            "Run {}/{} (repetition {}): {}",
+            run.index,
+            runs.len(),
+            run.repetition,
+            describe(&run.parameters)
+        );
+
+        // A failed run is recorded, the experiment goes on
+        let started = Utc::now();
+        let entry = match measure(stream_args) {
+            Ok((stream, reports)) => {
+                let all = log_reports(&stream, &reports, None);
+                // Structured results of the run (if its options request them)
+                write_output(
+                    &stream,
+                    stream.args.parameters(),
+                    started,
+                    &reports,
+                    Vec::new(),
+                    Vec::new(),
+                    Some(all.clone()),
+                )?;
+                let file = format!("run-{:04}.json", run.index);
+                let results = RunResults {
+                    run: run.index,
+                    repetition: run.repetition,
+                    parameters: &run.parameters,
+                    clients: reports
+                        .iter()
+                        .map(|report| ClientResults {
+                            client: report.id,
+                            summary: run_summary(&stream, std::slice::from_ref(report), None),
+                        })
+                        .collect(),
+                    all,
+                };
+                write_json(&experiment.output.join(&file), &results)?;
+                RunEntry {
+                    run,
+                    results: Some(file),
+                    error: None,
+                }
+            }
+            Err(e) => {
+                error!("Run {} failed: {e}", run.index);
+                RunEntry {
+                    run,
+                    results: None,
+                    error: Some(e.to_string()),
+                }
+            }
+        };
+        manifest.runs.push(entry);
+        write_json(&experiment.output.join(MANIFEST), &manifest)?;
+    }
+
+    let failed = manifest
+        .runs
+        .iter()
+        .filter(|entry| entry.error.is_some())
+        .count();
+    info!(
+        "Saved the results of {} run(s) to {}{}",
+        runs.len() - failed,
+        experiment.output.display(),
+        if failed > 0 {
+            format!(" ({failed} failed)")
+        } else {
+            String::new()
+        }
+    );
+
+    Ok(())
+}
+
+/// Publish options of a run.
+fn parse(run: &Run) -> Result<PublishStreamArgs, String> {
+    let command_line =
+        std::iter::once(env!("CARGO_PKG_NAME")).chain(run.args.iter().map(String::as_str));
+    match MqttStreamCli::try_parse_from(command_line) {
+        Ok(MqttStreamCli::Publish(stream_args)) => Ok(stream_args),
+        Ok(_) => Err(format!("Run {} is not a publish run", run.index)),
+        Err(e) => Err(format!("Invalid options in run {}: {e}", run.index)),
+    }
+}
+
+/// Publish the streams of a run on their own runtime.
+fn measure(
+    stream_args: PublishStreamArgs,
+) -> Result<(Arc<Stream>, Vec<ClientReport>), Box<dyn error::Error>> {
+    let stream = Arc::new(Stream::new(stream_args)?);
+    if stream.model.is_none() {
+        Err("Experiment runs require a rate or a trace")?;
+    }
+    let reports = runtime(stream.args.threads)?.block_on(start_publishers(&stream))?;
+    Ok((stream, reports))
+}
+
+/// Swept values of a run, e.g. `qos=1, size=64`.
+fn describe(parameters: &Table) -> String {
+    if parameters.is_empty() {
+        return "no parameters".to_string();
+    }
+    parameters
+        .iter()
+        .map(|(name, value)| match value {
+            Value::String(value) => format!("{name}={value}"),
+            value => format!("{name}={value}"),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}